@@ -0,0 +1,114 @@
+use bgb_link::commands::*;
+use bgb_link::net::stream::BgbStream;
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use std::io::{self, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+const PACKETS: usize = 1024;
+
+fn sync1_packets() -> Vec<TypedBgbCommand> {
+    (0..PACKETS)
+        .map(|i| TypedBgbCommand::Sync1 {
+            data: i as u8,
+            high_speed: false,
+            double_speed: false,
+            timestamp: i as u32 * 4096,
+        })
+        .collect()
+}
+
+/// Connects to a socket whose other end discards everything it receives.
+fn drained_socket() -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut peer, _) = listener.accept().unwrap();
+        io::copy(&mut peer, &mut io::sink()).unwrap();
+    });
+    let socket = TcpStream::connect(addr).unwrap();
+    socket.set_nodelay(true).unwrap();
+    socket
+}
+
+fn write(c: &mut Criterion) {
+    let commands = sync1_packets();
+    let mut group = c.benchmark_group("write");
+    group.throughput(Throughput::Bytes((PACKETS * 8) as u64));
+
+    let mut stream = BgbStream::wrap(drained_socket());
+    group.bench_function("per_packet", |b| {
+        b.iter(|| {
+            for command in &commands {
+                stream.write(command).unwrap();
+            }
+        })
+    });
+
+    let mut stream = BgbStream::wrap_buffered(drained_socket());
+    group.bench_function("buffered", |b| {
+        b.iter(|| {
+            for command in &commands {
+                stream.write(command).unwrap();
+            }
+            stream.flush().unwrap();
+        })
+    });
+
+    let mut stream = BgbStream::wrap(drained_socket());
+    group.bench_function("write_all_commands", |b| {
+        b.iter(|| stream.write_all_commands(&commands).unwrap())
+    });
+    group.finish();
+}
+
+/// Connects to a socket whose other end sends the same bytes over and over.
+fn repeating_socket(bytes: Vec<u8>) -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut peer, _) = listener.accept().unwrap();
+        while peer.write_all(&bytes).is_ok() {}
+    });
+    let socket = TcpStream::connect(addr).unwrap();
+    socket.set_nodelay(true).unwrap();
+    socket
+}
+
+fn read(c: &mut Criterion) {
+    let mut bytes = Vec::new();
+    encode_all(&sync1_packets(), &mut bytes);
+    let mut group = c.benchmark_group("read");
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+
+    let mut stream = BgbStream::wrap(repeating_socket(bytes.clone()));
+    group.bench_function("per_packet", |b| {
+        b.iter(|| {
+            for _ in 0..PACKETS {
+                black_box(stream.read().unwrap());
+            }
+        })
+    });
+
+    let mut stream = BgbStream::wrap_buffered(repeating_socket(bytes.clone()));
+    group.bench_function("read_batch", |b| {
+        b.iter(|| {
+            let mut received = 0;
+            while received < PACKETS {
+                received += black_box(stream.read_batch().unwrap()).len();
+            }
+        })
+    });
+
+    group.bench_function("decode_all", |b| {
+        b.iter(|| {
+            for raw in RawBgbCommand::decode_all(black_box(&bytes)) {
+                black_box(TypedBgbCommand::from_raw(&raw).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, write, read);
+criterion_main!(benches);
@@ -0,0 +1,48 @@
+//! Plays the second player in Tetris's 2-player mode against BGB.
+//!
+//! Run with an address to listen on (by default `127.0.0.1:8765`), connect BGB to it and
+//! start 2-player mode. The bot sends the player some garbage every few seconds and never
+//! tops out, so the player has to clear 30 lines to win.
+
+use bgb_link::net::listener::BgbListener;
+use bgb_link::peripheral::tetris::{TetrisBot, TetrisState};
+use bgb_link::peripheral::{PeripheralEvent, PeripheralLink};
+use std::io;
+use std::net::TcpListener;
+use std::time::{Duration, Instant};
+
+const ATTACK_INTERVAL: Duration = Duration::from_secs(5);
+
+fn main() -> io::Result<()> {
+    let addr = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| String::from("127.0.0.1:8765"));
+    let listener = BgbListener::wrap(TcpListener::bind(&addr)?);
+    println!("listening on {}", addr);
+    let (stream, peer) = listener.accept()?;
+    println!("{} connected", peer);
+
+    let mut link = PeripheralLink::new(TetrisBot::new(0), stream)?;
+    let mut last_state = TetrisState::Connecting;
+    let mut last_attack = Instant::now();
+    loop {
+        match link.step()? {
+            PeripheralEvent::Disconnected => return Ok(()),
+            PeripheralEvent::Transfer { .. } => {}
+            PeripheralEvent::Other(_) => continue,
+        }
+        let bot = link.device_mut();
+        if bot.state() == TetrisState::Playing && last_attack.elapsed() >= ATTACK_INTERVAL {
+            bot.clear_lines(2);
+            last_attack = Instant::now();
+        }
+        if bot.state() != last_state {
+            last_state = bot.state();
+            match last_state {
+                TetrisState::Playing => println!("round started"),
+                TetrisState::RoundOver => println!("round over: bot {:?}", bot.outcome()),
+                state => println!("{:?}", state),
+            }
+        }
+    }
+}
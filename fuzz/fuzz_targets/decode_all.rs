@@ -0,0 +1,19 @@
+#![no_main]
+
+use bgb_link::commands::{BgbCommand, RawBgbCommand, TypedBgbCommand};
+use libfuzzer_sys::fuzz_target;
+
+// Decodes a whole buffer at once, checking that it agrees with decoding one packet at a time.
+fuzz_target!(|data: &[u8]| {
+    let packets = RawBgbCommand::decode_all(data);
+    assert_eq!(packets.remainder().len(), data.len() % 8);
+    for (raw, bytes) in packets.zip(data.chunks_exact(8)) {
+        assert_eq!(&raw.serialize()[..], bytes);
+        if let Ok(command) = TypedBgbCommand::from_raw(&raw) {
+            assert_eq!(
+                TypedBgbCommand::from_raw(&command.to_raw()).unwrap(),
+                command
+            );
+        }
+    }
+});
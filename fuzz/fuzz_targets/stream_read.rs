@@ -0,0 +1,23 @@
+#![no_main]
+
+use bgb_link::commands::{BgbCommand, TypedBgbCommand};
+use bgb_link::net::stream::BgbStream;
+use libfuzzer_sys::fuzz_target;
+use std::io::Cursor;
+
+// Reads commands from arbitrary bytes until the stream runs out, checking that every command
+// it accepts is sent back out as a packet that means the same thing.
+fuzz_target!(|data: &[u8]| {
+    let mut stream = BgbStream::wrap(Cursor::new(data.to_vec()));
+    loop {
+        match stream.read() {
+            Ok(command) => {
+                let decoded = TypedBgbCommand::deserialize(&command.serialize())
+                    .expect("an accepted command re-encoded to an invalid packet");
+                assert_eq!(decoded, command);
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::InvalidData => {}
+            Err(_) => break,
+        }
+    }
+});
@@ -0,0 +1,55 @@
+//! Checks that an emulator follows the BGB link protocol the way BGB does.
+//!
+//! Run with `connect <address>` to connect to an emulator that's listening, or with
+//! `listen <address>` to wait for one to connect, optionally followed by how many
+//! milliseconds the emulator has to answer each scenario. Prints a report and exits with a
+//! failure status if any scenario failed.
+
+use bgb_link::conformance::Conformance;
+use bgb_link::net::listener::BgbListener;
+use std::net::TcpListener;
+use std::process;
+use std::time::Duration;
+
+const USAGE: &str = "usage: bgb-conformance (connect | listen) <address> [timeout-ms]";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (mode, addr) = match (args.first(), args.get(1)) {
+        (Some(mode), Some(addr)) if args.len() <= 3 => (mode.as_str(), addr.as_str()),
+        _ => usage(),
+    };
+    let mut conformance = Conformance::new();
+    if let Some(timeout) = args.get(2) {
+        match timeout.parse() {
+            Ok(ms) => conformance.set_timeout(Duration::from_millis(ms)),
+            Err(_) => usage(),
+        }
+    }
+
+    let report = match mode {
+        "connect" => conformance.connect(addr),
+        "listen" => TcpListener::bind(addr).and_then(|listener| {
+            println!("listening on {}", addr);
+            conformance.accept(&BgbListener::wrap(listener))
+        }),
+        _ => usage(),
+    };
+    match report {
+        Ok(report) => {
+            println!("{}", report);
+            if !report.passed() {
+                process::exit(1);
+            }
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
@@ -0,0 +1,161 @@
+mod tests;
+
+use crate::commands::*;
+use crate::net::stream::BgbStream;
+use std::io;
+use std::io::{Read, Write};
+use std::time::Instant;
+
+/// Which side of the link drives the serial clock.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BridgeRole {
+    /// The real Game Boy drives the clock. Bytes it shifts out are forwarded to BGB as
+    /// `Sync1` packets, and BGB's `Sync2` responses are loaded into the adapter to be
+    /// shifted back on the next transfer.
+    HardwareMaster,
+    /// BGB drives the clock. Bytes from its `Sync1` packets are clocked into the real
+    /// Game Boy by the adapter, and the byte shifted back is returned in a `Sync2`.
+    HardwareSlave,
+}
+
+/// What happened during a single call to `HardwareBridge::step`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BridgeEvent {
+    /// A byte was exchanged between the Game Boy and BGB. `from_bgb` is the byte the Game
+    /// Boy received in this transfer; in `HardwareMaster` mode that's the one BGB answered
+    /// the previous transfer with, since it has to be loaded before the Game Boy clocks.
+    Transfer { from_hardware: u8, from_bgb: u8 },
+    /// A packet that doesn't involve the hardware was handled.
+    Other(TypedBgbCommand),
+    /// The remote BGB instance asked to disconnect.
+    Disconnected,
+}
+
+/// Connects a serial link cable adapter to a BGB instance.
+///
+/// The adapter can be anything that implements `Read + Write` and exchanges one byte for
+/// every byte written: in `HardwareSlave` mode each byte written is clocked out to the
+/// Game Boy and the byte it returned is read back, and in `HardwareMaster` mode each byte
+/// read is one the Game Boy clocked in, with the last byte written being what it received.
+#[derive(Debug)]
+pub struct HardwareBridge<D: Read + Write, T: Read + Write> {
+    device: D,
+    stream: BgbStream<T>,
+    role: BridgeRole,
+    started: Instant,
+    loaded: u8,
+}
+
+impl<D: Read + Write, T: Read + Write> HardwareBridge<D, T> {
+    /// Creates a bridge between the adapter and an already connected `BgbStream`.
+    ///
+    /// In `HardwareMaster` mode, `0xFF` is loaded into the adapter as the first byte the
+    /// Game Boy will receive, as if nothing were connected.
+    pub fn new(device: D, stream: BgbStream<T>, role: BridgeRole) -> io::Result<Self> {
+        let mut bridge = HardwareBridge {
+            device,
+            stream,
+            role,
+            started: Instant::now(),
+            loaded: 0xFF,
+        };
+        if role == BridgeRole::HardwareMaster {
+            bridge.load(0xFF)?;
+        }
+        bridge.stream.write(&TypedBgbCommand::Status {
+            running: true,
+            paused: false,
+            support_reconnect: false,
+        })?;
+        Ok(bridge)
+    }
+
+    /// Returns the role the real hardware plays in the link.
+    pub fn role(&self) -> BridgeRole {
+        self.role
+    }
+
+    /// Handles a single exchange, either a byte clocked by the Game Boy or a packet from BGB,
+    /// depending on the role.
+    pub fn step(&mut self) -> io::Result<BridgeEvent> {
+        match self.role {
+            BridgeRole::HardwareMaster => self.step_hardware_master(),
+            BridgeRole::HardwareSlave => self.step_hardware_slave(),
+        }
+    }
+
+    /// Calls `step` until BGB disconnects or an error occurs.
+    pub fn run(&mut self) -> io::Result<()> {
+        while self.step()? != BridgeEvent::Disconnected {}
+        Ok(())
+    }
+
+    /// Consumes the bridge, returning the adapter and the stream.
+    pub fn into_inner(self) -> (D, BgbStream<T>) {
+        (self.device, self.stream)
+    }
+
+    fn step_hardware_master(&mut self) -> io::Result<BridgeEvent> {
+        let mut buf = [0u8];
+        self.device.read_exact(&mut buf)?;
+        let from_hardware = buf[0];
+        let from_bgb = self.loaded;
+        self.stream.write(&TypedBgbCommand::Sync1 {
+            data: from_hardware,
+            high_speed: false,
+            double_speed: false,
+            timestamp: real_timestamp(self.started.elapsed()),
+        })?;
+        loop {
+            match self.stream.read()? {
+                TypedBgbCommand::Sync2 { data } => {
+                    self.load(data)?;
+                    return Ok(BridgeEvent::Transfer {
+                        from_hardware,
+                        from_bgb,
+                    });
+                }
+                // BGB wasn't ready for a transfer, so the Game Boy sees a disconnected cable
+                TypedBgbCommand::Sync3Response => {
+                    self.load(0xFF)?;
+                    return Ok(BridgeEvent::Transfer {
+                        from_hardware,
+                        from_bgb,
+                    });
+                }
+                TypedBgbCommand::WantDisconnect => return Ok(BridgeEvent::Disconnected),
+                // the Game Boy is already driving the clock, so BGB can't start a transfer
+                other => {
+                    self.stream.answer_as_slave(&other, |_| Ok(None))?;
+                }
+            }
+        }
+    }
+
+    fn step_hardware_slave(&mut self) -> io::Result<BridgeEvent> {
+        let command = self.stream.read()?;
+        let device = &mut self.device;
+        let answered = self.stream.answer_as_slave(&command, |data| {
+            device.write_all(&[data])?;
+            device.flush()?;
+            let mut buf = [0u8];
+            device.read_exact(&mut buf)?;
+            Ok(Some(buf[0]))
+        })?;
+        match (answered, command) {
+            (Some((from_bgb, from_hardware)), _) => Ok(BridgeEvent::Transfer {
+                from_hardware,
+                from_bgb,
+            }),
+            (None, TypedBgbCommand::WantDisconnect) => Ok(BridgeEvent::Disconnected),
+            (None, other) => Ok(BridgeEvent::Other(other)),
+        }
+    }
+
+    fn load(&mut self, data: u8) -> io::Result<()> {
+        self.device.write_all(&[data])?;
+        self.device.flush()?;
+        self.loaded = data;
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+#[test]
+fn hardware_slave() {
+    use super::*;
+    use crate::net::tests::connected_pair;
+
+    /// Stands in for a serial adapter attached to a Game Boy that inverts every byte it receives.
+    struct InvertingGameBoy {
+        pending: Vec<u8>,
+    }
+
+    impl Read for InvertingGameBoy {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.pending.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = !self.pending.remove(0);
+            Ok(1)
+        }
+    }
+
+    impl Write for InvertingGameBoy {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.pending.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let (mut bgb, bridged) = connected_pair();
+    let device = InvertingGameBoy {
+        pending: Vec::new(),
+    };
+    let mut bridge = HardwareBridge::new(device, bridged, BridgeRole::HardwareSlave).unwrap();
+    assert_eq!(
+        bgb.read().unwrap(),
+        TypedBgbCommand::Status {
+            running: true,
+            paused: false,
+            support_reconnect: false,
+        }
+    );
+
+    bgb.write(&TypedBgbCommand::Sync1 {
+        data: 0x0F,
+        high_speed: false,
+        double_speed: false,
+        timestamp: 100,
+    })
+    .unwrap();
+    assert_eq!(
+        bridge.step().unwrap(),
+        BridgeEvent::Transfer {
+            from_hardware: 0xF0,
+            from_bgb: 0x0F,
+        }
+    );
+    assert_eq!(bgb.read().unwrap(), TypedBgbCommand::Sync2 { data: 0xF0 });
+
+    bgb.write(&TypedBgbCommand::Sync3Timestamp { timestamp: 200 })
+        .unwrap();
+    assert_eq!(
+        bridge.step().unwrap(),
+        BridgeEvent::Other(TypedBgbCommand::Sync3Timestamp { timestamp: 200 })
+    );
+    assert_eq!(
+        bgb.read().unwrap(),
+        TypedBgbCommand::Sync3Timestamp { timestamp: 200 }
+    );
+
+    bgb.write(&TypedBgbCommand::WantDisconnect).unwrap();
+    bridge.run().unwrap();
+}
+
+#[test]
+fn hardware_master() {
+    use super::*;
+    use crate::net::tests::connected_pair;
+
+    /// Stands in for a serial adapter attached to a Game Boy that clocks out a fixed script.
+    struct ScriptedGameBoy {
+        script: Vec<u8>,
+        loaded: Vec<u8>,
+    }
+
+    impl Read for ScriptedGameBoy {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.script.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.script.remove(0);
+            Ok(1)
+        }
+    }
+
+    impl Write for ScriptedGameBoy {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.loaded.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let (mut bgb, bridged) = connected_pair();
+    let device = ScriptedGameBoy {
+        script: vec![0x12, 0x34],
+        loaded: Vec::new(),
+    };
+    let mut bridge = HardwareBridge::new(device, bridged, BridgeRole::HardwareMaster).unwrap();
+    bgb.read().unwrap();
+
+    let emulator = std::thread::spawn(move || {
+        let mut received = Vec::new();
+        for response in &[0xAB, 0xCD] {
+            match bgb.read().unwrap() {
+                TypedBgbCommand::Sync1 { data, .. } => received.push(data),
+                other => panic!("unexpected {:?}", other),
+            }
+            bgb.write(&TypedBgbCommand::Sync2 { data: *response })
+                .unwrap();
+        }
+        received
+    });
+
+    assert_eq!(
+        bridge.step().unwrap(),
+        BridgeEvent::Transfer {
+            from_hardware: 0x12,
+            from_bgb: 0xFF,
+        }
+    );
+    assert_eq!(
+        bridge.step().unwrap(),
+        BridgeEvent::Transfer {
+            from_hardware: 0x34,
+            from_bgb: 0xAB,
+        }
+    );
+    assert_eq!(emulator.join().unwrap(), vec![0x12, 0x34]);
+
+    let (device, _) = bridge.into_inner();
+    assert_eq!(device.loaded, vec![0xFF, 0xAB, 0xCD]);
+}
+
+#[test]
+#[cfg(unix)]
+fn pseudo_terminal_adapter() {
+    use super::*;
+    use crate::net::tests::connected_pair;
+    use std::fs::File;
+    use std::os::unix::io::FromRawFd;
+    use std::ptr;
+
+    // the bridge gets the terminal end of a raw-mode pty, like a USB serial adapter's device
+    // file, and the other end plays the adapter with a Game Boy that inverts every byte
+    let (mut adapter, device) = unsafe {
+        let (mut master, mut slave) = (0, 0);
+        let result = libc::openpty(
+            &mut master,
+            &mut slave,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+        );
+        assert_eq!(result, 0, "{}", io::Error::last_os_error());
+        let mut termios = std::mem::zeroed();
+        assert_eq!(libc::tcgetattr(slave, &mut termios), 0);
+        libc::cfmakeraw(&mut termios);
+        assert_eq!(libc::tcsetattr(slave, libc::TCSANOW, &termios), 0);
+        (File::from_raw_fd(master), File::from_raw_fd(slave))
+    };
+    let game_boy = std::thread::spawn(move || {
+        let mut clocked = Vec::new();
+        let mut byte = [0];
+        // reading fails once the bridge closes its end
+        while adapter.read_exact(&mut byte).is_ok() {
+            clocked.push(byte[0]);
+            adapter.write_all(&[!byte[0]]).unwrap();
+        }
+        clocked
+    });
+
+    let (mut bgb, bridged) = connected_pair();
+    let mut bridge = HardwareBridge::new(device, bridged, BridgeRole::HardwareSlave).unwrap();
+    bgb.read().unwrap();
+    for &data in &[0x0F, 0xA5] {
+        bgb.write(&TypedBgbCommand::Sync1 {
+            data,
+            high_speed: false,
+            double_speed: false,
+            timestamp: 0,
+        })
+        .unwrap();
+        assert_eq!(
+            bridge.step().unwrap(),
+            BridgeEvent::Transfer {
+                from_hardware: !data,
+                from_bgb: data,
+            }
+        );
+        assert_eq!(bgb.read().unwrap(), TypedBgbCommand::Sync2 { data: !data });
+    }
+    drop(bridge);
+    assert_eq!(game_boy.join().unwrap(), [0x0F, 0xA5]);
+}
@@ -0,0 +1,63 @@
+use super::*;
+use bytes::{Buf, BufMut, BytesMut};
+use std::io;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Frames a byte stream into `RawBgbCommand`s, for use with `tokio_util::codec::Framed`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RawBgbCodec;
+
+impl Decoder for RawBgbCodec {
+    type Item = RawBgbCommand;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<RawBgbCommand>> {
+        if src.len() < 8 {
+            src.reserve(8 - src.len());
+            return Ok(None);
+        }
+        let mut buf = [0u8; 8];
+        src.copy_to_slice(&mut buf);
+        Ok(Some(RawBgbCommand::deserialize(&buf)))
+    }
+}
+
+impl Encoder<RawBgbCommand> for RawBgbCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: RawBgbCommand, dst: &mut BytesMut) -> io::Result<()> {
+        dst.put_slice(&item.serialize());
+        Ok(())
+    }
+}
+
+/// Frames a byte stream into `TypedBgbCommand`s, for use with `tokio_util::codec::Framed`.
+///
+/// If a packet is too malformed to interpret, decoding fails with an error of kind
+/// `InvalidData`, the same as `BgbStream::read`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TypedBgbCodec;
+
+impl Decoder for TypedBgbCodec {
+    type Item = TypedBgbCommand;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<TypedBgbCommand>> {
+        match RawBgbCodec.decode(src)? {
+            Some(raw) => match TypedBgbCommand::from_raw(&raw) {
+                Ok(result) => Ok(Some(result)),
+                Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+impl Encoder<TypedBgbCommand> for TypedBgbCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: TypedBgbCommand, dst: &mut BytesMut) -> io::Result<()> {
+        dst.put_slice(&item.serialize());
+        Ok(())
+    }
+}
@@ -1,8 +1,34 @@
+#[cfg(feature = "tokio-codec")]
+pub mod codec;
+#[allow(clippy::redundant_pattern_matching)]
 mod tests;
 pub mod typed;
 
 pub use typed::TypedBgbCommand;
 
+use std::convert::TryInto;
+use std::slice::ChunksExact;
+use std::time::Duration;
+
+/// The number of BGB timestamp units per second (2 MiHz).
+pub const TIMESTAMP_RATE: u64 = 1 << 21;
+
+/// The command number used for this crate's extensions to the protocol, which BGB doesn't
+/// define. The `b2` field says which extension message a packet is. Offers of extensions
+/// are `Status` packets instead, marked by this number in `b3`.
+pub const EXTENSION_COMMAND: u8 = 120;
+
+/// The extension bit for `Infrared` packets, which carry the state of a Game Boy Color's IR
+/// LED.
+pub const EXTENSION_INFRARED: u32 = 1 << 0;
+
+/// The extension bit for `StateHash` packets, which carry a hash of the sender's emulated
+/// state so that the two sides can tell when they've diverged.
+pub const EXTENSION_STATE_HASH: u32 = 1 << 1;
+
+/// Every extension this version of the crate understands.
+pub const SUPPORTED_EXTENSIONS: u32 = EXTENSION_INFRARED | EXTENSION_STATE_HASH;
+
 /// A common trait for anything that can be serialized into the BGB format.
 pub trait BgbCommand {
     /// Serializes the object into an 8-byte packet.
@@ -47,4 +73,61 @@ impl RawBgbCommand {
             i1: u32::from_le_bytes(i1_bytes),
         }
     }
+
+    /// Interprets every complete 8-byte packet in the buffer, in order, without copying it.
+    ///
+    /// Any trailing bytes that don't make up a full packet are available from
+    /// `DecodeAll::remainder`.
+    pub fn decode_all(bytes: &[u8]) -> DecodeAll<'_> {
+        DecodeAll {
+            chunks: bytes.chunks_exact(8),
+        }
+    }
+}
+
+/// An iterator over the packets in a byte slice, created by `RawBgbCommand::decode_all`.
+#[derive(Clone, Debug)]
+pub struct DecodeAll<'a> {
+    chunks: ChunksExact<'a, u8>,
+}
+
+impl<'a> DecodeAll<'a> {
+    /// Returns the bytes at the end of the slice that don't make up a full packet.
+    pub fn remainder(&self) -> &'a [u8] {
+        self.chunks.remainder()
+    }
+}
+
+impl Iterator for DecodeAll<'_> {
+    type Item = RawBgbCommand;
+
+    fn next(&mut self) -> Option<RawBgbCommand> {
+        self.chunks
+            .next()
+            .map(|chunk| RawBgbCommand::deserialize(chunk.try_into().unwrap()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.chunks.size_hint()
+    }
+}
+
+impl ExactSizeIterator for DecodeAll<'_> {}
+
+/// Serializes each command in turn onto the end of the buffer.
+pub fn encode_all(commands: &[impl BgbCommand], buf: &mut Vec<u8>) {
+    buf.reserve(commands.len() * 8);
+    for command in commands {
+        buf.extend_from_slice(&command.serialize());
+    }
+}
+
+/// Converts an amount of real time to a BGB timestamp, for peers that aren't emulating a
+/// Game Boy and just need their timestamps to advance at the right rate.
+///
+/// Timestamps are 31 bits, so this wraps around roughly every 17 minutes.
+pub fn real_timestamp(elapsed: Duration) -> u32 {
+    let ticks = elapsed.as_secs() * TIMESTAMP_RATE
+        + u64::from(elapsed.subsec_nanos()) * TIMESTAMP_RATE / 1_000_000_000;
+    (ticks & 0x7FFF_FFFF) as u32
 }
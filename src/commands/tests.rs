@@ -451,3 +451,190 @@ fn typed_from_raw() -> Result<(), super::typed::CommandError> {
 
     Ok(())
 }
+
+#[test]
+fn extension_commands() {
+    use super::typed::TypedBgbCommand::*;
+    use super::*;
+
+    let offer = ExtensionOffer {
+        extensions: SUPPORTED_EXTENSIONS,
+    };
+    assert_eq!(
+        offer.to_raw(),
+        RawBgbCommand {
+            b1: 108,
+            b2: 1,
+            b3: 120,
+            b4: 0,
+            i1: 3,
+        }
+    );
+    let infrared = Infrared {
+        led_on: true,
+        timestamp: 0x1234,
+    };
+    assert_eq!(infrared.serialize(), [120, 1, 1, 0, 0x34, 0x12, 0, 0]);
+    let hash = StateHash {
+        hash: 0xBEEF,
+        timestamp: 0x1234,
+    };
+    assert_eq!(hash.serialize(), [120, 2, 0xEF, 0xBE, 0x34, 0x12, 0, 0]);
+    for command in &[offer, infrared, hash] {
+        assert_eq!(
+            TypedBgbCommand::from_raw(&command.to_raw()).unwrap(),
+            *command
+        );
+    }
+    assert!(TypedBgbCommand::deserialize(&[120, 0, 0, 0, 0, 0, 0, 0]).is_err());
+    assert!(TypedBgbCommand::deserialize(&[120, 3, 0, 0, 0, 0, 0, 0]).is_err());
+}
+
+#[test]
+fn batch_encoding() {
+    use super::typed::TypedBgbCommand::*;
+    use super::*;
+
+    let commands = [
+        Sync2 { data: 7 },
+        Sync3Timestamp { timestamp: 300 },
+        WantDisconnect,
+    ];
+    let mut buf = Vec::new();
+    encode_all(&commands, &mut buf);
+    buf.extend_from_slice(&[104, 1, 0x81]);
+
+    let mut frames = RawBgbCommand::decode_all(&buf);
+    assert_eq!(frames.len(), 3);
+    for command in &commands {
+        assert_eq!(frames.next(), Some(command.to_raw()));
+    }
+    assert_eq!(frames.next(), None);
+    assert_eq!(frames.remainder(), &[104, 1, 0x81]);
+}
+
+#[cfg(feature = "tokio-codec")]
+#[test]
+fn tokio_codec() {
+    use super::codec::*;
+    use super::typed::TypedBgbCommand::*;
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    let mut buf = BytesMut::new();
+    TypedBgbCodec
+        .encode(Sync3Timestamp { timestamp: 5 }, &mut buf)
+        .unwrap();
+    RawBgbCodec
+        .encode(
+            Status {
+                running: true,
+                paused: false,
+                support_reconnect: true,
+            }
+            .to_raw(),
+            &mut buf,
+        )
+        .unwrap();
+    assert_eq!(buf.len(), 16);
+
+    let mut partial = buf.split_to(12);
+    assert_eq!(
+        TypedBgbCodec.decode(&mut partial).unwrap(),
+        Some(Sync3Timestamp { timestamp: 5 })
+    );
+    assert_eq!(TypedBgbCodec.decode(&mut partial).unwrap(), None);
+    partial.unsplit(buf);
+    assert_eq!(
+        RawBgbCodec.decode(&mut partial).unwrap(),
+        Some(
+            Status {
+                running: true,
+                paused: false,
+                support_reconnect: true
+            }
+            .to_raw()
+        )
+    );
+    assert!(partial.is_empty());
+
+    let mut invalid = BytesMut::from(&[246u8, 0, 0, 0, 0, 0, 0, 0][..]);
+    assert_eq!(
+        TypedBgbCodec.decode(&mut invalid).unwrap_err().kind(),
+        std::io::ErrorKind::InvalidData
+    );
+}
+
+/// Generates every command that `to_raw` can encode without losing information.
+#[cfg(test)]
+fn any_typed_command() -> impl proptest::strategy::Strategy<Value = super::TypedBgbCommand> {
+    use super::TypedBgbCommand::*;
+    use proptest::prelude::*;
+
+    prop_oneof![
+        any::<bool>().prop_map(|valid| Version { valid }),
+        (0..8u8, any::<bool>()).prop_map(|(button_number, pressed)| Joypad {
+            button_number,
+            pressed,
+        }),
+        any::<(u8, bool, bool, u32)>().prop_map(|(data, high_speed, double_speed, timestamp)| {
+            Sync1 {
+                data,
+                high_speed,
+                double_speed,
+                timestamp,
+            }
+        }),
+        any::<u8>().prop_map(|data| Sync2 { data }),
+        Just(Sync3Response),
+        any::<u32>().prop_map(|timestamp| Sync3Timestamp { timestamp }),
+        any::<(bool, bool, bool)>().prop_map(|(running, paused, support_reconnect)| Status {
+            running,
+            paused,
+            support_reconnect,
+        }),
+        Just(WantDisconnect),
+        any::<u32>().prop_map(|extensions| ExtensionOffer { extensions }),
+        any::<(bool, u32)>().prop_map(|(led_on, timestamp)| Infrared { led_on, timestamp }),
+        any::<(u16, u32)>().prop_map(|(hash, timestamp)| StateHash { hash, timestamp }),
+    ]
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn raw_round_trip(bytes in proptest::prelude::any::<[u8; 8]>()) {
+        use super::*;
+
+        let raw = RawBgbCommand::deserialize(&bytes);
+        proptest::prop_assert_eq!(raw.serialize(), bytes);
+        proptest::prop_assert_eq!(RawBgbCommand::deserialize(&raw.serialize()), raw);
+    }
+
+    #[test]
+    fn typed_round_trip(command in any_typed_command()) {
+        use super::*;
+
+        proptest::prop_assert_eq!(
+            TypedBgbCommand::from_raw(&command.to_raw()).unwrap(),
+            command.clone()
+        );
+        proptest::prop_assert_eq!(
+            TypedBgbCommand::deserialize(&command.serialize()).unwrap(),
+            command
+        );
+    }
+
+    #[test]
+    fn typed_reencodes(bytes in proptest::prelude::any::<[u8; 8]>()) {
+        use super::*;
+
+        // whatever a malformed packet is interpreted as, sending it on means the same thing
+        if let Ok(command) = TypedBgbCommand::deserialize(&bytes) {
+            proptest::prop_assert_eq!(
+                TypedBgbCommand::from_raw(&command.to_raw()).unwrap(),
+                command
+            );
+        }
+    }
+}
@@ -4,7 +4,11 @@ use std::fmt;
 use TypedBgbCommand::*;
 
 /// Particular commands and their relevant data.
+///
+/// This crate adds commands of its own for its extensions to the protocol, and may add more,
+/// so matches on this enum need a wildcard arm.
 #[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
 pub enum TypedBgbCommand {
     Version {
         valid: bool,
@@ -32,6 +36,25 @@ pub enum TypedBgbCommand {
         support_reconnect: bool,
     },
     WantDisconnect,
+    /// Lists the extensions the sender understands, as a mask of `EXTENSION_*` bits.
+    /// `BgbStream` consumes these during the handshake instead of returning them.
+    ///
+    /// This is sent as a `Status` packet saying that the sender is running, with
+    /// `EXTENSION_COMMAND` in `b3` and the mask in `i1`. BGB and versions of this crate
+    /// without extensions ignore those fields and see an ordinary status.
+    ExtensionOffer {
+        extensions: u32,
+    },
+    /// The sender's infrared LED was turned on or off at the given timestamp.
+    Infrared {
+        led_on: bool,
+        timestamp: u32,
+    },
+    /// A 16-bit hash of the sender's emulated state as of the given timestamp.
+    StateHash {
+        hash: u16,
+        timestamp: u32,
+    },
 }
 
 impl TypedBgbCommand {
@@ -122,6 +145,27 @@ impl TypedBgbCommand {
                 b4: 0,
                 i1: 0,
             },
+            ExtensionOffer { extensions } => RawBgbCommand {
+                b1: 108,
+                b2: 1 << 0,
+                b3: EXTENSION_COMMAND,
+                b4: 0,
+                i1: extensions,
+            },
+            Infrared { led_on, timestamp } => RawBgbCommand {
+                b1: EXTENSION_COMMAND,
+                b2: 1,
+                b3: if led_on { 1 } else { 0 },
+                b4: 0,
+                i1: timestamp,
+            },
+            StateHash { hash, timestamp } => RawBgbCommand {
+                b1: EXTENSION_COMMAND,
+                b2: 2,
+                b3: hash as u8,
+                b4: (hash >> 8) as u8,
+                i1: timestamp,
+            },
         }
     }
 
@@ -129,7 +173,7 @@ impl TypedBgbCommand {
     ///
     /// In most cases, this will accept malformed input and either ignore it or pass it along.
     /// The exceptions are if `b1` is not recognized as a valid command type or if the `b2`
-    /// field of a `sync3` command is not recognized.
+    /// field of a `sync3` or extension command is not recognized.
     pub fn from_raw(raw: &RawBgbCommand) -> Result<TypedBgbCommand, CommandError> {
         let RawBgbCommand { b1, b2, b3, b4, i1 } = *raw;
         match b1 {
@@ -156,12 +200,24 @@ impl TypedBgbCommand {
                     Err(CommandError::new(String::from("invalid sync3 command")))
                 }
             }
+            108 if b3 == EXTENSION_COMMAND => Ok(ExtensionOffer { extensions: i1 }),
             108 => Ok(Status {
                 running: b2 & (1 << 0) > 0,
                 paused: b2 & (1 << 1) > 0,
                 support_reconnect: b2 & (1 << 2) > 0,
             }),
             109 => Ok(WantDisconnect),
+            EXTENSION_COMMAND => match b2 {
+                1 => Ok(Infrared {
+                    led_on: b3 & 1 > 0,
+                    timestamp: i1,
+                }),
+                2 => Ok(StateHash {
+                    hash: u16::from_le_bytes([b3, b4]),
+                    timestamp: i1,
+                }),
+                _ => Err(CommandError::new(String::from("invalid extension command"))),
+            },
             _ => Err(CommandError::new(String::from("invalid command number"))),
         }
     }
@@ -0,0 +1,407 @@
+mod tests;
+
+use crate::commands::*;
+use crate::net::handshake::HandshakeError;
+use crate::net::listener::BgbListener;
+use crate::net::stream::BgbStream;
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+/// How long a peer has to answer before a scenario fails, by default.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The byte sent to the peer in the master transfer scenario.
+const TRANSFER_DATA: u8 = 0xA5;
+/// The timestamp sent to the peer in the heartbeat scenario.
+const HEARTBEAT_TIMESTAMP: u32 = 1000;
+/// How long the peer is watched for hanging up after this side pauses and resumes, at most.
+const STATUS_GRACE: Duration = Duration::from_millis(200);
+
+/// The scenarios that a `Conformance` run checks, in the order they run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scenario {
+    /// The peer's first packet is a version packet for protocol 1.4, sent in time.
+    Handshake,
+    /// The peer reports its status as running, and stays connected while this side pauses
+    /// and resumes.
+    Status,
+    /// The peer answers a `Sync1` with a `Sync2`, or a `Sync3Response` if its game isn't
+    /// waiting for a transfer.
+    MasterTransfer,
+    /// The peer echoes a `Sync3Timestamp` back with the same timestamp, as a link slave does.
+    Heartbeat,
+    /// The peer hangs up, or agrees to, after a `WantDisconnect`.
+    Disconnect,
+}
+
+impl Scenario {
+    /// Every scenario, in the order they run.
+    pub const ALL: [Scenario; 5] = [
+        Scenario::Handshake,
+        Scenario::Status,
+        Scenario::MasterTransfer,
+        Scenario::Heartbeat,
+        Scenario::Disconnect,
+    ];
+
+    /// Returns a short name for the scenario, as used in reports.
+    pub fn name(self) -> &'static str {
+        match self {
+            Scenario::Handshake => "handshake",
+            Scenario::Status => "status",
+            Scenario::MasterTransfer => "master transfer",
+            Scenario::Heartbeat => "sync3 heartbeat",
+            Scenario::Disconnect => "disconnect",
+        }
+    }
+}
+
+/// How a single scenario went.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The peer behaved as BGB does, with a note on what it did.
+    Passed(String),
+    /// The peer didn't, with a description of what went wrong.
+    Failed(String),
+    /// The scenario couldn't run because an earlier one broke the connection.
+    Skipped,
+}
+
+/// Why a scenario failed.
+#[derive(Debug)]
+enum Failure {
+    /// The peer didn't behave like BGB, but the connection can still be used.
+    Protocol(String),
+    /// The connection broke, or lost its place in the stream of packets.
+    Transport(io::Error),
+}
+
+impl From<io::Error> for Failure {
+    fn from(error: io::Error) -> Failure {
+        Failure::Transport(error)
+    }
+}
+
+/// The outcome of every scenario in a `Conformance` run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConformanceReport {
+    pub results: Vec<(Scenario, Outcome)>,
+}
+
+impl ConformanceReport {
+    /// Returns whether every scenario passed.
+    pub fn passed(&self) -> bool {
+        self.results
+            .iter()
+            .all(|(_, outcome)| matches!(outcome, Outcome::Passed(_)))
+    }
+
+    /// Returns the outcome of a scenario.
+    pub fn outcome(&self, scenario: Scenario) -> Option<&Outcome> {
+        self.results
+            .iter()
+            .find(|(s, _)| *s == scenario)
+            .map(|(_, outcome)| outcome)
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (scenario, outcome) in &self.results {
+            match outcome {
+                Outcome::Passed(note) => writeln!(f, "PASS  {}: {}", scenario.name(), note)?,
+                Outcome::Failed(error) => writeln!(f, "FAIL  {}: {}", scenario.name(), error)?,
+                Outcome::Skipped => writeln!(f, "SKIP  {}", scenario.name())?,
+            }
+        }
+        let passed = self
+            .results
+            .iter()
+            .filter(|(_, outcome)| matches!(outcome, Outcome::Passed(_)))
+            .count();
+        write!(f, "{} of {} scenarios passed", passed, self.results.len())
+    }
+}
+
+/// Checks that a peer, such as an emulator, follows the BGB link protocol the way BGB does.
+///
+/// Each scenario sends the peer some packets and waits for its answer, ignoring unrelated
+/// packets in the meantime. The peer's game should be idle, since the scenarios don't
+/// answer transfers the peer starts itself.
+///
+/// A scenario where the peer answers wrongly or not at all fails on its own, but once the
+/// connection breaks, or the peer stops partway through a packet, the remaining scenarios
+/// are skipped.
+#[derive(Clone, Debug)]
+pub struct Conformance {
+    timeout: Duration,
+}
+
+impl Default for Conformance {
+    fn default() -> Self {
+        Conformance::new()
+    }
+}
+
+impl Conformance {
+    /// Creates a harness that gives the peer `DEFAULT_TIMEOUT` to answer each scenario.
+    pub fn new() -> Conformance {
+        Conformance {
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Sets how long the peer has to answer each scenario.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Connects to a listening peer and runs every scenario.
+    ///
+    /// Returns an error only if the connection can't be made at all, including if the peer
+    /// doesn't accept it within the timeout.
+    pub fn connect<A: ToSocketAddrs>(&self, addr: A) -> io::Result<ConformanceReport> {
+        let mut last_error = None;
+        for addr in addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.timeout) {
+                Ok(socket) => return self.start(socket, addr),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any addresses",
+            )
+        }))
+    }
+
+    /// Waits for a peer to connect and runs every scenario.
+    ///
+    /// Returns an error only if accepting the connection fails. This blocks until a peer
+    /// connects, which then has the timeout to send its half of the handshake.
+    pub fn accept(&self, listener: &BgbListener) -> io::Result<ConformanceReport> {
+        let (socket, addr) = listener.get_ref().accept()?;
+        self.start(socket, addr)
+    }
+
+    /// Runs every scenario over a connection that has completed its handshake.
+    pub fn run(&self, mut stream: BgbStream<TcpStream>) -> ConformanceReport {
+        let mut results = vec![(
+            Scenario::Handshake,
+            Outcome::Passed(String::from("peer speaks protocol 1.4")),
+        )];
+        let mut broken = false;
+        for &scenario in &Scenario::ALL[1..] {
+            let outcome = if broken {
+                Outcome::Skipped
+            } else {
+                match self.scenario(scenario, &mut stream) {
+                    Ok(note) => Outcome::Passed(note),
+                    Err(Failure::Protocol(error)) => Outcome::Failed(error),
+                    Err(Failure::Transport(e)) => {
+                        broken = true;
+                        Outcome::Failed(e.to_string())
+                    }
+                }
+            };
+            results.push((scenario, outcome));
+        }
+        ConformanceReport { results }
+    }
+
+    /// Performs the handshake on a new connection, giving the peer the timeout to send its
+    /// version, and runs the rest of the scenarios if it succeeds.
+    fn start(&self, socket: TcpStream, addr: SocketAddr) -> io::Result<ConformanceReport> {
+        socket.set_nodelay(true)?;
+        socket.set_read_timeout(Some(self.timeout))?;
+        let mut stream = BgbStream::wrap(socket);
+        stream.set_peer(addr);
+        match stream.handshake() {
+            Ok(()) => Ok(self.run(stream)),
+            Err(e) => self.handshake_failed(e),
+        }
+    }
+
+    fn handshake_failed(&self, error: io::Error) -> io::Result<ConformanceReport> {
+        let error = if is_timeout(&error) {
+            format!(
+                "timed out after {:?} waiting for the peer's version",
+                self.timeout
+            )
+        } else if HandshakeError::from_io(&error).is_some() {
+            error.to_string()
+        } else {
+            return Err(error);
+        };
+        let mut results = vec![(Scenario::Handshake, Outcome::Failed(error))];
+        results.extend(Scenario::ALL[1..].iter().map(|&s| (s, Outcome::Skipped)));
+        Ok(ConformanceReport { results })
+    }
+
+    fn scenario(
+        &self,
+        scenario: Scenario,
+        stream: &mut BgbStream<TcpStream>,
+    ) -> Result<String, Failure> {
+        match scenario {
+            Scenario::Handshake => Ok(String::from("peer speaks protocol 1.4")),
+            Scenario::Status => {
+                let running = self.expect(stream, "its status", |command| match *command {
+                    TypedBgbCommand::Status { running, .. } => Some(running),
+                    _ => None,
+                })?;
+                if !running {
+                    return Err(failure("peer reported that it isn't running"));
+                }
+                for &paused in &[true, false] {
+                    stream.write(&TypedBgbCommand::Status {
+                        running: true,
+                        paused,
+                        support_reconnect: false,
+                    })?;
+                }
+                let grace = self.timeout.min(STATUS_GRACE);
+                match self.expect_within(stream, grace, "a disconnect", |command| match *command {
+                    TypedBgbCommand::WantDisconnect => Some(()),
+                    _ => None,
+                }) {
+                    Ok(()) => Err(failure("peer disconnected when this side paused")),
+                    Err(Failure::Protocol(_)) => Ok(String::from(
+                        "peer is running and stayed connected through a pause",
+                    )),
+                    Err(Failure::Transport(e)) => Err(Failure::Transport(e)),
+                }
+            }
+            Scenario::MasterTransfer => {
+                stream.write(&TypedBgbCommand::Sync1 {
+                    data: TRANSFER_DATA,
+                    high_speed: false,
+                    double_speed: false,
+                    timestamp: 0,
+                })?;
+                self.expect(stream, "an answer to Sync1", |command| match *command {
+                    TypedBgbCommand::Sync2 { data } => {
+                        Some(format!("peer answered with {:#04x}", data))
+                    }
+                    TypedBgbCommand::Sync3Response => {
+                        Some(String::from("peer's game wasn't waiting for a transfer"))
+                    }
+                    _ => None,
+                })
+            }
+            Scenario::Heartbeat => {
+                stream.write(&TypedBgbCommand::Sync3Timestamp {
+                    timestamp: HEARTBEAT_TIMESTAMP,
+                })?;
+                // the peer's own timestamps don't answer this one
+                self.expect(
+                    stream,
+                    "the Sync3 timestamp to be echoed",
+                    |command| match *command {
+                        TypedBgbCommand::Sync3Timestamp { timestamp }
+                            if timestamp == HEARTBEAT_TIMESTAMP =>
+                        {
+                            Some(String::from("peer echoed the timestamp"))
+                        }
+                        _ => None,
+                    },
+                )
+            }
+            Scenario::Disconnect => {
+                stream.write(&TypedBgbCommand::WantDisconnect)?;
+                match self.expect(
+                    stream,
+                    "the connection to close",
+                    |command| match *command {
+                        TypedBgbCommand::WantDisconnect => Some(()),
+                        _ => None,
+                    },
+                ) {
+                    Ok(()) => Ok(String::from("peer agreed to disconnect")),
+                    Err(Failure::Transport(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                        Ok(String::from("peer closed the connection"))
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+        }
+    }
+
+    /// Reads packets until one that `check` accepts, as in `expect_within`, giving the peer
+    /// the harness's timeout.
+    fn expect<R>(
+        &self,
+        stream: &mut BgbStream<TcpStream>,
+        waiting_for: &str,
+        check: impl Fn(&TypedBgbCommand) -> Option<R>,
+    ) -> Result<R, Failure> {
+        self.expect_within(stream, self.timeout, waiting_for, check)
+    }
+
+    /// Reads packets until one that `check` accepts, skipping the rest.
+    ///
+    /// Transfers the peer starts are refused with a `Sync3Response`, as if this side's game
+    /// weren't listening. If nothing is accepted in time, or a packet is malformed, returns
+    /// a protocol failure. Only the wait for the start of each packet is limited by the
+    /// timeout: a packet that stops partway through leaves the stream out of step, so
+    /// that's a transport failure.
+    fn expect_within<R>(
+        &self,
+        stream: &mut BgbStream<TcpStream>,
+        timeout: Duration,
+        waiting_for: &str,
+        check: impl Fn(&TypedBgbCommand) -> Option<R>,
+    ) -> Result<R, Failure> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) || !wait_for_data(stream, remaining)? {
+                return Err(failure(&format!(
+                    "timed out after {:?} waiting for {}",
+                    timeout, waiting_for
+                )));
+            }
+            // the rest of a packet that has started arriving gets the full timeout
+            stream.get_ref().set_read_timeout(Some(self.timeout))?;
+            let command = match stream.read() {
+                Ok(command) => command,
+                Err(ref e) if e.kind() == io::ErrorKind::InvalidData => {
+                    return Err(failure(&e.to_string()))
+                }
+                Err(e) => return Err(Failure::Transport(e)),
+            };
+            if let Some(result) = check(&command) {
+                return Ok(result);
+            }
+            if let TypedBgbCommand::Sync1 { .. } = command {
+                stream.write(&TypedBgbCommand::Sync3Response)?;
+            }
+        }
+    }
+}
+
+/// Waits up to `timeout` for the peer to send something, without consuming it. Returns
+/// `false` if nothing arrived in time.
+fn wait_for_data(stream: &BgbStream<TcpStream>, timeout: Duration) -> io::Result<bool> {
+    stream.get_ref().set_read_timeout(Some(timeout))?;
+    match stream.get_ref().peek(&mut [0]) {
+        // the end of the stream is reported by the read that follows
+        Ok(_) => Ok(true),
+        Err(ref e) if is_timeout(e) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn failure(error: &str) -> Failure {
+    Failure::Protocol(String::from(error))
+}
+
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    )
+}
@@ -0,0 +1,211 @@
+#[test]
+fn conforming_peer() {
+    use super::*;
+    use crate::peripheral::{PeripheralLink, SerialDevice};
+    use std::net::TcpListener;
+
+    struct Inverter;
+    impl SerialDevice for Inverter {
+        fn transfer(&mut self, received: u8) -> u8 {
+            !received
+        }
+    }
+
+    let listener = BgbListener::wrap(TcpListener::bind("127.0.0.1:0").unwrap());
+    let addr = listener.local_addr().unwrap();
+    let peer = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        PeripheralLink::new(Inverter, stream)
+            .unwrap()
+            .run()
+            .unwrap();
+    });
+
+    let report = Conformance::new().connect(addr).unwrap();
+    peer.join().unwrap();
+    assert!(report.passed(), "{}", report);
+    assert_eq!(report.results.len(), Scenario::ALL.len());
+    assert_eq!(
+        report.outcome(Scenario::MasterTransfer),
+        Some(&Outcome::Passed(String::from("peer answered with 0x5a")))
+    );
+    assert!(report.to_string().ends_with("5 of 5 scenarios passed"));
+}
+
+#[test]
+fn silent_peer() {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Barrier};
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let done = Arc::new(Barrier::new(2));
+    let peer_done = done.clone();
+    // a peer that completes the handshake and then never says anything else
+    let peer = std::thread::spawn(move || {
+        let (stream, _) = BgbListener::wrap(listener).accept().unwrap();
+        peer_done.wait();
+        drop(stream);
+    });
+
+    let mut conformance = Conformance::new();
+    conformance.set_timeout(Duration::from_millis(50));
+    let report = conformance.connect(addr).unwrap();
+    done.wait();
+    peer.join().unwrap();
+    assert!(!report.passed());
+    assert_eq!(
+        report.outcome(Scenario::Handshake),
+        Some(&Outcome::Passed(String::from("peer speaks protocol 1.4")))
+    );
+    for &scenario in &Scenario::ALL[1..] {
+        assert!(matches!(report.outcome(scenario), Some(Outcome::Failed(_))));
+    }
+    assert!(report
+        .to_string()
+        .contains("FAIL  sync3 heartbeat: timed out"));
+}
+
+#[test]
+fn bad_handshake() {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    let listener = BgbListener::wrap(TcpListener::bind("127.0.0.1:0").unwrap());
+    let addr = listener.local_addr().unwrap();
+    let peer = std::thread::spawn(move || {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        // protocol 1.3
+        stream.write_all(&[1, 1, 3, 0, 0, 0, 0, 0]).unwrap();
+    });
+
+    let report = Conformance::new().accept(&listener).unwrap();
+    peer.join().unwrap();
+    assert!(matches!(
+        report.outcome(Scenario::Handshake),
+        Some(Outcome::Failed(_))
+    ));
+    assert_eq!(
+        report.outcome(Scenario::Disconnect),
+        Some(&Outcome::Skipped)
+    );
+
+    // a peer that never sends its version fails once the timeout runs out
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let peer = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+        drop(stream);
+    });
+    let mut conformance = Conformance::new();
+    conformance.set_timeout(Duration::from_millis(50));
+    let report = conformance.connect(addr).unwrap();
+    peer.join().unwrap();
+    assert_eq!(
+        report.outcome(Scenario::Handshake),
+        Some(&Outcome::Failed(String::from(
+            "timed out after 50ms waiting for the peer's version"
+        )))
+    );
+}
+
+#[test]
+fn misbehaving_peers() {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    /// Runs the harness against a peer that sends the given status and then misbehaves.
+    fn check(running: bool, misbehave: fn(&mut BgbStream<TcpStream>)) -> ConformanceReport {
+        let listener = BgbListener::wrap(TcpListener::bind("127.0.0.1:0").unwrap());
+        let addr = listener.local_addr().unwrap();
+        let peer = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .write(&TypedBgbCommand::Status {
+                    running,
+                    paused: false,
+                    support_reconnect: false,
+                })
+                .unwrap();
+            misbehave(&mut stream);
+        });
+        let mut conformance = Conformance::new();
+        conformance.set_timeout(Duration::from_millis(100));
+        let report = conformance.connect(addr).unwrap();
+        peer.join().unwrap();
+        report
+    }
+
+    // a peer that isn't running fails the status scenario, but the link carries on
+    let report = check(false, |stream| loop {
+        let command = stream.read().unwrap();
+        stream
+            .answer_as_slave(&command, |data| Ok(Some(!data)))
+            .unwrap();
+        if command == TypedBgbCommand::WantDisconnect {
+            break;
+        }
+    });
+    assert_eq!(
+        report.outcome(Scenario::Status),
+        Some(&Outcome::Failed(String::from(
+            "peer reported that it isn't running"
+        )))
+    );
+    assert_eq!(
+        report.outcome(Scenario::MasterTransfer),
+        Some(&Outcome::Passed(String::from("peer answered with 0x5a")))
+    );
+
+    // a peer that hangs up when this side pauses breaks the link
+    let report = check(true, |stream| {
+        stream.read().unwrap();
+    });
+    assert!(matches!(
+        report.outcome(Scenario::Status),
+        Some(Outcome::Failed(_))
+    ));
+    assert_eq!(
+        report.outcome(Scenario::MasterTransfer),
+        Some(&Outcome::Skipped)
+    );
+
+    // so does one that stops partway through a packet
+    let report = check(true, |stream| {
+        stream.get_mut().write_all(&[104, 0x12, 0x81]).unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+    });
+    assert!(matches!(
+        report.outcome(Scenario::Status),
+        Some(Outcome::Failed(_))
+    ));
+    assert_eq!(report.outcome(Scenario::Heartbeat), Some(&Outcome::Skipped));
+
+    // a peer that sends timestamps of its own without echoing fails the heartbeat only
+    let report = check(true, |stream| loop {
+        let command = stream.read().unwrap();
+        match command {
+            TypedBgbCommand::Sync3Timestamp { timestamp } => stream
+                .write(&TypedBgbCommand::Sync3Timestamp {
+                    timestamp: timestamp + 1,
+                })
+                .unwrap(),
+            TypedBgbCommand::WantDisconnect => break,
+            _ => {
+                stream.answer_as_slave(&command, |_| Ok(None)).unwrap();
+            }
+        }
+    });
+    assert!(matches!(
+        report.outcome(Scenario::Heartbeat),
+        Some(Outcome::Failed(_))
+    ));
+    assert!(matches!(
+        report.outcome(Scenario::Disconnect),
+        Some(Outcome::Passed(_))
+    ));
+}
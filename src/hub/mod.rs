@@ -0,0 +1,386 @@
+mod tests;
+
+use crate::commands::*;
+use crate::net::listener::BgbListener;
+use crate::net::stream::BgbStream;
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The number of Game Boys a Four Player Adapter can connect.
+pub const PLAYERS: usize = 4;
+
+/// The first byte of every packet the adapter sends during the ping phase.
+pub const PING_HEADER: u8 = 0xFE;
+/// What a Game Boy sends at the start of a ping packet to say it's there.
+pub const ACK1: u8 = 0x88;
+/// What player 1 sends at the start of a ping packet instead of `ACK1` to start transmission.
+pub const ACK2: u8 = 0xAA;
+/// What the adapter sends four times between the ping and transmission phases.
+pub const START: u8 = 0xCC;
+
+const PING_LENGTH: usize = 4;
+const DEFAULT_SIZE: u8 = 4;
+const DEFAULT_INTERVAL: Duration = Duration::from_millis(2);
+/// How long a player has to finish its handshake or answer a byte, by default.
+pub const DEFAULT_PLAYER_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The stage of the protocol that a `FourPlayerAdapter` is in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdapterPhase {
+    /// Sending ping packets to find out who's connected, and waiting for player 1 to start.
+    Ping,
+    /// Telling everyone that transmission is about to begin.
+    Starting,
+    /// Collecting a packet from every player and sending all of them back to everyone.
+    Transmission,
+}
+
+/// The protocol logic of a DMG-07 Four Player Adapter, without any connections.
+///
+/// The adapter drives the clock for every player at once. Before each byte, `outgoing`
+/// returns what is shifted out to each player, and `clock` then takes what each player
+/// shifted back. A player that isn't there shifts back `0xFF`, as an open line would.
+///
+/// During the ping phase the adapter repeatedly sends `PING_HEADER` followed by three STAT
+/// bytes, which hold a bit for each player that answered the last ping in the upper nibble
+/// and the receiving player's number in the lower one. Players answer with `ACK1` twice and
+/// then RATE and SIZE, which are only taken from player 1. Once player 1 answers with `ACK2`
+/// instead, the adapter sends `START` four times and switches to the transmission phase.
+///
+/// Each transmission cycle lasts four packets of SIZE bytes. While it lasts, the adapter
+/// sends every player the packets collected during the previous cycle, in player order, and
+/// collects the first SIZE bytes each player sends back as its next packet. If every player
+/// sends nothing but `0xFF` for a whole cycle, the adapter goes back to the ping phase.
+#[derive(Clone, Debug)]
+pub struct FourPlayerAdapter {
+    phase: AdapterPhase,
+    position: usize,
+    connected: u8,
+    answered: u8,
+    start_requested: bool,
+    rate: u8,
+    size: u8,
+    collected: Vec<u8>,
+    frame: Vec<u8>,
+}
+
+impl FourPlayerAdapter {
+    /// Creates an adapter at the start of the ping phase.
+    pub fn new() -> FourPlayerAdapter {
+        FourPlayerAdapter {
+            phase: AdapterPhase::Ping,
+            position: 0,
+            connected: 0,
+            answered: 0,
+            start_requested: false,
+            rate: 0,
+            size: DEFAULT_SIZE,
+            collected: Vec::new(),
+            frame: Vec::new(),
+        }
+    }
+
+    /// Returns the stage of the protocol the adapter is in.
+    pub fn phase(&self) -> AdapterPhase {
+        self.phase
+    }
+
+    /// Returns the RATE byte player 1 last sent during the ping phase.
+    pub fn rate(&self) -> u8 {
+        self.rate
+    }
+
+    /// Returns the number of bytes each player sends per transmission cycle, as set by
+    /// player 1 during the ping phase.
+    pub fn size(&self) -> u8 {
+        self.size
+    }
+
+    /// Returns which players answered the last complete ping packet.
+    pub fn connected(&self) -> [bool; PLAYERS] {
+        let mut connected = [false; PLAYERS];
+        for (player, flag) in connected.iter_mut().enumerate() {
+            *flag = self.connected & (1 << player) != 0;
+        }
+        connected
+    }
+
+    /// Returns the byte that will be shifted out to each player on the next clock.
+    pub fn outgoing(&self) -> [u8; PLAYERS] {
+        let mut outgoing = [0; PLAYERS];
+        for (player, byte) in outgoing.iter_mut().enumerate() {
+            *byte = match self.phase {
+                AdapterPhase::Ping if self.position == 0 => PING_HEADER,
+                AdapterPhase::Ping => self.connected << 4 | (player as u8 + 1),
+                AdapterPhase::Starting => START,
+                AdapterPhase::Transmission => self.frame[self.position],
+            };
+        }
+        outgoing
+    }
+
+    /// Clocks a byte, taking what each player shifted back.
+    pub fn clock(&mut self, received: [u8; PLAYERS]) {
+        match self.phase {
+            AdapterPhase::Ping => self.clock_ping(received),
+            AdapterPhase::Starting => {
+                self.position += 1;
+                if self.position == PING_LENGTH {
+                    self.start_transmission();
+                }
+            }
+            AdapterPhase::Transmission => self.clock_transmission(received),
+        }
+    }
+
+    fn clock_ping(&mut self, received: [u8; PLAYERS]) {
+        let player1_answered = self.answered & 1 != 0;
+        match self.position {
+            0 => {
+                for (player, &byte) in received.iter().enumerate() {
+                    if byte == ACK1 || byte == ACK2 {
+                        self.answered |= 1 << player;
+                    }
+                }
+                self.start_requested = received[0] == ACK2;
+            }
+            2 if player1_answered => self.rate = received[0],
+            // a size of zero would leave nothing to transmit
+            3 if player1_answered && received[0] != 0 => self.size = received[0],
+            _ => {}
+        }
+        self.position += 1;
+        if self.position == PING_LENGTH {
+            self.position = 0;
+            self.connected = self.answered;
+            self.answered = 0;
+            if self.start_requested {
+                self.start_requested = false;
+                self.phase = AdapterPhase::Starting;
+            }
+        }
+    }
+
+    fn start_transmission(&mut self) {
+        let cycle = PLAYERS * self.size as usize;
+        self.phase = AdapterPhase::Transmission;
+        self.position = 0;
+        self.frame = vec![0; cycle];
+        self.collected = vec![0xFF; cycle];
+    }
+
+    fn clock_transmission(&mut self, received: [u8; PLAYERS]) {
+        let size = self.size as usize;
+        if self.position < size {
+            for (player, &byte) in received.iter().enumerate() {
+                self.collected[player * size + self.position] = byte;
+            }
+        }
+        self.position += 1;
+        if self.position == self.frame.len() {
+            self.position = 0;
+            if self.collected.iter().all(|&byte| byte == 0xFF) {
+                self.phase = AdapterPhase::Ping;
+                self.connected = 0;
+            } else {
+                self.frame.copy_from_slice(&self.collected);
+                for byte in &mut self.collected {
+                    *byte = 0xFF;
+                }
+            }
+        }
+    }
+}
+
+impl Default for FourPlayerAdapter {
+    fn default() -> FourPlayerAdapter {
+        FourPlayerAdapter::new()
+    }
+}
+
+/// The bytes exchanged during a single call to `FourPlayerHub::step`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HubTransfer {
+    /// What was shifted out to each player.
+    pub sent: [u8; PLAYERS],
+    /// What each player shifted back, or `None` for empty slots and players that
+    /// disconnected during the transfer.
+    pub received: [Option<u8>; PLAYERS],
+}
+
+/// Connects up to four BGB instances through an emulated Four Player Adapter.
+///
+/// The hub is the link master for every player, so each player's game must be waiting for
+/// transfers with the external clock, as it would be with a real adapter. Each byte the
+/// adapter clocks is sent to every player as a `Sync1` packet and their `Sync2` responses are
+/// fed back into the adapter. A player that answers with `Sync3Response` because it isn't
+/// ready shifts back `0xFF`, and one that disconnects or doesn't answer within the player
+/// timeout frees its slot, so that a stalled player can't hold up everyone else.
+///
+/// The adapter's RATE is recorded but doesn't affect how fast the hub clocks, which is set
+/// with `set_interval` instead.
+#[derive(Debug)]
+pub struct FourPlayerHub {
+    adapter: FourPlayerAdapter,
+    players: [Option<BgbStream<TcpStream>>; PLAYERS],
+    interval: Duration,
+    player_timeout: Duration,
+    started: Instant,
+}
+
+impl FourPlayerHub {
+    /// Creates a hub with no players, which clocks a byte every 2 milliseconds while running.
+    pub fn new() -> FourPlayerHub {
+        FourPlayerHub {
+            adapter: FourPlayerAdapter::new(),
+            players: [None, None, None, None],
+            interval: DEFAULT_INTERVAL,
+            player_timeout: DEFAULT_PLAYER_TIMEOUT,
+            started: Instant::now(),
+        }
+    }
+
+    /// Returns the emulated adapter.
+    pub fn adapter(&self) -> &FourPlayerAdapter {
+        &self.adapter
+    }
+
+    /// Sets how long `run` waits between bytes.
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// Sets how long a player has to finish its handshake in `run`, and to answer each byte.
+    /// This only applies to players added afterwards.
+    pub fn set_player_timeout(&mut self, timeout: Duration) {
+        self.player_timeout = timeout;
+    }
+
+    /// Returns which player slots have a connection in them.
+    pub fn players(&self) -> [bool; PLAYERS] {
+        let mut players = [false; PLAYERS];
+        for (slot, player) in players.iter_mut().zip(&self.players) {
+            *slot = player.is_some();
+        }
+        players
+    }
+
+    /// Puts a connection that has completed its handshake in the first empty slot and
+    /// returns the slot's index.
+    ///
+    /// The connection's read timeout is set to the player timeout. If all four slots are
+    /// taken, the connection is sent `WantDisconnect` and closed.
+    pub fn add_player(&mut self, mut stream: BgbStream<TcpStream>) -> Option<usize> {
+        // a player that can't be written to will be dropped by the next step anyway
+        let slot = match self.players.iter().position(Option::is_none) {
+            Some(slot) => slot,
+            None => {
+                let _ = stream.write(&TypedBgbCommand::WantDisconnect);
+                return None;
+            }
+        };
+        let _ = stream.get_ref().set_read_timeout(Some(self.player_timeout));
+        let _ = stream.write(&TypedBgbCommand::Status {
+            running: true,
+            paused: false,
+            support_reconnect: false,
+        });
+        self.players[slot] = Some(stream);
+        Some(slot)
+    }
+
+    /// Clocks a single byte between the adapter and every player.
+    pub fn step(&mut self) -> HubTransfer {
+        let sent = self.adapter.outgoing();
+        let timestamp = real_timestamp(self.started.elapsed());
+        for (slot, &data) in self.players.iter_mut().zip(&sent) {
+            let sync = TypedBgbCommand::Sync1 {
+                data,
+                high_speed: false,
+                double_speed: false,
+                timestamp,
+            };
+            if let Some(stream) = slot {
+                if stream.write(&sync).is_err() {
+                    *slot = None;
+                }
+            }
+        }
+
+        let mut received = [None; PLAYERS];
+        for (slot, byte) in self.players.iter_mut().zip(&mut received) {
+            if let Some(stream) = slot {
+                *byte = response(stream);
+                if byte.is_none() {
+                    *slot = None;
+                }
+            }
+        }
+        self.adapter
+            .clock(received.map(|byte| byte.unwrap_or(0xFF)));
+        HubTransfer { sent, received }
+    }
+
+    /// Accepts players from the listener and clocks bytes between them, forever.
+    ///
+    /// Connections beyond the fourth are turned away as in `add_player`. Only returns if
+    /// accepting a connection fails; handshakes that fail or don't finish within the player
+    /// timeout are ignored.
+    pub fn run(&mut self, listener: &BgbListener) -> io::Result<()> {
+        listener.set_nonblocking(true)?;
+        loop {
+            loop {
+                match listener.get_ref().accept() {
+                    Ok((socket, addr)) => {
+                        if let Ok(stream) = self.handshake(socket, addr) {
+                            self.add_player(stream);
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => return Err(e),
+                }
+            }
+            if self.players.iter().any(Option::is_some) {
+                self.step();
+            }
+            thread::sleep(self.interval);
+        }
+    }
+
+    fn handshake(&self, socket: TcpStream, addr: SocketAddr) -> io::Result<BgbStream<TcpStream>> {
+        // sockets accepted from a nonblocking listener may be nonblocking themselves
+        socket.set_nonblocking(false)?;
+        socket.set_nodelay(true)?;
+        socket.set_read_timeout(Some(self.player_timeout))?;
+        let mut stream = BgbStream::wrap(socket);
+        stream.set_peer(addr);
+        stream.handshake()?;
+        Ok(stream)
+    }
+}
+
+impl Default for FourPlayerHub {
+    fn default() -> FourPlayerHub {
+        FourPlayerHub::new()
+    }
+}
+
+/// Waits for a player's answer to a `Sync1`, returning `None` if they disconnected.
+fn response(stream: &mut BgbStream<TcpStream>) -> Option<u8> {
+    loop {
+        match stream.read() {
+            Ok(TypedBgbCommand::Sync2 { data }) => return Some(data),
+            // the player wasn't ready, so nothing was shifted in
+            Ok(TypedBgbCommand::Sync3Response) => return Some(0xFF),
+            Ok(TypedBgbCommand::WantDisconnect) => return None,
+            // the adapter drives the clock, so players can't start transfers of their own
+            Ok(command) => {
+                stream.answer_as_slave(&command, |_| Ok(None)).ok()?;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidData => {}
+            Err(_) => return None,
+        }
+    }
+}
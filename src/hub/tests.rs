@@ -0,0 +1,164 @@
+#[test]
+fn adapter_phases() {
+    use super::*;
+
+    fn exchange(adapter: &mut FourPlayerAdapter, received: [u8; PLAYERS]) -> [u8; PLAYERS] {
+        let sent = adapter.outgoing();
+        adapter.clock(received);
+        sent
+    }
+
+    let mut adapter = FourPlayerAdapter::new();
+
+    // players 1 and 3 answer the first ping, and player 1 asks for 2-byte packets
+    assert_eq!(
+        exchange(&mut adapter, [ACK1, 0xFF, ACK1, 0xFF]),
+        [PING_HEADER; 4]
+    );
+    assert_eq!(
+        exchange(&mut adapter, [ACK1, 0xFF, ACK1, 0xFF]),
+        [0x01, 0x02, 0x03, 0x04]
+    );
+    assert_eq!(
+        exchange(&mut adapter, [0x30, 0xFF, 0x00, 0xFF]),
+        [0x01, 0x02, 0x03, 0x04]
+    );
+    assert_eq!(
+        exchange(&mut adapter, [0x02, 0xFF, 0x07, 0xFF]),
+        [0x01, 0x02, 0x03, 0x04]
+    );
+
+    // then player 1 starts transmission
+    assert_eq!(
+        exchange(&mut adapter, [ACK2, 0xFF, ACK1, 0xFF]),
+        [PING_HEADER; 4]
+    );
+    assert_eq!(
+        exchange(&mut adapter, [ACK2, 0xFF, ACK1, 0xFF]),
+        [0x51, 0x52, 0x53, 0x54]
+    );
+    exchange(&mut adapter, [0x30, 0xFF, 0x00, 0xFF]);
+    exchange(&mut adapter, [0x02, 0xFF, 0x00, 0xFF]);
+    for _ in 0..4 {
+        assert_eq!(exchange(&mut adapter, [0; 4]), [START; 4]);
+    }
+    assert_eq!(adapter.phase(), AdapterPhase::Transmission);
+    assert_eq!((adapter.rate(), adapter.size()), (0x30, 2));
+    assert_eq!(adapter.connected(), [true, false, true, false]);
+
+    // the first cycle sends nothing, and the second sends what was collected in the first
+    let first = [[0x11, 0xFF, 0x31, 0xFF], [0x12, 0xFF, 0x32, 0xFF]];
+    for (i, &received) in first.iter().chain(&[[0; 4]; 6]).enumerate() {
+        assert_eq!(exchange(&mut adapter, received), [0; 4], "byte {}", i);
+    }
+    let frame = [0x11, 0x12, 0xFF, 0xFF, 0x31, 0x32, 0xFF, 0xFF];
+    for &byte in &frame {
+        assert_eq!(exchange(&mut adapter, [0xFF; 4]), [byte; 4]);
+    }
+
+    // nobody sent anything in that cycle, so the adapter starts pinging again
+    assert_eq!(adapter.phase(), AdapterPhase::Ping);
+    assert_eq!(adapter.outgoing(), [PING_HEADER; 4]);
+}
+
+#[test]
+fn hub_over_bgb() {
+    use super::*;
+    use std::net::TcpListener;
+
+    let listener = BgbListener::wrap(TcpListener::bind("127.0.0.1:0").unwrap());
+    let addr = listener.local_addr().unwrap();
+    let scripts = [
+        // ping, start, then a 1-byte packet per cycle
+        vec![
+            ACK1, ACK1, 0, 1, ACK2, ACK1, 0, 1, 0, 0, 0, 0, 0x11, 0, 0, 0, 0x12, 0, 0, 0,
+        ],
+        vec![
+            ACK1, ACK1, 0, 0, ACK1, ACK1, 0, 0, 0, 0, 0, 0, 0x21, 0, 0, 0, 0x22, 0, 0, 0,
+        ],
+    ];
+    let mut hub = FourPlayerHub::new();
+    let mut players = Vec::new();
+    for script in scripts.iter().cloned() {
+        players.push(std::thread::spawn(move || {
+            let mut stream = BgbStream::connect(addr).unwrap();
+            let mut received = Vec::new();
+            for byte in script {
+                loop {
+                    match stream.read().unwrap() {
+                        TypedBgbCommand::Sync1 { data, .. } => {
+                            received.push(data);
+                            break;
+                        }
+                        TypedBgbCommand::Status { .. } => {}
+                        other => panic!("unexpected {:?}", other),
+                    }
+                }
+                stream
+                    .write(&TypedBgbCommand::Sync2 { data: byte })
+                    .unwrap();
+            }
+            received
+        }));
+        // accepting each player before the next connects keeps them in order
+        let (stream, _) = listener.accept().unwrap();
+        let slot = hub.add_player(stream);
+        assert_eq!(slot, Some(players.len() - 1));
+    }
+    assert_eq!(hub.players(), [true, true, false, false]);
+
+    let mut last = None;
+    for _ in 0..scripts[0].len() {
+        last = Some(hub.step());
+    }
+    assert_eq!(last.unwrap().received, [Some(0), Some(0), None, None]);
+    assert_eq!(hub.adapter().phase(), AdapterPhase::Transmission);
+
+    let sent: Vec<_> = players.into_iter().map(|p| p.join().unwrap()).collect();
+    assert_eq!(&sent[0][..4], [PING_HEADER, 0x01, 0x01, 0x01]);
+    assert_eq!(&sent[0][4..8], [PING_HEADER, 0x31, 0x31, 0x31]);
+    assert_eq!(&sent[0][8..12], [START; 4]);
+    assert_eq!(&sent[0][12..16], [0; 4]);
+    assert_eq!(&sent[0][16..], [0x11, 0x21, 0xFF, 0xFF]);
+    assert_eq!(&sent[1][4..8], [PING_HEADER, 0x32, 0x32, 0x32]);
+    assert_eq!(&sent[1][16..], [0x11, 0x21, 0xFF, 0xFF]);
+
+    // the scripts are over, so the players hang up
+    assert_eq!(hub.step().received, [None; PLAYERS]);
+    assert_eq!(hub.players(), [false; PLAYERS]);
+}
+
+#[test]
+fn stalled_players() {
+    use super::*;
+    use std::net::TcpListener;
+
+    let listener = BgbListener::wrap(TcpListener::bind("127.0.0.1:0").unwrap());
+    let addr = listener.local_addr().unwrap();
+
+    // a player that never answers is dropped instead of holding up the step
+    let silent = std::thread::spawn(move || {
+        let mut stream = BgbStream::connect(addr).unwrap();
+        while stream.read().is_ok() {}
+    });
+    let mut hub = FourPlayerHub::new();
+    hub.set_player_timeout(Duration::from_millis(100));
+    hub.add_player(listener.accept().unwrap().0);
+    assert_eq!(hub.step().received, [None; PLAYERS]);
+    assert_eq!(hub.players(), [false; PLAYERS]);
+    drop(hub);
+    silent.join().unwrap();
+
+    // nor does a client that never sends its half of the handshake stop others from joining
+    std::thread::spawn(move || {
+        let mut hub = FourPlayerHub::new();
+        hub.set_player_timeout(Duration::from_millis(100));
+        hub.run(&listener).unwrap();
+    });
+    let _mute = std::net::TcpStream::connect(addr).unwrap();
+    let mut stream = BgbStream::connect(addr).unwrap();
+    assert!(matches!(
+        stream.read().unwrap(),
+        TypedBgbCommand::Status { running: true, .. }
+    ));
+}
@@ -1,2 +1,9 @@
+pub mod bridge;
 pub mod commands;
+pub mod conformance;
+pub mod hub;
+pub mod lockstep;
 pub mod net;
+pub mod netplay;
+pub mod peripheral;
+pub mod transfer;
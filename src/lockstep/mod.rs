@@ -0,0 +1,694 @@
+mod tests;
+
+use crate::commands::*;
+use crate::net::stream::BgbStream;
+use std::io;
+use std::net::TcpStream;
+
+/// The number of emulated clock cycles per BGB timestamp unit.
+///
+/// Cycles are counted at the normal-speed rate of 4 MiHz (2^22 Hz), even while a Game Boy Color
+/// is in double speed mode, and timestamps at 2 MiHz.
+pub const CYCLES_PER_TIMESTAMP: u64 = 2;
+
+/// The number of cycles in one frame, which is how far `LinkSync` lets an emulator run ahead
+/// by default.
+pub const CYCLES_PER_FRAME: u64 = 70224;
+
+const TIMESTAMP_MASK: u32 = 0x7FFF_FFFF;
+
+/// The version of the format written by `LinkState::encode`.
+pub const STATE_VERSION: u8 = 1;
+
+const STATE_MAGIC: &[u8; 4] = b"BGBL";
+const STATE_LENGTH: usize = 64;
+
+const STATE_MASTER: u8 = 1 << 0;
+const STATE_MASTER_RECEIVED: u8 = 1 << 1;
+const STATE_SLAVE: u8 = 1 << 2;
+const STATE_SLAVE_READY: u8 = 1 << 3;
+const STATE_PEER_STATUS: u8 = 1 << 4;
+const STATE_REMOTE: u8 = 1 << 5;
+const STATE_ROLE_MASTER: u8 = 1 << 6;
+const STATE_ROLE_SLAVE: u8 = 1 << 7;
+
+/// What the emulator should do next, as returned by `LinkSync::update`.
+///
+/// More actions may be added, so matches on this enum need a wildcard arm.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum SyncAction {
+    /// The emulator may keep running until its cycle count reaches `until`, then it must call
+    /// `update` again. It may also call `update` earlier.
+    Run { until: u64 },
+    /// The emulator has gotten as far ahead of the peer as it is allowed to and must not run
+    /// until `update` says so. `wait` blocks until there is news from the peer.
+    Stall,
+    /// A serial transfer finished at the current cycle. The emulator should store `received`
+    /// in SB, clear bit 7 of SC and request the serial interrupt, then call `update` again.
+    TransferComplete { received: u8 },
+    /// The peer has disconnected.
+    Disconnected,
+    /// The side driving the clock has changed without the emulator asking for it, because
+    /// the peer started a transfer after this side had been the master. Call `update` again
+    /// to carry on.
+    RoleChanged { role: LinkRole },
+}
+
+/// Which side of the link drove the clock for the most recent transfer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkRole {
+    /// This side's game started the transfer with its internal clock.
+    Master,
+    /// The peer started the transfer, and this side's game followed its clock.
+    Slave,
+}
+
+/// The last status the peer reported.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PeerStatus {
+    pub running: bool,
+    pub paused: bool,
+    pub support_reconnect: bool,
+}
+
+/// A transfer this side started with its internal clock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct MasterTransfer {
+    data: u8,
+    high_speed: bool,
+    double_speed: bool,
+    complete_at: u64,
+    received: Option<u8>,
+}
+
+impl MasterTransfer {
+    /// Returns the timestamp the transfer was started at.
+    fn timestamp(&self) -> u32 {
+        timestamp(self.complete_at - transfer_cycles(self.high_speed, self.double_speed))
+    }
+}
+
+/// A transfer the peer started, which completes here once this side has caught up to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct SlaveTransfer {
+    at: u64,
+    data: u8,
+}
+
+/// Maps the peer's timestamps onto this side's cycle count.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct RemoteClock {
+    timestamp: u32,
+    cycles: u64,
+}
+
+/// Keeps an emulator within a bounded number of cycles of a linked BGB peer and carries out
+/// serial transfers with it, following the timing rules of the BGB 1.4 link protocol.
+///
+/// The emulator calls `update` with its current cycle count at least as often as it is told
+/// to, and calls `start_transfer` and `set_slave_data` when the game writes to the serial
+/// registers. The peer's clock is aligned to this side's when its first timestamp arrives.
+///
+/// A transfer started here completes once both the peer has responded and the 8 bits have had
+/// time to shift out. A transfer started by the peer completes once this side reaches the
+/// peer's timestamp for it, and is answered with the byte set by `set_slave_data`, or with a
+/// `Sync3Response` if the game wasn't waiting for a transfer.
+///
+/// Both sides may claim the clock at once, which shows up as the peer's `Sync1` arriving
+/// while this side's transfer is unanswered, or as this side starting a transfer before
+/// reaching the timestamp of one the peer started. BGB only answers a `Sync1` with a `Sync2`
+/// when the game is waiting for the external clock, and a game driving its own transfer
+/// isn't, so each side refuses the other's `Sync1` with a `Sync3Response` and both transfers
+/// complete with `0xFF`, just as when the peer's game isn't listening.
+#[derive(Debug)]
+pub struct LinkSync {
+    stream: BgbStream<TcpStream>,
+    max_lead: u64,
+    heartbeat_interval: u64,
+    cycles: u64,
+    last_sent: Option<u64>,
+    remote: Option<RemoteClock>,
+    master: Option<MasterTransfer>,
+    slave: Option<SlaveTransfer>,
+    slave_data: u8,
+    slave_ready: bool,
+    peer_status: Option<PeerStatus>,
+    role: Option<LinkRole>,
+    role_changed: bool,
+    refusals: u32,
+    disconnected: bool,
+}
+
+impl LinkSync {
+    /// Starts synchronizing over a connection that has completed its handshake, and reports
+    /// this side as running.
+    pub fn new(stream: BgbStream<TcpStream>) -> io::Result<LinkSync> {
+        let mut sync = LinkSync {
+            stream,
+            max_lead: CYCLES_PER_FRAME,
+            heartbeat_interval: CYCLES_PER_FRAME / 4,
+            cycles: 0,
+            last_sent: None,
+            remote: None,
+            master: None,
+            slave: None,
+            slave_data: 0xFF,
+            slave_ready: false,
+            peer_status: None,
+            role: None,
+            role_changed: false,
+            refusals: 0,
+            disconnected: false,
+        };
+        sync.set_status(false)?;
+        Ok(sync)
+    }
+
+    /// Sets how many cycles this side may run ahead of the peer's last timestamp, and how
+    /// often timestamps are sent when no transfers are happening.
+    ///
+    /// `heartbeat_interval` should be well below `max_lead`, or both sides will spend time
+    /// stalled waiting for each other.
+    pub fn set_limits(&mut self, max_lead: u64, heartbeat_interval: u64) {
+        self.max_lead = max_lead;
+        self.heartbeat_interval = heartbeat_interval.max(1);
+    }
+
+    /// Reports to the peer whether this side is paused.
+    pub fn set_status(&mut self, paused: bool) -> io::Result<()> {
+        self.stream.write(&TypedBgbCommand::Status {
+            running: true,
+            paused,
+            support_reconnect: false,
+        })
+    }
+
+    /// Returns the last status the peer reported, if any.
+    pub fn peer_status(&self) -> Option<PeerStatus> {
+        self.peer_status
+    }
+
+    /// Returns which side drove the clock for the most recent transfer, or `None` if there
+    /// hasn't been one yet.
+    pub fn role(&self) -> Option<LinkRole> {
+        self.role
+    }
+
+    /// Returns the cycle count passed to the last call to `update`.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Gets a mutable reference to the underlying stream.
+    ///
+    /// Reading from it directly will lose packets that `LinkSync` needs to see.
+    pub fn stream_mut(&mut self) -> &mut BgbStream<TcpStream> {
+        &mut self.stream
+    }
+
+    /// Sets what this side shifts out when the peer starts a transfer (the contents of SB),
+    /// and whether the game is waiting for one (bit 7 of SC with the external clock selected).
+    ///
+    /// `ready` is cleared again when a transfer completes.
+    pub fn set_slave_data(&mut self, data: u8, ready: bool) {
+        self.slave_data = data;
+        self.slave_ready = ready;
+    }
+
+    /// Starts a transfer driven by this side's clock at the cycle count last passed to
+    /// `update`, as when the game writes to SC with bits 7 and 0 set.
+    ///
+    /// `high_speed` and `double_speed` are SC bit 1 and the CPU speed on a Game Boy Color,
+    /// and determine how long the transfer takes.
+    ///
+    /// If the peer has started a transfer that this side hasn't reached yet, both sides are
+    /// claiming the clock, so the peer's transfer is refused and this one completes with
+    /// `0xFF` once the peer refuses it in turn.
+    pub fn start_transfer(
+        &mut self,
+        data: u8,
+        high_speed: bool,
+        double_speed: bool,
+    ) -> io::Result<()> {
+        self.stream.write(&TypedBgbCommand::Sync1 {
+            data,
+            high_speed,
+            double_speed,
+            timestamp: timestamp(self.cycles),
+        })?;
+        self.last_sent = Some(self.cycles);
+        self.master = Some(MasterTransfer {
+            data,
+            high_speed,
+            double_speed,
+            complete_at: self.cycles + transfer_cycles(high_speed, double_speed),
+            received: None,
+        });
+        if self.slave.take().is_some() {
+            // the game stopped waiting for the peer's clock before the peer's transfer was due
+            self.stream.write(&TypedBgbCommand::Sync3Response)?;
+            self.role_changed = false;
+        }
+        self.role = Some(LinkRole::Master);
+        Ok(())
+    }
+
+    /// Handles any packets that have arrived and works out what the emulator should do, now
+    /// that it has reached the given cycle count.
+    pub fn update(&mut self, cycles: u64) -> io::Result<SyncAction> {
+        self.cycles = self.cycles.max(cycles);
+        self.receive_available()?;
+        if self.disconnected {
+            return Ok(SyncAction::Disconnected);
+        }
+        while self.refusals > 0 {
+            self.stream.write(&TypedBgbCommand::Sync3Response)?;
+            self.refusals -= 1;
+        }
+        if self.role_changed {
+            self.role_changed = false;
+            if let Some(role) = self.role {
+                return Ok(SyncAction::RoleChanged { role });
+            }
+        }
+
+        if let Some(SlaveTransfer { at, data }) = self.slave {
+            if self.cycles >= at {
+                self.slave = None;
+                if self.slave_ready {
+                    self.slave_ready = false;
+                    self.stream.write(&TypedBgbCommand::Sync2 {
+                        data: self.slave_data,
+                    })?;
+                    return Ok(SyncAction::TransferComplete { received: data });
+                } else {
+                    self.stream.write(&TypedBgbCommand::Sync3Response)?;
+                }
+            }
+        }
+
+        if let Some(MasterTransfer {
+            complete_at,
+            received,
+            ..
+        }) = self.master
+        {
+            if self.cycles >= complete_at {
+                if let Some(received) = received {
+                    self.master = None;
+                    return Ok(SyncAction::TransferComplete { received });
+                }
+                self.send_timestamp()?;
+                return Ok(SyncAction::Stall);
+            }
+        }
+
+        if self
+            .last_sent
+            .is_none_or(|sent| self.cycles >= sent + self.heartbeat_interval)
+        {
+            self.send_timestamp()?;
+        }
+
+        let limit = match self.remote {
+            Some(ref remote) => remote.cycles + self.max_lead,
+            None => self.cycles,
+        };
+        if self.cycles >= limit {
+            self.send_timestamp()?;
+            return Ok(SyncAction::Stall);
+        }
+        let mut until = limit;
+        if let Some(ref slave) = self.slave {
+            until = until.min(slave.at);
+        }
+        if let Some(ref master) = self.master {
+            until = until.min(master.complete_at);
+        }
+        Ok(SyncAction::Run { until })
+    }
+
+    /// Blocks until a packet arrives from the peer and handles it. Meant to be called when
+    /// `update` returns `Stall`.
+    pub fn wait(&mut self) -> io::Result<()> {
+        match self.stream.read() {
+            Ok(command) => {
+                self.receive(&command);
+                Ok(())
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                self.disconnected = true;
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Captures everything `LinkSync` knows about the link apart from the connection itself,
+    /// for an emulator to keep in its save states.
+    pub fn save_state(&self) -> LinkState {
+        LinkState {
+            max_lead: self.max_lead,
+            heartbeat_interval: self.heartbeat_interval,
+            cycles: self.cycles,
+            remote: self.remote,
+            master: self.master,
+            slave: self.slave,
+            slave_data: self.slave_data,
+            slave_ready: self.slave_ready,
+            peer_status: self.peer_status,
+            role: self.role,
+        }
+    }
+
+    /// Returns to a state captured by `save_state`, possibly on a different connection, and
+    /// brings the peer up to date.
+    ///
+    /// The peer is sent this side's status and timestamp, and a transfer started here that
+    /// the peer hadn't answered yet is started again, since any answer it sent after the
+    /// state was saved has been lost. The peer kept running while this side went back in
+    /// time, so this side is allowed to run until it catches up.
+    pub fn load_state(&mut self, state: &LinkState) -> io::Result<()> {
+        self.max_lead = state.max_lead;
+        self.heartbeat_interval = state.heartbeat_interval;
+        self.cycles = state.cycles;
+        self.remote = state.remote;
+        self.master = state.master;
+        self.slave = state.slave;
+        self.slave_data = state.slave_data;
+        self.slave_ready = state.slave_ready;
+        self.peer_status = state.peer_status;
+        self.role = state.role;
+        self.role_changed = false;
+        self.refusals = 0;
+        self.disconnected = false;
+        self.last_sent = None;
+
+        self.set_status(false)?;
+        match self.master {
+            Some(master) if master.received.is_none() => {
+                self.stream.write(&TypedBgbCommand::Sync1 {
+                    data: master.data,
+                    high_speed: master.high_speed,
+                    double_speed: master.double_speed,
+                    timestamp: master.timestamp(),
+                })?;
+                self.last_sent = Some(self.cycles);
+                Ok(())
+            }
+            _ => self.send_timestamp(),
+        }
+    }
+
+    /// Tells the peer that this side is disconnecting.
+    pub fn disconnect(&mut self) -> io::Result<()> {
+        self.stream.write(&TypedBgbCommand::WantDisconnect)
+    }
+
+    fn receive_available(&mut self) -> io::Result<()> {
+        self.stream.get_ref().set_nonblocking(true)?;
+        let result = loop {
+            match self.stream.maybe_read() {
+                Ok(Some(command)) => self.receive(&command),
+                Ok(None) => {
+                    // either half a packet has arrived, or the peer has hung up
+                    if let Ok(0) = self.stream.get_ref().peek(&mut [0u8]) {
+                        self.disconnected = true;
+                    }
+                    break Ok(());
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        self.stream.get_ref().set_nonblocking(false)?;
+        result
+    }
+
+    fn receive(&mut self, command: &TypedBgbCommand) {
+        match *command {
+            TypedBgbCommand::Sync1 {
+                data, timestamp, ..
+            } => {
+                let at = self.remote_cycles(timestamp);
+                // both sides claimed the clock, and this side's game is driving its own
+                // transfer rather than waiting for the peer's
+                if self.master.is_some_and(|master| master.received.is_none()) {
+                    self.refusals += 1;
+                    return;
+                }
+                self.slave = Some(SlaveTransfer { at, data });
+                self.change_role(LinkRole::Slave);
+            }
+            TypedBgbCommand::Sync2 { data } => {
+                if let Some(ref mut master) = self.master {
+                    master.received = Some(data);
+                }
+            }
+            // the peer wasn't ready, so nothing was shifted in
+            TypedBgbCommand::Sync3Response => {
+                if let Some(ref mut master) = self.master {
+                    master.received = Some(0xFF);
+                }
+            }
+            TypedBgbCommand::Sync3Timestamp { timestamp } => {
+                self.remote_cycles(timestamp);
+            }
+            TypedBgbCommand::Status {
+                running,
+                paused,
+                support_reconnect,
+            } => {
+                self.peer_status = Some(PeerStatus {
+                    running,
+                    paused,
+                    support_reconnect,
+                })
+            }
+            TypedBgbCommand::WantDisconnect => self.disconnected = true,
+            TypedBgbCommand::Version { .. }
+            | TypedBgbCommand::Joypad { .. }
+            | TypedBgbCommand::ExtensionOffer { .. }
+            | TypedBgbCommand::Infrared { .. }
+            | TypedBgbCommand::StateHash { .. } => {}
+        }
+    }
+
+    /// Switches to the given role, noting that `update` should report it if this side had
+    /// been playing the other one.
+    fn change_role(&mut self, role: LinkRole) {
+        if self.role.is_some_and(|old| old != role) {
+            self.role_changed = true;
+        }
+        self.role = Some(role);
+    }
+
+    /// Records a timestamp from the peer, returning the corresponding local cycle count.
+    fn remote_cycles(&mut self, timestamp: u32) -> u64 {
+        let cycles = self.cycles;
+        let remote = self.remote.get_or_insert(RemoteClock { timestamp, cycles });
+        let elapsed = timestamp.wrapping_sub(remote.timestamp) & TIMESTAMP_MASK;
+        // timestamps from before the last one are treated as if they were the same
+        if elapsed < 1 << 30 {
+            remote.timestamp = timestamp;
+            remote.cycles += u64::from(elapsed) * CYCLES_PER_TIMESTAMP;
+        }
+        remote.cycles
+    }
+
+    fn send_timestamp(&mut self) -> io::Result<()> {
+        if self.last_sent == Some(self.cycles) {
+            return Ok(());
+        }
+        self.last_sent = Some(self.cycles);
+        self.stream.write(&TypedBgbCommand::Sync3Timestamp {
+            timestamp: timestamp(self.cycles),
+        })
+    }
+}
+
+/// The state of a `LinkSync`, as saved by `LinkSync::save_state`.
+///
+/// This covers the emulator's cycle count, the peer's last timestamp, a transfer in either
+/// direction that hasn't completed yet, the byte waiting in SB for the peer, and the peer's
+/// last status. Whether a master or a slave transfer is pending tells which side is driving
+/// the clock, and the role of the most recent transfer is kept as well.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LinkState {
+    max_lead: u64,
+    heartbeat_interval: u64,
+    cycles: u64,
+    remote: Option<RemoteClock>,
+    master: Option<MasterTransfer>,
+    slave: Option<SlaveTransfer>,
+    slave_data: u8,
+    slave_ready: bool,
+    peer_status: Option<PeerStatus>,
+    role: Option<LinkRole>,
+}
+
+impl LinkState {
+    /// Returns the cycle count the state was saved at.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Serializes the state as a 64-byte blob, starting with `BGBL` and `STATE_VERSION`.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut flags = 0;
+        let master = self.master.unwrap_or(MasterTransfer {
+            data: 0,
+            high_speed: false,
+            double_speed: false,
+            complete_at: 0,
+            received: None,
+        });
+        let slave = self.slave.unwrap_or(SlaveTransfer { at: 0, data: 0 });
+        let remote = self.remote.unwrap_or(RemoteClock {
+            timestamp: 0,
+            cycles: 0,
+        });
+        let peer_status = self.peer_status.unwrap_or_default();
+        for &(set, flag) in &[
+            (self.master.is_some(), STATE_MASTER),
+            (master.received.is_some(), STATE_MASTER_RECEIVED),
+            (self.slave.is_some(), STATE_SLAVE),
+            (self.slave_ready, STATE_SLAVE_READY),
+            (self.peer_status.is_some(), STATE_PEER_STATUS),
+            (self.remote.is_some(), STATE_REMOTE),
+            (self.role == Some(LinkRole::Master), STATE_ROLE_MASTER),
+            (self.role == Some(LinkRole::Slave), STATE_ROLE_SLAVE),
+        ] {
+            if set {
+                flags |= flag;
+            }
+        }
+
+        let mut blob = Vec::with_capacity(STATE_LENGTH);
+        blob.extend_from_slice(STATE_MAGIC);
+        blob.push(STATE_VERSION);
+        blob.push(flags);
+        blob.extend_from_slice(&self.cycles.to_le_bytes());
+        blob.extend_from_slice(&self.max_lead.to_le_bytes());
+        blob.extend_from_slice(&self.heartbeat_interval.to_le_bytes());
+        blob.extend_from_slice(&master.complete_at.to_le_bytes());
+        blob.push(master.data);
+        blob.push(u8::from(master.high_speed) | u8::from(master.double_speed) << 1);
+        blob.push(master.received.unwrap_or(0));
+        blob.extend_from_slice(&slave.at.to_le_bytes());
+        blob.push(slave.data);
+        blob.push(self.slave_data);
+        blob.push(
+            u8::from(peer_status.running)
+                | u8::from(peer_status.paused) << 1
+                | u8::from(peer_status.support_reconnect) << 2,
+        );
+        blob.extend_from_slice(&remote.timestamp.to_le_bytes());
+        blob.extend_from_slice(&remote.cycles.to_le_bytes());
+        blob
+    }
+
+    /// Parses a blob written by `encode`.
+    ///
+    /// Returns an error of kind `InvalidData` if the blob is malformed or was written by an
+    /// incompatible version.
+    pub fn decode(blob: &[u8]) -> io::Result<LinkState> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        if blob.len() < 5 || &blob[..4] != STATE_MAGIC {
+            return Err(invalid(String::from("not a link state")));
+        }
+        if blob[4] != STATE_VERSION {
+            return Err(invalid(format!(
+                "link state version {} is not supported, expected {}",
+                blob[4], STATE_VERSION
+            )));
+        }
+        if blob.len() != STATE_LENGTH {
+            return Err(invalid(format!(
+                "link state is {} bytes long, expected {}",
+                blob.len(),
+                STATE_LENGTH
+            )));
+        }
+
+        let flags = blob[5];
+        let u64_at = |i: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&blob[i..i + 8]);
+            u64::from_le_bytes(bytes)
+        };
+        let has = |flag: u8| flags & flag != 0;
+        Ok(LinkState {
+            cycles: u64_at(6),
+            max_lead: u64_at(14),
+            heartbeat_interval: u64_at(22).max(1),
+            master: if has(STATE_MASTER) {
+                Some(MasterTransfer {
+                    complete_at: u64_at(30),
+                    data: blob[38],
+                    high_speed: blob[39] & 1 != 0,
+                    double_speed: blob[39] & 2 != 0,
+                    received: if has(STATE_MASTER_RECEIVED) {
+                        Some(blob[40])
+                    } else {
+                        None
+                    },
+                })
+            } else {
+                None
+            },
+            slave: if has(STATE_SLAVE) {
+                Some(SlaveTransfer {
+                    at: u64_at(41),
+                    data: blob[49],
+                })
+            } else {
+                None
+            },
+            slave_data: blob[50],
+            slave_ready: has(STATE_SLAVE_READY),
+            peer_status: if has(STATE_PEER_STATUS) {
+                Some(PeerStatus {
+                    running: blob[51] & 1 != 0,
+                    paused: blob[51] & 2 != 0,
+                    support_reconnect: blob[51] & 4 != 0,
+                })
+            } else {
+                None
+            },
+            remote: if has(STATE_REMOTE) {
+                Some(RemoteClock {
+                    timestamp: u32::from_le_bytes([blob[52], blob[53], blob[54], blob[55]]),
+                    cycles: u64_at(56),
+                })
+            } else {
+                None
+            },
+            role: if has(STATE_ROLE_MASTER) {
+                Some(LinkRole::Master)
+            } else if has(STATE_ROLE_SLAVE) {
+                Some(LinkRole::Slave)
+            } else {
+                None
+            },
+        })
+    }
+}
+
+/// Returns how many cycles a transfer driven by the internal clock takes.
+fn transfer_cycles(high_speed: bool, double_speed: bool) -> u64 {
+    let mut duration = 8 * 512;
+    if high_speed {
+        duration /= 32;
+    }
+    if double_speed {
+        duration /= 2;
+    }
+    duration
+}
+
+/// Converts a cycle count to a BGB timestamp.
+pub fn timestamp(cycles: u64) -> u32 {
+    (cycles / CYCLES_PER_TIMESTAMP) as u32 & TIMESTAMP_MASK
+}
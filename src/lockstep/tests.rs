@@ -0,0 +1,281 @@
+/// Runs an emulator that starts a transfer of `send` at cycle `transfer_at` (if it's the
+/// master) and returns every byte it received.
+#[cfg(test)]
+fn emulate(mut sync: super::LinkSync, send: u8, transfer_at: Option<u64>, end: u64) -> Vec<u8> {
+    use super::*;
+
+    let mut received = Vec::new();
+    let mut cycles = 0;
+    sync.set_slave_data(send, transfer_at.is_none());
+    while cycles < end {
+        if transfer_at == Some(cycles) {
+            sync.start_transfer(send, false, false).unwrap();
+        }
+        match sync.update(cycles).unwrap() {
+            SyncAction::Run { until } => {
+                let mut next = (cycles + 456).min(until);
+                if let Some(at) = transfer_at {
+                    if cycles < at {
+                        next = next.min(at);
+                    }
+                }
+                cycles = next;
+            }
+            SyncAction::Stall => sync.wait().unwrap(),
+            SyncAction::TransferComplete { received: byte } => received.push(byte),
+            SyncAction::Disconnected => return received,
+            SyncAction::RoleChanged { .. } => {}
+        }
+    }
+    sync.disconnect().unwrap();
+    received
+}
+
+#[test]
+fn lockstep_transfer() {
+    use super::*;
+    use crate::net::tests::connected_pair;
+
+    let (a, b) = connected_pair();
+    let master = LinkSync::new(a).unwrap();
+    let slave = LinkSync::new(b).unwrap();
+    let slave = std::thread::spawn(move || emulate(slave, 0x55, None, 4 * CYCLES_PER_FRAME));
+    assert_eq!(
+        emulate(master, 0xAA, Some(10032), 4 * CYCLES_PER_FRAME),
+        vec![0x55]
+    );
+    assert_eq!(slave.join().unwrap(), vec![0xAA]);
+}
+
+#[test]
+fn stalls_when_ahead() {
+    use super::*;
+    use crate::net::tests::connected_pair;
+
+    let (mut peer, stream) = connected_pair();
+    let mut sync = LinkSync::new(stream).unwrap();
+    sync.set_limits(1000, 100);
+    assert_eq!(
+        peer.read().unwrap(),
+        TypedBgbCommand::Status {
+            running: true,
+            paused: false,
+            support_reconnect: false,
+        }
+    );
+
+    // nothing has been heard from the peer yet
+    assert_eq!(sync.update(0).unwrap(), SyncAction::Stall);
+    assert_eq!(
+        peer.read().unwrap(),
+        TypedBgbCommand::Sync3Timestamp { timestamp: 0 }
+    );
+
+    peer.write(&TypedBgbCommand::Sync3Timestamp { timestamp: 5000 })
+        .unwrap();
+    sync.wait().unwrap();
+    assert_eq!(sync.update(0).unwrap(), SyncAction::Run { until: 1000 });
+    assert_eq!(sync.update(1000).unwrap(), SyncAction::Stall);
+    assert_eq!(
+        peer.read().unwrap(),
+        TypedBgbCommand::Sync3Timestamp { timestamp: 500 }
+    );
+
+    peer.write(&TypedBgbCommand::Sync3Timestamp { timestamp: 5250 })
+        .unwrap();
+    sync.wait().unwrap();
+    assert_eq!(sync.update(1000).unwrap(), SyncAction::Run { until: 1500 });
+
+    // a transfer from the peer completes when this side reaches its timestamp
+    sync.set_slave_data(0x12, true);
+    peer.write(&TypedBgbCommand::Sync1 {
+        data: 0x34,
+        high_speed: false,
+        double_speed: false,
+        timestamp: 5800,
+    })
+    .unwrap();
+    sync.wait().unwrap();
+    assert_eq!(sync.update(1100).unwrap(), SyncAction::Run { until: 1600 });
+    assert_eq!(
+        peer.read().unwrap(),
+        TypedBgbCommand::Sync3Timestamp { timestamp: 550 }
+    );
+    assert_eq!(
+        sync.update(1600).unwrap(),
+        SyncAction::TransferComplete { received: 0x34 }
+    );
+    assert_eq!(peer.read().unwrap(), TypedBgbCommand::Sync2 { data: 0x12 });
+}
+
+#[test]
+fn save_and_load_state() {
+    use super::*;
+    use crate::net::tests::connected_pair;
+
+    let (mut peer, stream) = connected_pair();
+    let mut sync = LinkSync::new(stream).unwrap();
+    peer.read().unwrap();
+    peer.write(&TypedBgbCommand::Status {
+        running: true,
+        paused: true,
+        support_reconnect: false,
+    })
+    .unwrap();
+    peer.write(&TypedBgbCommand::Sync3Timestamp { timestamp: 100 })
+        .unwrap();
+    sync.wait().unwrap();
+    sync.wait().unwrap();
+    sync.update(2000).unwrap();
+    assert_eq!(
+        peer.read().unwrap(),
+        TypedBgbCommand::Sync3Timestamp { timestamp: 1000 }
+    );
+
+    // the state is saved while a transfer is waiting for the peer's answer
+    sync.set_slave_data(0x42, false);
+    sync.start_transfer(0x99, true, false).unwrap();
+    let state = sync.save_state();
+    let blob = state.encode();
+    assert_eq!(blob.len(), 64);
+    assert_eq!(&blob[..5], b"BGBL\x01");
+    assert_eq!(LinkState::decode(&blob).unwrap(), state);
+    assert_eq!(state.cycles(), 2000);
+
+    let mut newer = blob.clone();
+    newer[4] = STATE_VERSION + 1;
+    assert_eq!(
+        LinkState::decode(&newer).unwrap_err().kind(),
+        io::ErrorKind::InvalidData
+    );
+    assert!(LinkState::decode(&blob[..40]).is_err());
+
+    // the answer arrives after the save, so it's lost when the state is loaded
+    assert!(matches!(
+        peer.read().unwrap(),
+        TypedBgbCommand::Sync1 { data: 0x99, .. }
+    ));
+    peer.write(&TypedBgbCommand::Sync2 { data: 0x11 }).unwrap();
+    sync.wait().unwrap();
+    sync.update(2500).unwrap();
+    sync.load_state(&LinkState::decode(&blob).unwrap()).unwrap();
+    assert_eq!(sync.cycles(), 2000);
+    assert_eq!(sync.peer_status().map(|status| status.paused), Some(true));
+    assert!(matches!(
+        peer.read().unwrap(),
+        TypedBgbCommand::Status { .. }
+    ));
+    assert_eq!(
+        peer.read().unwrap(),
+        TypedBgbCommand::Sync1 {
+            data: 0x99,
+            high_speed: true,
+            double_speed: false,
+            timestamp: 1000,
+        }
+    );
+    peer.write(&TypedBgbCommand::Sync2 { data: 0x22 }).unwrap();
+    sync.wait().unwrap();
+    assert_eq!(
+        sync.update(2000 + 8 * 512 / 32).unwrap(),
+        SyncAction::TransferComplete { received: 0x22 }
+    );
+}
+
+#[test]
+fn master_conflicts() {
+    use super::*;
+    use crate::net::tests::connected_pair;
+
+    let (mut peer, stream) = connected_pair();
+    let mut sync = LinkSync::new(stream).unwrap();
+    sync.set_limits(100_000, 50_000);
+    peer.read().unwrap();
+    peer.write(&TypedBgbCommand::Sync3Timestamp { timestamp: 0 })
+        .unwrap();
+    sync.wait().unwrap();
+    sync.update(0).unwrap();
+    peer.read().unwrap();
+    assert_eq!(sync.role(), None);
+
+    // the peer claimed the clock while this side's transfer was unanswered, so each side
+    // refuses the other's transfer
+    sync.update(1000).unwrap();
+    sync.start_transfer(0xAA, false, false).unwrap();
+    assert!(matches!(
+        peer.read().unwrap(),
+        TypedBgbCommand::Sync1 {
+            data: 0xAA,
+            timestamp: 500,
+            ..
+        }
+    ));
+    peer.write(&TypedBgbCommand::Sync1 {
+        data: 0x55,
+        high_speed: false,
+        double_speed: false,
+        timestamp: 400,
+    })
+    .unwrap();
+    sync.wait().unwrap();
+    assert_eq!(
+        sync.update(1000).unwrap(),
+        SyncAction::Run { until: 1000 + 4096 }
+    );
+    assert_eq!(peer.read().unwrap(), TypedBgbCommand::Sync3Response);
+    peer.write(&TypedBgbCommand::Sync3Response).unwrap();
+    sync.wait().unwrap();
+    assert_eq!(
+        sync.update(1000 + 4096).unwrap(),
+        SyncAction::TransferComplete { received: 0xFF }
+    );
+    assert_eq!(sync.role(), Some(LinkRole::Master));
+
+    // the peer started a transfer this side hasn't reached yet
+    peer.write(&TypedBgbCommand::Sync1 {
+        data: 0x33,
+        high_speed: false,
+        double_speed: false,
+        timestamp: 20_000,
+    })
+    .unwrap();
+    sync.wait().unwrap();
+    assert_eq!(
+        sync.update(10_000).unwrap(),
+        SyncAction::RoleChanged {
+            role: LinkRole::Slave
+        }
+    );
+    assert_eq!(
+        sync.update(10_000).unwrap(),
+        SyncAction::Run { until: 40_000 }
+    );
+
+    // so claiming the clock before then refuses it, and this side keeps the role it asked for
+    sync.start_transfer(0x44, false, false).unwrap();
+    assert!(matches!(
+        peer.read().unwrap(),
+        TypedBgbCommand::Sync1 {
+            data: 0x44,
+            timestamp: 5000,
+            ..
+        }
+    ));
+    assert_eq!(peer.read().unwrap(), TypedBgbCommand::Sync3Response);
+    assert_eq!(
+        sync.update(10_000).unwrap(),
+        SyncAction::Run {
+            until: 10_000 + 4096
+        }
+    );
+    assert_eq!(sync.role(), Some(LinkRole::Master));
+    let state = LinkState::decode(&sync.save_state().encode()).unwrap();
+    assert_eq!(state, sync.save_state());
+
+    peer.write(&TypedBgbCommand::Sync3Response).unwrap();
+    sync.wait().unwrap();
+    assert_eq!(
+        sync.update(10_000 + 4096).unwrap(),
+        SyncAction::TransferComplete { received: 0xFF }
+    );
+}
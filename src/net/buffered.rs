@@ -0,0 +1,170 @@
+use super::stream::BgbStream;
+use crate::commands::*;
+use std::io;
+use std::io::{BufRead, Read, Write};
+
+const DEFAULT_CAPACITY: usize = 8 * 1024;
+
+/// Buffers both directions of a read/writer, so that many packets can be moved with a single
+/// system call.
+///
+/// Unlike `BufWriter`, nothing is written until the buffer fills up or `flush` is called, so
+/// a `BgbStream` over this transport must be flushed after every batch of packets that the
+/// other party is waiting on. Buffered data is flushed on drop, ignoring any errors.
+#[derive(Debug)]
+pub struct BufferedTransport<T: Read + Write> {
+    inner: T,
+    read_buf: Box<[u8]>,
+    read_pos: usize,
+    read_len: usize,
+    write_buf: Vec<u8>,
+}
+
+impl<T: Read + Write> BufferedTransport<T> {
+    /// Wraps the read/writer with 8 KiB buffers in each direction.
+    pub fn new(inner: T) -> BufferedTransport<T> {
+        BufferedTransport::with_capacity(DEFAULT_CAPACITY, inner)
+    }
+
+    /// Wraps the read/writer with buffers of the given size in each direction.
+    ///
+    /// The capacity is rounded up to hold at least one packet.
+    pub fn with_capacity(capacity: usize, inner: T) -> BufferedTransport<T> {
+        let capacity = capacity.max(8);
+        BufferedTransport {
+            inner,
+            read_buf: vec![0; capacity].into_boxed_slice(),
+            read_pos: 0,
+            read_len: 0,
+            write_buf: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Gets a reference to the underlying read/writer.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the underlying read/writer.
+    ///
+    /// Reading or writing directly may skip over buffered data.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Returns the data that has been read from the underlying read/writer but not consumed.
+    pub fn buffer(&self) -> &[u8] {
+        &self.read_buf[self.read_pos..self.read_len]
+    }
+
+    /// Reads from the underlying read/writer until at least `min` bytes are buffered, keeping
+    /// any bytes that are already buffered.
+    ///
+    /// Returns an error of kind `UnexpectedEof` if the stream ends first.
+    fn fill_to(&mut self, min: usize) -> io::Result<()> {
+        if self.read_len - self.read_pos >= min {
+            return Ok(());
+        }
+        self.read_buf.copy_within(self.read_pos..self.read_len, 0);
+        self.read_len -= self.read_pos;
+        self.read_pos = 0;
+        while self.read_len < min {
+            match self.inner.read(&mut self.read_buf[self.read_len..]) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.read_len += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn flush_buf(&mut self) -> io::Result<()> {
+        let result = self.inner.write_all(&self.write_buf);
+        self.write_buf.clear();
+        result
+    }
+}
+
+impl<T: Read + Write> Read for BufferedTransport<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<T: Read + Write> BufRead for BufferedTransport<T> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.read_pos == self.read_len {
+            self.read_pos = 0;
+            self.read_len = self.inner.read(&mut self.read_buf)?;
+        }
+        Ok(self.buffer())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.read_pos = (self.read_pos + amt).min(self.read_len);
+    }
+}
+
+impl<T: Read + Write> Write for BufferedTransport<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.write_buf.len() + buf.len() > self.write_buf.capacity() {
+            self.flush_buf()?;
+        }
+        if buf.len() >= self.write_buf.capacity() {
+            self.inner.write(buf)
+        } else {
+            self.write_buf.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_buf()?;
+        self.inner.flush()
+    }
+}
+
+impl<T: Read + Write> Drop for BufferedTransport<T> {
+    fn drop(&mut self) {
+        let _ = self.flush_buf();
+    }
+}
+
+impl<T: Read + Write> BgbStream<BufferedTransport<T>> {
+    /// Wraps the read/writer in a `BufferedTransport` and uses it for communication.
+    ///
+    /// Remember to call `flush` after writing.
+    pub fn wrap_buffered(inner: T) -> BgbStream<BufferedTransport<T>> {
+        BgbStream::wrap(BufferedTransport::new(inner))
+    }
+
+    /// Reads every complete packet that has arrived, waiting for at least one if none are
+    /// buffered.
+    ///
+    /// Packets are decoded directly out of the read buffer in a single pass. If one of them is
+    /// too malformed to interpret, the packets before it are returned and it's left in the
+    /// buffer, so that the next call consumes it alone and returns an error of kind
+    /// `InvalidData`.
+    pub fn read_batch(&mut self) -> io::Result<Vec<TypedBgbCommand>> {
+        self.get_mut().fill_to(8)?;
+        let mut commands = Vec::with_capacity(self.get_ref().buffer().len() / 8);
+        while let Some(raw) = RawBgbCommand::decode_all(self.get_ref().buffer()).next() {
+            let decoded = TypedBgbCommand::from_raw(&raw);
+            if decoded.is_err() && !commands.is_empty() {
+                break;
+            }
+            self.get_mut().consume(8);
+            match self.receive_decoded(&raw, decoded) {
+                Some(Ok(command)) => commands.push(command),
+                Some(Err(e)) => return Err(e),
+                None => {}
+            }
+        }
+        Ok(commands)
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::VecDeque;
+
+/// How many hashes are kept from each side while waiting for the other side's hash for the
+/// same timestamp.
+const MAX_PENDING: usize = 256;
+
+/// Compares the state hashes sent by both sides of a link, as in `StateHash` packets.
+///
+/// Each side is expected to hash its state at the same timestamps, and hashes are compared
+/// once both sides' hashes for a timestamp are known. Hashes that are never matched are
+/// eventually forgotten. A `BgbStream` keeps one of these for the hashes it sends and
+/// receives.
+#[derive(Clone, Debug, Default)]
+pub struct DesyncDetector {
+    local: VecDeque<(u32, u16)>,
+    remote: VecDeque<(u32, u16)>,
+    first_mismatch: Option<u32>,
+}
+
+impl DesyncDetector {
+    /// Creates a detector that hasn't seen any hashes.
+    pub fn new() -> DesyncDetector {
+        DesyncDetector::default()
+    }
+
+    /// Records a hash of this side's state.
+    pub fn record_local(&mut self, timestamp: u32, hash: u16) {
+        let mismatch = Self::record(&mut self.local, &mut self.remote, timestamp, hash);
+        self.first_mismatch = self.first_mismatch.or(mismatch);
+    }
+
+    /// Records a hash of the other side's state.
+    pub fn record_remote(&mut self, timestamp: u32, hash: u16) {
+        let mismatch = Self::record(&mut self.remote, &mut self.local, timestamp, hash);
+        self.first_mismatch = self.first_mismatch.or(mismatch);
+    }
+
+    /// Returns the timestamp of the first pair of hashes that didn't match, if any have
+    /// differed yet.
+    pub fn first_mismatch(&self) -> Option<u32> {
+        self.first_mismatch
+    }
+
+    /// Matches a hash against the other side's pending hashes, returning its timestamp if
+    /// they differ.
+    fn record(
+        pending: &mut VecDeque<(u32, u16)>,
+        other: &mut VecDeque<(u32, u16)>,
+        timestamp: u32,
+        hash: u16,
+    ) -> Option<u32> {
+        match other.iter().position(|&(t, _)| t == timestamp) {
+            Some(i) => {
+                let (_, other_hash) = other.remove(i)?;
+                if other_hash == hash {
+                    None
+                } else {
+                    Some(timestamp)
+                }
+            }
+            None => {
+                if pending.len() == MAX_PENDING {
+                    pending.pop_front();
+                }
+                pending.push_back((timestamp, hash));
+                None
+            }
+        }
+    }
+}
+
+/// Hashes some emulated state, such as a Game Boy's RAM, down to the 16 bits that fit in a
+/// `StateHash` packet.
+///
+/// This is 32-bit FNV-1a folded in half. Any hash works as long as both sides use the same
+/// one.
+pub fn state_hash(data: &[u8]) -> u16 {
+    let hash = data.iter().fold(0x811C_9DC5u32, |hash, &byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    });
+    (hash >> 16) as u16 ^ hash as u16
+}
@@ -0,0 +1,107 @@
+use crate::commands::*;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// The rule that a peer's handshake packet broke.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandshakeRule {
+    /// The packet's command number isn't one that BGB defines.
+    KnownCommand,
+    /// The packet was a valid command, but not a version packet.
+    VersionFirst,
+    /// The version packet was for a protocol other than 1.4.
+    SupportedVersion,
+    /// The version packet was for protocol 1.4, but its `i1` field wasn't zero.
+    ZeroPadding,
+}
+
+/// The error returned when the other party's first packet isn't a valid version packet.
+///
+/// Handshake functions return this wrapped in an `io::Error` of kind `InvalidData`. Use
+/// `HandshakeError::from_io` to get it back out.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HandshakeError {
+    received: RawBgbCommand,
+    decoded: Option<TypedBgbCommand>,
+    rule: HandshakeRule,
+}
+
+impl HandshakeError {
+    /// Checks the packet that the other party sent at the start of the connection.
+    pub(crate) fn check(received: &RawBgbCommand) -> Result<(), HandshakeError> {
+        let decoded = TypedBgbCommand::from_raw(received).ok();
+        let RawBgbCommand { b1, b2, b3, b4, i1 } = *received;
+        let rule = match decoded {
+            None => HandshakeRule::KnownCommand,
+            Some(TypedBgbCommand::Version { valid: true }) => return Ok(()),
+            Some(TypedBgbCommand::Version { .. }) if (b1, b2, b3, b4) == (1, 1, 4, 0) => {
+                debug_assert_ne!(i1, 0);
+                HandshakeRule::ZeroPadding
+            }
+            Some(TypedBgbCommand::Version { .. }) => HandshakeRule::SupportedVersion,
+            Some(_) => HandshakeRule::VersionFirst,
+        };
+        Err(HandshakeError {
+            received: received.clone(),
+            decoded,
+            rule,
+        })
+    }
+
+    /// Finds the `HandshakeError` inside an error returned by a handshake function, if that's
+    /// why it failed.
+    pub fn from_io(error: &io::Error) -> Option<&HandshakeError> {
+        error.get_ref()?.downcast_ref()
+    }
+
+    /// Returns the packet that the other party sent.
+    pub fn received(&self) -> &RawBgbCommand {
+        &self.received
+    }
+
+    /// Returns the packet that the other party sent, interpreted as a command, or `None` if
+    /// it was too malformed to interpret.
+    pub fn decoded(&self) -> Option<&TypedBgbCommand> {
+        self.decoded.as_ref()
+    }
+
+    /// Returns the rule that the packet broke.
+    pub fn rule(&self) -> HandshakeRule {
+        self.rule
+    }
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let RawBgbCommand { b1, b2, b3, b4, i1 } = self.received;
+        match self.rule {
+            HandshakeRule::KnownCommand => {
+                write!(f, "peer sent unknown command {} instead of its version", b1)
+            }
+            HandshakeRule::VersionFirst => match &self.decoded {
+                Some(command) => write!(f, "peer sent {:?} instead of its version", command),
+                None => write!(f, "peer sent command {} instead of its version", b1),
+            },
+            HandshakeRule::SupportedVersion if b4 == 0 => {
+                write!(f, "peer speaks protocol {}.{}, expected 1.4", b2, b3)
+            }
+            HandshakeRule::SupportedVersion => {
+                write!(f, "peer speaks protocol {}.{}.{}, expected 1.4", b2, b3, b4)
+            }
+            HandshakeRule::ZeroPadding => write!(
+                f,
+                "peer's version packet has {:#x} in a field that should be zero",
+                i1
+            ),
+        }
+    }
+}
+
+impl Error for HandshakeError {}
+
+impl From<HandshakeError> for io::Error {
+    fn from(error: HandshakeError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
@@ -0,0 +1,250 @@
+use super::split::{BgbReader, BgbWriter};
+use super::stream::BgbStream;
+use crate::commands::*;
+use std::collections::VecDeque;
+use std::io;
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How many unanswered heartbeats are remembered for measuring round trips.
+const MAX_OUTSTANDING: usize = 16;
+
+/// Round-trip latency and clock drift measured by a `HeartbeatStream`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LatencyStats {
+    /// The number of round trips measured.
+    pub samples: u64,
+    /// The most recent round-trip time.
+    pub last_rtt: Option<Duration>,
+    /// The shortest round-trip time seen.
+    pub min_rtt: Option<Duration>,
+    /// The longest round-trip time seen.
+    pub max_rtt: Option<Duration>,
+    /// The average of all round-trip times.
+    pub mean_rtt: Option<Duration>,
+    /// How much faster the peer's timestamps advance than real time, in parts per million.
+    /// Negative if the peer is running slow.
+    pub drift_ppm: Option<f64>,
+}
+
+#[derive(Debug)]
+struct State {
+    last_sent: Instant,
+    outstanding: VecDeque<(u32, Instant)>,
+    total_rtt: Duration,
+    remote: Option<RemoteClock>,
+    stats: LatencyStats,
+}
+
+/// Follows how far the peer's timestamps have advanced since the first one.
+///
+/// The 31-bit timestamps wrap about every 17 minutes, so the advance is added up between
+/// consecutive samples, which only need to be closer together than that.
+#[derive(Debug)]
+pub(super) struct RemoteClock {
+    since: Instant,
+    last: u32,
+    ticks: u64,
+}
+
+impl RemoteClock {
+    /// Starts following the peer's clock from a timestamp received at `now`.
+    pub(super) fn new(timestamp: u32, now: Instant) -> RemoteClock {
+        RemoteClock {
+            since: now,
+            last: timestamp,
+            ticks: 0,
+        }
+    }
+
+    /// Takes another timestamp, received at `now`, and returns how much faster the peer's
+    /// clock has advanced than real time in parts per million, if any real time has passed.
+    pub(super) fn sample(&mut self, timestamp: u32, now: Instant) -> Option<f64> {
+        self.ticks += u64::from(timestamp.wrapping_sub(self.last) & 0x7FFF_FFFF);
+        self.last = timestamp;
+        let local = (now - self.since).as_secs_f64();
+        let remote = self.ticks as f64 / TIMESTAMP_RATE as f64;
+        if local > 0.0 {
+            Some((remote - local) / local * 1e6)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    writer: Mutex<BgbWriter<TcpStream>>,
+    state: Mutex<State>,
+    started: Instant,
+    interval: Duration,
+    stop: AtomicBool,
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn writer(&self) -> MutexGuard<'_, BgbWriter<TcpStream>> {
+        self.writer
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Wraps a `BgbStream` to send `Sync3Timestamp` packets from a background thread whenever
+/// nothing else has been sent for a while, and measures latency from the peer's replies.
+///
+/// Timestamps are taken from real time. Round trips are measured when the peer echoes a
+/// heartbeat back with the same timestamp, as `HardwareBridge` does, so they're only
+/// available with a peer that echoes. BGB sends periodic timestamps of its own instead, which
+/// never match a heartbeat and are only used to measure clock drift, by comparing how far the
+/// peer's timestamps advance against how much real time has passed. Drift is only measured
+/// correctly if the peer sends a timestamp at least every 17 minutes or so.
+///
+/// Packets must be read with `read` for their timestamps to be measured. The background thread
+/// stops when the `HeartbeatStream` is dropped or a heartbeat can't be sent.
+#[derive(Debug)]
+pub struct HeartbeatStream {
+    reader: BgbReader<TcpStream>,
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HeartbeatStream {
+    /// Starts sending heartbeats over a connection that has completed its handshake, whenever
+    /// `interval` passes without anything being written.
+    pub fn new(stream: BgbStream<TcpStream>, interval: Duration) -> io::Result<HeartbeatStream> {
+        let (reader, writer) = stream.split()?;
+        let now = Instant::now();
+        let shared = Arc::new(Shared {
+            writer: Mutex::new(writer),
+            state: Mutex::new(State {
+                last_sent: now,
+                outstanding: VecDeque::new(),
+                total_rtt: Duration::from_secs(0),
+                remote: None,
+                stats: LatencyStats::default(),
+            }),
+            started: now,
+            interval,
+            stop: AtomicBool::new(false),
+        });
+        let thread = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || heartbeat(&shared))
+        };
+        Ok(HeartbeatStream {
+            reader,
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    /// Reads a command from the peer, taking measurements if it's a `Sync3Timestamp`.
+    ///
+    /// If the command is too malformed to interpret, returns an error of
+    /// kind `InvalidData`.
+    pub fn read(&mut self) -> io::Result<TypedBgbCommand> {
+        let command = self.reader.read()?;
+        if let TypedBgbCommand::Sync3Timestamp { timestamp } = command {
+            let now = Instant::now();
+            let mut guard = self.shared.state();
+            let state = &mut *guard;
+            let echoed = state
+                .outstanding
+                .iter()
+                .position(|&(sent, _)| sent == timestamp);
+            if let Some(index) = echoed {
+                // heartbeats sent before the echoed one aren't going to be answered
+                let (_, sent) = state.outstanding.drain(..=index).next_back().unwrap();
+                let rtt = now - sent;
+                state.total_rtt += rtt;
+                let stats = &mut state.stats;
+                stats.samples += 1;
+                stats.last_rtt = Some(rtt);
+                stats.min_rtt = Some(stats.min_rtt.map_or(rtt, |min| min.min(rtt)));
+                stats.max_rtt = Some(stats.max_rtt.map_or(rtt, |max| max.max(rtt)));
+                stats.mean_rtt = Some(state.total_rtt / stats.samples as u32);
+                // an echo carries this side's clock, not the peer's
+                return Ok(command);
+            }
+            match state.remote {
+                Some(ref mut clock) => {
+                    if let Some(drift) = clock.sample(timestamp, now) {
+                        state.stats.drift_ppm = Some(drift);
+                    }
+                }
+                None => state.remote = Some(RemoteClock::new(timestamp, now)),
+            }
+        }
+        Ok(command)
+    }
+
+    /// Serializes the command to an 8-byte packet and writes it to the stream, which also
+    /// postpones the next heartbeat.
+    pub fn write(&self, command: &impl BgbCommand) -> io::Result<()> {
+        self.shared.writer().write(command)?;
+        self.shared.state().last_sent = Instant::now();
+        Ok(())
+    }
+
+    /// Returns the measurements taken so far.
+    pub fn stats(&self) -> LatencyStats {
+        self.shared.state().stats
+    }
+
+    /// Returns the timestamp that a heartbeat sent now would carry.
+    pub fn timestamp(&self) -> u32 {
+        real_timestamp(self.shared.started.elapsed())
+    }
+}
+
+impl Drop for HeartbeatStream {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+fn heartbeat(shared: &Shared) {
+    loop {
+        let wait = shared
+            .interval
+            .checked_sub(shared.state().last_sent.elapsed())
+            .unwrap_or_default();
+        thread::park_timeout(wait);
+        if shared.stop.load(Ordering::SeqCst) {
+            return;
+        }
+        let now = Instant::now();
+        let timestamp = real_timestamp(now - shared.started);
+        {
+            let mut state = shared.state();
+            if now - state.last_sent < shared.interval {
+                continue;
+            }
+            state.last_sent = now;
+            // a peer that never echoes shouldn't make this grow forever
+            if state.outstanding.len() == MAX_OUTSTANDING {
+                state.outstanding.pop_front();
+            }
+            // recorded before sending, so that an echo can't arrive first
+            state.outstanding.push_back((timestamp, now));
+        }
+        // a stalled peer can block the write, which mustn't hold up `read` or `stats`
+        let command = TypedBgbCommand::Sync3Timestamp { timestamp };
+        if shared.writer().write(&command).is_err() {
+            return;
+        }
+    }
+}
@@ -1,5 +1,4 @@
 use super::stream::BgbStream;
-use crate::commands::*;
 use std::io;
 use std::net::{SocketAddr, TcpListener, TcpStream};
 
@@ -16,22 +15,46 @@ impl BgbListener {
 
     /// Accepts a connection and performs the BGB handshake before returning.
     /// Additionally sets TCP_NODELAY as recommended by the spec.
-    /// If a bad handshake is received, returns an error of kind `InvalidData`.
+    /// If a bad handshake is received, returns an error of kind `InvalidData` wrapping a
+    /// `HandshakeError`.
     pub fn accept(&self) -> io::Result<(BgbStream<TcpStream>, SocketAddr)> {
+        self.accept_with_extensions(0)
+    }
+
+    /// As `accept`, but offers the given `EXTENSION_*` bits during the handshake, as in
+    /// `BgbStream::handshake_with_extensions`.
+    pub fn accept_with_extensions(
+        &self,
+        extensions: u32,
+    ) -> io::Result<(BgbStream<TcpStream>, SocketAddr)> {
         let (stream, addr) = self.inner.accept()?;
         stream.set_nodelay(true)?;
         let mut stream = BgbStream::wrap(stream);
-        stream.write(&TypedBgbCommand::Version { valid: true })?;
-        if stream.read()? == (TypedBgbCommand::Version { valid: true }) {
-            Ok((stream, addr))
-        } else {
-            Err(io::Error::new(io::ErrorKind::InvalidData, "bad handshake"))
-        }
+        stream.set_peer(addr);
+        stream.handshake_with_extensions(extensions)?;
+        Ok((stream, addr))
+    }
+
+    /// Gets a reference to the underlying `TcpListener`, for accepting connections without
+    /// waiting for their handshakes.
+    pub fn get_ref(&self) -> &TcpListener {
+        &self.inner
+    }
+
+    /// Returns the local socket address of the underlying `TcpListener`.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    /// Moves the underlying `TcpListener` into or out of nonblocking mode, so that `accept`
+    /// returns an error of kind `WouldBlock` instead of waiting for a connection.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.inner.set_nonblocking(nonblocking)
     }
 
     /// Returns an `Iterator` equivalent to calling `accept` in a loop, but without
     /// the `SocketAddr` information. (idk why the standard library just did it like that)
-    pub fn incoming(&self) -> BgbIncoming {
+    pub fn incoming(&self) -> BgbIncoming<'_> {
         BgbIncoming { inner: self }
     }
 }
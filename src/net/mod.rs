@@ -1,2 +1,13 @@
+pub mod buffered;
+pub mod desync;
+pub mod handshake;
+pub mod heartbeat;
 pub mod listener;
+pub mod serial;
+pub mod split;
+pub mod stats;
 pub mod stream;
+pub(crate) mod tests;
+mod trace;
+#[cfg(feature = "websocket")]
+pub mod websocket;
@@ -0,0 +1,131 @@
+use super::stream::BgbStream;
+use crate::commands::*;
+use std::collections::VecDeque;
+use std::io;
+use std::io::{Read, Write};
+
+/// What `SerialStream` answers with when nothing has been written, which is what a game
+/// sees when no cable is connected.
+const DEFAULT_IDLE: u8 = 0xFF;
+
+/// Turns the bytes a game sends over the link cable into a plain byte stream.
+///
+/// The game must drive the clock. Reading returns the data of each `Sync1` packet from BGB,
+/// and writing queues bytes to answer them with in the `Sync2` packets. Each byte written is
+/// only sent when the game clocks the next transfer, and when nothing is queued the game
+/// receives the idle byte, `0xFF` by default.
+///
+/// Reading returns end-of-file once BGB asks to disconnect.
+#[derive(Debug)]
+pub struct SerialStream<T: Read + Write> {
+    stream: BgbStream<T>,
+    incoming: VecDeque<u8>,
+    outgoing: VecDeque<u8>,
+    idle: u8,
+    disconnected: bool,
+}
+
+impl<T: Read + Write> SerialStream<T> {
+    /// Wraps an already connected `BgbStream`, telling BGB that this side is running.
+    pub fn new(mut stream: BgbStream<T>) -> io::Result<SerialStream<T>> {
+        stream.write(&TypedBgbCommand::Status {
+            running: true,
+            paused: false,
+            support_reconnect: false,
+        })?;
+        Ok(SerialStream {
+            stream,
+            incoming: VecDeque::new(),
+            outgoing: VecDeque::new(),
+            idle: DEFAULT_IDLE,
+            disconnected: false,
+        })
+    }
+
+    /// Returns the byte the game receives when nothing has been written.
+    pub fn idle(&self) -> u8 {
+        self.idle
+    }
+
+    /// Sets the byte the game receives when nothing has been written.
+    pub fn set_idle(&mut self, idle: u8) {
+        self.idle = idle;
+    }
+
+    /// Returns the number of written bytes that the game hasn't clocked yet.
+    pub fn pending(&self) -> usize {
+        self.outgoing.len()
+    }
+
+    /// Gets a reference to the underlying `BgbStream`.
+    pub fn get_ref(&self) -> &BgbStream<T> {
+        &self.stream
+    }
+
+    /// Consumes the adapter, returning the underlying `BgbStream`.
+    ///
+    /// Bytes that have been received but not read, or written but not sent, are lost.
+    pub fn into_inner(self) -> BgbStream<T> {
+        self.stream
+    }
+
+    /// Handles a single packet from BGB, returning `false` if it asked to disconnect.
+    fn exchange(&mut self) -> io::Result<bool> {
+        if self.disconnected {
+            return Ok(false);
+        }
+        let command = self.stream.read()?;
+        let (outgoing, idle) = (&mut self.outgoing, self.idle);
+        let answered = self
+            .stream
+            .answer_as_slave(&command, |_| Ok(Some(outgoing.pop_front().unwrap_or(idle))))?;
+        if let Some((data, _)) = answered {
+            self.incoming.push_back(data);
+        }
+        if command == TypedBgbCommand::WantDisconnect {
+            self.disconnected = true;
+        }
+        Ok(!self.disconnected)
+    }
+}
+
+impl<T: Read + Write> Read for SerialStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while self.incoming.is_empty() {
+            if !self.exchange()? {
+                return Ok(0);
+            }
+        }
+        let len = self.incoming.len().min(buf.len());
+        for (byte, received) in buf.iter_mut().zip(self.incoming.drain(..len)) {
+            *byte = received;
+        }
+        Ok(len)
+    }
+}
+
+impl<T: Read + Write> Write for SerialStream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.outgoing.extend(buf);
+        Ok(buf.len())
+    }
+
+    /// Waits for the game to clock out every byte that has been written, keeping whatever it
+    /// sends in the meantime to be read.
+    ///
+    /// Returns an error of kind `WriteZero` if BGB disconnects first.
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.outgoing.is_empty() {
+            if !self.exchange()? {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "BGB disconnected before every byte was sent",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,214 @@
+use super::stats::LinkStats;
+use super::stream::BgbStream;
+use crate::commands::*;
+use std::io;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+/// A transport that can open another handle to the same connection, so that one handle can
+/// wait in a read while the other writes.
+pub trait TryClone: Sized {
+    /// Creates a new independently owned handle to the same connection.
+    fn try_clone(&self) -> io::Result<Self>;
+}
+
+impl TryClone for TcpStream {
+    fn try_clone(&self) -> io::Result<TcpStream> {
+        TcpStream::try_clone(self)
+    }
+}
+
+/// The reading half of a `BgbStream`, created by `split` or `split_shared`.
+#[derive(Debug)]
+pub struct BgbReader<T: Read + Write> {
+    inner: BgbStream<T>,
+}
+
+impl<T: Read + Write> BgbReader<T> {
+    /// Takes ownership of the given read/writer and uses it to receive packets.
+    ///
+    /// No handshake is performed, so this should only be used on a connection that has
+    /// already completed one.
+    pub fn wrap(inner: T) -> BgbReader<T> {
+        BgbReader {
+            inner: BgbStream::wrap(inner),
+        }
+    }
+
+    /// Reads 8 bytes from the connection and interprets the raw command data, as in
+    /// `BgbStream::read_raw`.
+    pub fn read_raw(&mut self) -> io::Result<RawBgbCommand> {
+        self.inner.read_raw()
+    }
+
+    /// Reads 8 bytes from the connection and interprets them as a command, as in
+    /// `BgbStream::read`.
+    pub fn read(&mut self) -> io::Result<TypedBgbCommand> {
+        self.inner.read()
+    }
+
+    /// Returns a snapshot of the traffic counters for this half, which include everything
+    /// the stream counted before it was split.
+    pub fn stats(&self) -> LinkStats {
+        self.inner.stats()
+    }
+
+    /// Gets a reference to the underlying read/writer.
+    pub fn get_ref(&self) -> &T {
+        self.inner.get_ref()
+    }
+
+    /// Consumes the `BgbReader`, returning the underlying read/writer.
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+/// The writing half of a `BgbStream`, created by `split` or `split_shared`.
+#[derive(Debug)]
+pub struct BgbWriter<T: Read + Write> {
+    inner: BgbStream<T>,
+}
+
+impl<T: Read + Write> BgbWriter<T> {
+    /// Takes ownership of the given read/writer and uses it to send packets.
+    ///
+    /// No handshake is performed, so this should only be used on a connection that has
+    /// already completed one.
+    pub fn wrap(inner: T) -> BgbWriter<T> {
+        BgbWriter {
+            inner: BgbStream::wrap(inner),
+        }
+    }
+
+    /// Serializes the command to an 8-byte packet and writes it to the stream.
+    pub fn write(&mut self, command: &impl BgbCommand) -> io::Result<()> {
+        self.inner.write(command)
+    }
+
+    /// Serializes all of the commands and writes them to the stream, as in
+    /// `BgbStream::write_all_commands`.
+    pub fn write_all_commands(&mut self, commands: &[impl BgbCommand]) -> io::Result<()> {
+        self.inner.write_all_commands(commands)
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    /// Returns a snapshot of the traffic counters for this half, which start from zero when
+    /// the stream is split.
+    pub fn stats(&self) -> LinkStats {
+        self.inner.stats()
+    }
+
+    /// Gets a reference to the underlying read/writer.
+    pub fn get_ref(&self) -> &T {
+        self.inner.get_ref()
+    }
+
+    /// Consumes the `BgbWriter`, returning the underlying read/writer.
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+/// A read/writer that has been shared between a `BgbReader` and a `BgbWriter` by
+/// `split_shared`.
+///
+/// The lock is held for the whole of each read, except that reads which time out are retried
+/// with the lock released in between. Unless the transport has a read timeout (or is
+/// nonblocking), a reader waiting for data will hold up every write.
+#[derive(Debug)]
+pub struct SharedHalf<T: Read + Write> {
+    inner: Arc<Mutex<T>>,
+}
+
+impl<T: Read + Write> SharedHalf<T> {
+    /// Locks the shared read/writer, blocking until the other half isn't using it.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        // a panic on the other half can't leave a packet half-parsed, since each half only
+        // ever reads or writes whole buffers
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<T: Read + Write> Read for SharedHalf<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.lock().read(buf) {
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    thread::yield_now()
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl<T: Read + Write> Write for SharedHalf<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lock().write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        // keep each packet in one piece, even if the reader gets the lock in between
+        self.lock().write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock().flush()
+    }
+}
+
+impl<T: Read + Write> TryClone for SharedHalf<T> {
+    fn try_clone(&self) -> io::Result<SharedHalf<T>> {
+        Ok(SharedHalf {
+            inner: Arc::clone(&self.inner),
+        })
+    }
+}
+
+impl<T: Read + Write> BgbStream<T> {
+    /// Splits the connection into a reading half and a writing half that share the
+    /// read/writer behind a lock, for transports that can't open a second handle to the
+    /// connection.
+    ///
+    /// See `SharedHalf` for how the lock is held. Where the transport is a `TcpStream`,
+    /// `split` lets both halves run without waiting for each other.
+    pub fn split_shared(self) -> (BgbReader<SharedHalf<T>>, BgbWriter<SharedHalf<T>>) {
+        let reader = self.map_inner(|inner| SharedHalf {
+            inner: Arc::new(Mutex::new(inner)),
+        });
+        let writer = reader.with_inner(SharedHalf {
+            inner: Arc::clone(&reader.get_ref().inner),
+        });
+        (BgbReader { inner: reader }, BgbWriter { inner: writer })
+    }
+}
+
+impl<T: Read + Write + TryClone> BgbStream<T> {
+    /// Creates a new independently owned handle to the same connection, which reports to the
+    /// same trace and shares the negotiated extensions but has its own traffic counters.
+    pub fn try_clone(&self) -> io::Result<BgbStream<T>> {
+        Ok(self.with_inner(self.get_ref().try_clone()?))
+    }
+
+    /// Splits the connection into a reading half and a writing half that can be used from
+    /// different threads at the same time, each with its own handle to the connection.
+    ///
+    /// The handshake should already have been performed, as `connect` and
+    /// `BgbListener::accept` do.
+    pub fn split(self) -> io::Result<(BgbReader<T>, BgbWriter<T>)> {
+        let writer = self.try_clone()?;
+        Ok((BgbReader { inner: self }, BgbWriter { inner: writer }))
+    }
+}
@@ -0,0 +1,321 @@
+use crate::commands::*;
+use std::fmt;
+use std::time::Duration;
+
+/// The type of a packet, as determined by its first byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CommandKind {
+    Version,
+    Joypad,
+    Sync1,
+    Sync2,
+    Sync3,
+    Status,
+    WantDisconnect,
+    Extension,
+    Unknown,
+}
+
+impl CommandKind {
+    /// Every kind, in the order they're exported.
+    pub const ALL: [CommandKind; 9] = [
+        CommandKind::Version,
+        CommandKind::Joypad,
+        CommandKind::Sync1,
+        CommandKind::Sync2,
+        CommandKind::Sync3,
+        CommandKind::Status,
+        CommandKind::WantDisconnect,
+        CommandKind::Extension,
+        CommandKind::Unknown,
+    ];
+
+    /// Classifies a packet by its command number.
+    pub fn of(raw: &RawBgbCommand) -> CommandKind {
+        match raw.b1 {
+            1 => CommandKind::Version,
+            101 => CommandKind::Joypad,
+            104 => CommandKind::Sync1,
+            105 => CommandKind::Sync2,
+            106 => CommandKind::Sync3,
+            108 if raw.b3 == EXTENSION_COMMAND => CommandKind::Extension,
+            108 => CommandKind::Status,
+            109 => CommandKind::WantDisconnect,
+            EXTENSION_COMMAND => CommandKind::Extension,
+            _ => CommandKind::Unknown,
+        }
+    }
+
+    /// A short lowercase name, used as a label value when exporting.
+    pub fn name(self) -> &'static str {
+        match self {
+            CommandKind::Version => "version",
+            CommandKind::Joypad => "joypad",
+            CommandKind::Sync1 => "sync1",
+            CommandKind::Sync2 => "sync2",
+            CommandKind::Sync3 => "sync3",
+            CommandKind::Status => "status",
+            CommandKind::WantDisconnect => "want_disconnect",
+            CommandKind::Extension => "extension",
+            CommandKind::Unknown => "unknown",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Packet counts for one direction of a connection.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DirectionStats {
+    packets: [u64; 9],
+}
+
+impl DirectionStats {
+    /// Returns the number of packets of the given kind.
+    pub fn packets(&self, kind: CommandKind) -> u64 {
+        self.packets[kind.index()]
+    }
+
+    /// Returns the number of bytes taken up by packets of the given kind.
+    pub fn bytes(&self, kind: CommandKind) -> u64 {
+        self.packets(kind) * 8
+    }
+
+    /// Returns the number of packets of all kinds.
+    pub fn total_packets(&self) -> u64 {
+        self.packets.iter().sum()
+    }
+
+    /// Returns the number of bytes taken up by packets of all kinds.
+    pub fn total_bytes(&self) -> u64 {
+        self.total_packets() * 8
+    }
+
+    fn record(&mut self, kind: CommandKind) {
+        self.packets[kind.index()] += 1;
+    }
+}
+
+/// Counters describing the traffic on a `BgbStream`.
+///
+/// `LinkStats` from several connections can be combined with `merge`, for example to export
+/// totals for a whole server.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LinkStats {
+    /// Packets written to the connection.
+    pub sent: DirectionStats,
+    /// Packets read from the connection.
+    pub received: DirectionStats,
+    /// Packets that were read but too malformed to interpret.
+    pub decode_errors: u64,
+    /// Serial data bytes sent in `Sync1` and `Sync2` packets.
+    pub serial_bytes_sent: u64,
+    /// Serial data bytes received in `Sync1` and `Sync2` packets.
+    pub serial_bytes_received: u64,
+    /// Handshakes that completed successfully.
+    pub handshakes: u64,
+    /// Handshakes where the peer sent something other than a valid version packet, or the
+    /// connection failed before it sent anything.
+    pub failed_handshakes: u64,
+    /// The total time spent in successful handshakes.
+    pub handshake_time: Duration,
+    /// How long the most recent successful handshake took.
+    pub last_handshake: Option<Duration>,
+}
+
+impl LinkStats {
+    /// Counts a packet written to the connection.
+    pub fn record_sent(&mut self, raw: &RawBgbCommand) {
+        let kind = CommandKind::of(raw);
+        self.sent.record(kind);
+        if kind == CommandKind::Sync1 || kind == CommandKind::Sync2 {
+            self.serial_bytes_sent += 1;
+        }
+    }
+
+    /// Counts a packet read from the connection.
+    pub fn record_received(&mut self, raw: &RawBgbCommand) {
+        let kind = CommandKind::of(raw);
+        self.received.record(kind);
+        if kind == CommandKind::Sync1 || kind == CommandKind::Sync2 {
+            self.serial_bytes_received += 1;
+        }
+    }
+
+    /// Counts a handshake, successful or not.
+    pub fn record_handshake(&mut self, duration: Option<Duration>) {
+        match duration {
+            Some(duration) => {
+                self.handshakes += 1;
+                self.handshake_time += duration;
+                self.last_handshake = Some(duration);
+            }
+            None => self.failed_handshakes += 1,
+        }
+    }
+
+    /// Adds the counts from another connection to these.
+    pub fn merge(&mut self, other: &LinkStats) {
+        for kind in CommandKind::ALL.iter() {
+            self.sent.packets[kind.index()] += other.sent.packets(*kind);
+            self.received.packets[kind.index()] += other.received.packets(*kind);
+        }
+        self.decode_errors += other.decode_errors;
+        self.serial_bytes_sent += other.serial_bytes_sent;
+        self.serial_bytes_received += other.serial_bytes_received;
+        self.handshakes += other.handshakes;
+        self.failed_handshakes += other.failed_handshakes;
+        self.handshake_time += other.handshake_time;
+        if other.last_handshake.is_some() {
+            self.last_handshake = other.last_handshake;
+        }
+    }
+
+    /// Writes the counters in the Prometheus text exposition format, with the given labels
+    /// added to every sample.
+    pub fn write_prometheus(
+        &self,
+        out: &mut impl fmt::Write,
+        labels: &[(&str, &str)],
+    ) -> fmt::Result {
+        let directions = [("sent", &self.sent), ("received", &self.received)];
+
+        family(
+            out,
+            "bgb_packets_total",
+            "counter",
+            "Packets transferred, by direction and command.",
+        )?;
+        for (direction, stats) in directions.iter() {
+            for kind in CommandKind::ALL.iter() {
+                let extra = [("direction", *direction), ("command", kind.name())];
+                sample(
+                    out,
+                    "bgb_packets_total",
+                    labels,
+                    &extra,
+                    stats.packets(*kind),
+                )?;
+            }
+        }
+
+        family(
+            out,
+            "bgb_bytes_total",
+            "counter",
+            "Bytes transferred, by direction and command.",
+        )?;
+        for (direction, stats) in directions.iter() {
+            for kind in CommandKind::ALL.iter() {
+                let extra = [("direction", *direction), ("command", kind.name())];
+                sample(out, "bgb_bytes_total", labels, &extra, stats.bytes(*kind))?;
+            }
+        }
+
+        family(
+            out,
+            "bgb_serial_bytes_total",
+            "counter",
+            "Serial data bytes carried by sync1 and sync2 packets.",
+        )?;
+        let serial = [
+            ("sent", self.serial_bytes_sent),
+            ("received", self.serial_bytes_received),
+        ];
+        for (direction, bytes) in serial.iter() {
+            let extra = [("direction", *direction)];
+            sample(out, "bgb_serial_bytes_total", labels, &extra, bytes)?;
+        }
+
+        family(
+            out,
+            "bgb_decode_errors_total",
+            "counter",
+            "Packets too malformed to interpret.",
+        )?;
+        sample(
+            out,
+            "bgb_decode_errors_total",
+            labels,
+            &[],
+            self.decode_errors,
+        )?;
+
+        family(
+            out,
+            "bgb_failed_handshakes_total",
+            "counter",
+            "Handshakes that failed or where the peer sent an invalid version packet.",
+        )?;
+        sample(
+            out,
+            "bgb_failed_handshakes_total",
+            labels,
+            &[],
+            self.failed_handshakes,
+        )?;
+
+        family(
+            out,
+            "bgb_handshake_duration_seconds",
+            "summary",
+            "Time taken by successful handshakes.",
+        )?;
+        sample(
+            out,
+            "bgb_handshake_duration_seconds_sum",
+            labels,
+            &[],
+            self.handshake_time.as_secs_f64(),
+        )?;
+        sample(
+            out,
+            "bgb_handshake_duration_seconds_count",
+            labels,
+            &[],
+            self.handshakes,
+        )
+    }
+
+    /// Returns the counters in the Prometheus text exposition format.
+    pub fn to_prometheus(&self, labels: &[(&str, &str)]) -> String {
+        let mut out = String::new();
+        self.write_prometheus(&mut out, labels)
+            .expect("writing to a String can't fail");
+        out
+    }
+}
+
+fn family(out: &mut impl fmt::Write, name: &str, kind: &str, help: &str) -> fmt::Result {
+    writeln!(out, "# HELP {} {}", name, help)?;
+    writeln!(out, "# TYPE {} {}", name, kind)
+}
+
+fn sample(
+    out: &mut impl fmt::Write,
+    name: &str,
+    labels: &[(&str, &str)],
+    extra: &[(&str, &str)],
+    value: impl fmt::Display,
+) -> fmt::Result {
+    out.write_str(name)?;
+    for (i, (label, text)) in labels.iter().chain(extra).enumerate() {
+        out.write_str(if i == 0 { "{" } else { "," })?;
+        write!(out, "{}=\"", label)?;
+        for c in text.chars() {
+            match c {
+                '\\' => out.write_str("\\\\")?,
+                '"' => out.write_str("\\\"")?,
+                '\n' => out.write_str("\\n")?,
+                c => out.write_char(c)?,
+            }
+        }
+        out.write_char('"')?;
+    }
+    if !labels.is_empty() || !extra.is_empty() {
+        out.write_char('}')?;
+    }
+    writeln!(out, " {}", value)
+}
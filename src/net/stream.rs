@@ -1,11 +1,25 @@
+use super::desync::DesyncDetector;
+use super::handshake::HandshakeError;
+use super::stats::LinkStats;
+use super::trace::ConnectionSpan;
+use crate::commands::typed::CommandError;
 use crate::commands::*;
 use std::io;
 use std::io::{Read, Write};
-use std::net::{TcpStream, ToSocketAddrs};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Instant;
+
+/// The most packets that `write_all_commands` passes to the underlying writer in one call.
+pub const WRITE_CHUNK: usize = 64;
 
 #[derive(Debug)]
 pub struct BgbStream<T: Read + Write> {
     inner: T,
+    stats: LinkStats,
+    trace: ConnectionSpan,
+    extensions: u32,
+    peer_extensions: u32,
+    desync: DesyncDetector,
 }
 
 impl<T: Read + Write> BgbStream<T> {
@@ -13,14 +27,158 @@ impl<T: Read + Write> BgbStream<T> {
     ///
     /// For use over TCP, see `connect`.
     pub fn wrap(inner: T) -> BgbStream<T> {
-        BgbStream { inner }
+        BgbStream {
+            inner,
+            stats: LinkStats::default(),
+            trace: ConnectionSpan::new(None),
+            extensions: 0,
+            peer_extensions: 0,
+            desync: DesyncDetector::new(),
+        }
+    }
+
+    /// Sends a version packet and waits for the other party's, as required at the start
+    /// of every connection.
+    ///
+    /// If the other party provides an invalid handshake, returns an error of kind `InvalidData`
+    /// wrapping a `HandshakeError` that describes what was wrong with it.
+    pub fn handshake(&mut self) -> io::Result<()> {
+        self.handshake_with_extensions(0)
+    }
+
+    /// Performs the handshake like `handshake`, then offers the given `EXTENSION_*` bits to
+    /// the other party in an `ExtensionOffer` packet.
+    ///
+    /// Extensions aren't part of the BGB protocol, so the offer is shaped like a `Status`
+    /// packet saying that this side is running, which BGB and versions of this crate without
+    /// extensions take as an ordinary status. Such a peer never makes an offer of its own, so
+    /// no extension packets are ever sent to it. An offer of zero sends nothing at all, as in
+    /// `handshake`.
+    ///
+    /// Offers from the other party are consumed by the stream rather than returned by `read`.
+    /// The peer's offer is noticed when the packets around it are read, so `extensions` only
+    /// includes what was offered here once something sent after the peer's handshake has been
+    /// read.
+    pub fn handshake_with_extensions(&mut self, extensions: u32) -> io::Result<()> {
+        let started = Instant::now();
+        let received = match self.exchange_versions(extensions) {
+            Ok(received) => received,
+            Err(e) => {
+                self.stats.record_handshake(None);
+                return Err(e);
+            }
+        };
+        match HandshakeError::check(&received) {
+            Ok(()) => {
+                let duration = started.elapsed();
+                self.stats.record_handshake(Some(duration));
+                self.trace.handshake(&received, duration);
+                Ok(())
+            }
+            Err(e) => {
+                self.stats.record_handshake(None);
+                self.trace.handshake_failed(&e);
+                Err(e.into())
+            }
+        }
+    }
+
+    fn exchange_versions(&mut self, extensions: u32) -> io::Result<RawBgbCommand> {
+        self.write(&TypedBgbCommand::Version { valid: true })?;
+        if extensions != 0 {
+            self.write(&TypedBgbCommand::ExtensionOffer { extensions })?;
+            self.extensions = extensions;
+        }
+        self.read_raw()
+    }
+
+    /// Returns the `EXTENSION_*` bits that both parties have offered so far.
+    pub fn extensions(&self) -> u32 {
+        self.extensions & self.peer_extensions
+    }
+
+    /// Sends the state of the infrared LED, as of the given timestamp.
+    ///
+    /// If both parties haven't offered `EXTENSION_INFRARED`, returns an error of kind
+    /// `Unsupported` instead, since BGB would ignore the packet.
+    pub fn write_infrared(&mut self, led_on: bool, timestamp: u32) -> io::Result<()> {
+        if self.extensions() & EXTENSION_INFRARED == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the infrared extension wasn't negotiated",
+            ));
+        }
+        self.write(&TypedBgbCommand::Infrared { led_on, timestamp })
+    }
+
+    /// Sends a hash of the emulated state as of the given timestamp, such as one from
+    /// `state_hash`, and compares it to the other party's hash for the same timestamp.
+    ///
+    /// If both parties haven't offered `EXTENSION_STATE_HASH`, returns an error of kind
+    /// `Unsupported` instead, so that stock BGB peers never see the packet.
+    pub fn write_state_hash(&mut self, hash: u16, timestamp: u32) -> io::Result<()> {
+        if self.extensions() & EXTENSION_STATE_HASH == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the state hash extension wasn't negotiated",
+            ));
+        }
+        self.write(&TypedBgbCommand::StateHash { hash, timestamp })?;
+        self.desync.record_local(timestamp, hash);
+        Ok(())
+    }
+
+    /// Returns the timestamp of the first state hash that differed between the two parties,
+    /// if any have yet.
+    ///
+    /// Hashes are compared as they are sent with `write_state_hash` and read from the other
+    /// party, so this only knows about hashes that have been read.
+    pub fn first_desync(&self) -> Option<u32> {
+        self.desync.first_mismatch()
+    }
+
+    /// Returns a snapshot of the traffic counters for this connection.
+    pub fn stats(&self) -> LinkStats {
+        self.stats
+    }
+
+    /// Resets the traffic counters for this connection to zero.
+    pub fn reset_stats(&mut self) {
+        self.stats = LinkStats::default();
+    }
+
+    /// Gets a reference to the underlying read/writer.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the underlying read/writer.
+    ///
+    /// Reading or writing directly may corrupt the stream of packets.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Consumes the `BgbStream`, returning the underlying read/writer.
+    pub fn into_inner(self) -> T {
+        self.inner
     }
 
     /// Reads 8 bytes from the connection and interprets the raw command data.
+    ///
+    /// `ExtensionOffer` packets are recorded and skipped, as they're part of the handshake.
     pub fn read_raw(&mut self) -> io::Result<RawBgbCommand> {
-        let mut buf = [0u8; 8];
-        self.inner.read_exact(&mut buf)?;
-        Ok(RawBgbCommand::deserialize(&buf))
+        loop {
+            let mut buf = [0u8; 8];
+            if let Err(e) = self.inner.read_exact(&mut buf) {
+                self.trace.read_failed(&e);
+                return Err(e);
+            }
+            let raw = RawBgbCommand::deserialize(&buf);
+            if self.record_received(&raw) {
+                return Ok(raw);
+            }
+        }
     }
 
     /// Reads 8 bytes from the connection and interprets them as a command.
@@ -28,15 +186,176 @@ impl<T: Read + Write> BgbStream<T> {
     /// If the command is too malformed to interpret, returns an error of
     /// kind `InvalidData`.
     pub fn read(&mut self) -> io::Result<TypedBgbCommand> {
-        match TypedBgbCommand::from_raw(&self.read_raw()?) {
-            Ok(result) => Ok(result),
-            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
-        }
+        let raw = self.read_raw()?;
+        self.interpret(&raw)
     }
 
     /// Serializes the command to an 8-byte packet and writes it to the stream.
     pub fn write(&mut self, command: &impl BgbCommand) -> io::Result<()> {
-        self.inner.write_all(&command.serialize())
+        let bytes = command.serialize();
+        if let Err(e) = self.inner.write_all(&bytes) {
+            self.trace.write_failed(&e);
+            return Err(e);
+        }
+        self.record_sent(&RawBgbCommand::deserialize(&bytes));
+        Ok(())
+    }
+
+    /// Serializes all of the commands and writes them to the stream, up to `WRITE_CHUNK`
+    /// packets at a time, without allocating.
+    pub fn write_all_commands(&mut self, commands: &[impl BgbCommand]) -> io::Result<()> {
+        let mut buf = [0; WRITE_CHUNK * 8];
+        for chunk in commands.chunks(WRITE_CHUNK) {
+            let bytes = &mut buf[..chunk.len() * 8];
+            for (command, packet) in chunk.iter().zip(bytes.chunks_exact_mut(8)) {
+                packet.copy_from_slice(&command.serialize());
+            }
+            if let Err(e) = self.inner.write_all(bytes) {
+                self.trace.write_failed(&e);
+                return Err(e);
+            }
+            for raw in RawBgbCommand::decode_all(bytes) {
+                self.record_sent(&raw);
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes the underlying writer, which is needed for buffered transports to actually
+    /// send anything.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    /// Interprets a packet that was read from the connection, counting it if it's malformed.
+    pub(crate) fn interpret(&mut self, raw: &RawBgbCommand) -> io::Result<TypedBgbCommand> {
+        match TypedBgbCommand::from_raw(raw) {
+            Ok(result) => Ok(result),
+            Err(e) => {
+                self.stats.decode_errors += 1;
+                Err(io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+        }
+    }
+
+    /// Counts a packet that was read from the underlying read/writer without going through
+    /// `read_raw`.
+    ///
+    /// Returns `false` if the packet was an `ExtensionOffer`, which the stream consumes
+    /// itself and mustn't be passed on to the application.
+    pub(crate) fn record_received(&mut self, raw: &RawBgbCommand) -> bool {
+        self.record_decoded(raw, TypedBgbCommand::from_raw(raw).as_ref().ok())
+    }
+
+    /// Counts a packet that was read from the underlying read/writer and has already been
+    /// decoded, returning what `read` would.
+    ///
+    /// Returns `None` if the packet was an `ExtensionOffer`, as in `record_received`.
+    pub(crate) fn receive_decoded(
+        &mut self,
+        raw: &RawBgbCommand,
+        decoded: Result<TypedBgbCommand, CommandError>,
+    ) -> Option<io::Result<TypedBgbCommand>> {
+        match decoded {
+            Ok(command) => {
+                if self.record_decoded(raw, Some(&command)) {
+                    Some(Ok(command))
+                } else {
+                    None
+                }
+            }
+            Err(e) => {
+                self.record_decoded(raw, None);
+                self.stats.decode_errors += 1;
+                Some(Err(io::Error::new(io::ErrorKind::InvalidData, e)))
+            }
+        }
+    }
+
+    fn record_decoded(&mut self, raw: &RawBgbCommand, command: Option<&TypedBgbCommand>) -> bool {
+        self.stats.record_received(raw);
+        self.trace.received(raw);
+        match command {
+            Some(&TypedBgbCommand::ExtensionOffer { extensions }) => {
+                self.peer_extensions = extensions;
+                return false;
+            }
+            Some(&TypedBgbCommand::StateHash { hash, timestamp }) => {
+                self.desync.record_remote(timestamp, hash);
+            }
+            _ => {}
+        }
+        true
+    }
+
+    /// Does what the link slave must for a packet read from the other party.
+    ///
+    /// A `Sync1` is answered with a `Sync2` carrying the byte that `answer` returns for its
+    /// data, or with a `Sync3Response` if `answer` returns `None` because nothing is waiting
+    /// to be clocked. A `Sync3Timestamp` is echoed back, which lets BGB keep running between
+    /// transfers. Anything written is flushed.
+    ///
+    /// Returns the data of a `Sync1` and the byte it was answered with, if it was answered
+    /// with a `Sync2`.
+    pub fn answer_as_slave(
+        &mut self,
+        command: &TypedBgbCommand,
+        answer: impl FnOnce(u8) -> io::Result<Option<u8>>,
+    ) -> io::Result<Option<(u8, u8)>> {
+        let answered = match *command {
+            TypedBgbCommand::Sync1 { data, .. } => match answer(data)? {
+                Some(sent) => {
+                    self.write(&TypedBgbCommand::Sync2 { data: sent })?;
+                    Some((data, sent))
+                }
+                None => {
+                    self.write(&TypedBgbCommand::Sync3Response)?;
+                    None
+                }
+            },
+            TypedBgbCommand::Sync3Timestamp { .. } => {
+                self.write(command)?;
+                None
+            }
+            _ => return Ok(None),
+        };
+        self.flush()?;
+        Ok(answered)
+    }
+
+    fn record_sent(&mut self, raw: &RawBgbCommand) {
+        self.stats.record_sent(raw);
+        self.trace.sent(raw);
+    }
+
+    /// Wraps another handle to the same connection, keeping the trace and the negotiated
+    /// extensions but starting with fresh traffic counters.
+    pub(crate) fn with_inner<U: Read + Write>(&self, inner: U) -> BgbStream<U> {
+        BgbStream {
+            inner,
+            stats: LinkStats::default(),
+            trace: self.trace.clone(),
+            extensions: self.extensions,
+            peer_extensions: self.peer_extensions,
+            desync: DesyncDetector::new(),
+        }
+    }
+
+    /// Replaces the read/writer, keeping everything the stream has counted and negotiated.
+    pub(crate) fn map_inner<U: Read + Write>(self, f: impl FnOnce(T) -> U) -> BgbStream<U> {
+        BgbStream {
+            inner: f(self.inner),
+            stats: self.stats,
+            trace: self.trace,
+            extensions: self.extensions,
+            peer_extensions: self.peer_extensions,
+            desync: self.desync,
+        }
+    }
+
+    /// Reports this connection's events as coming from the given peer.
+    pub(crate) fn set_peer(&mut self, peer: SocketAddr) {
+        self.trace = ConnectionSpan::new(Some(peer));
     }
 }
 
@@ -45,38 +364,44 @@ impl BgbStream<TcpStream> {
     ///
     /// This method also enables TCP_NODELAY, as recommended in the spec, and waits for the handshake to
     /// complete before returning. If the other party provides an invalid handshake, returns an error
-    /// of kind `InvalidData`.
+    /// of kind `InvalidData` wrapping a `HandshakeError`.
     pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<BgbStream<TcpStream>> {
+        BgbStream::connect_with_extensions(addr, 0)
+    }
+
+    /// As `connect`, but offers the given `EXTENSION_*` bits during the handshake, as in
+    /// `handshake_with_extensions`.
+    pub fn connect_with_extensions<A: ToSocketAddrs>(
+        addr: A,
+        extensions: u32,
+    ) -> io::Result<BgbStream<TcpStream>> {
         let inner = TcpStream::connect(addr)?;
         inner.set_nodelay(true)?;
-        let mut stream = BgbStream { inner };
-        stream.write(&TypedBgbCommand::Version { valid: true })?;
-        if stream.read()? == (TypedBgbCommand::Version { valid: true }) {
-            Ok(stream)
-        } else {
-            Err(io::Error::new(io::ErrorKind::InvalidData, "bad handshake"))
-        }
+        let peer = inner.peer_addr()?;
+        let mut stream = BgbStream::wrap(inner);
+        stream.set_peer(peer);
+        stream.handshake_with_extensions(extensions)?;
+        Ok(stream)
     }
 
     /// Uses `TcpStream.peek` to check if 8 bytes are available, and if so, reads them and
-    /// interprets the raw data.
+    /// interprets the raw data. `ExtensionOffer` packets are skipped as in `read_raw`.
     pub fn maybe_read_raw(&mut self) -> io::Result<Option<RawBgbCommand>> {
         let mut buf = [0u8; 8];
-        if self.inner.peek(&mut buf)? == 8 {
+        while self.inner.peek(&mut buf)? == 8 {
             self.inner.read_exact(&mut buf)?;
-            Ok(Some(RawBgbCommand::deserialize(&buf)))
-        } else {
-            Ok(None)
+            let raw = RawBgbCommand::deserialize(&buf);
+            if self.record_received(&raw) {
+                return Ok(Some(raw));
+            }
         }
+        Ok(None)
     }
 
     /// As `read` but for `maybe_read_raw` instead of `read_raw`.
     pub fn maybe_read(&mut self) -> io::Result<Option<TypedBgbCommand>> {
         if let Some(raw) = self.maybe_read_raw()? {
-            match TypedBgbCommand::from_raw(&raw) {
-                Ok(result) => Ok(Some(result)),
-                Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
-            }
+            self.interpret(&raw).map(Some)
         } else {
             Ok(None)
         }
@@ -0,0 +1,865 @@
+#[cfg(feature = "websocket")]
+#[test]
+fn websocket_round_trip() {
+    use super::stream::BgbStream;
+    use super::websocket::*;
+    use crate::commands::*;
+    use std::net::TcpListener;
+
+    let listener = BgbWsListener::wrap(TcpListener::bind("127.0.0.1:0").unwrap());
+    let url = format!("ws://{}/", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let received = stream.read().unwrap();
+        stream
+            .write(&TypedBgbCommand::Sync2 { data: 0x42 })
+            .unwrap();
+        received
+    });
+
+    let secure = BgbStream::connect_ws(&url.replace("ws://", "wss://"));
+    assert_eq!(secure.unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+
+    let mut client = BgbStream::connect_ws(&url).unwrap();
+    client
+        .write(&TypedBgbCommand::Sync1 {
+            data: 0x24,
+            high_speed: false,
+            double_speed: true,
+            timestamp: 1234,
+        })
+        .unwrap();
+    assert_eq!(
+        client.read().unwrap(),
+        TypedBgbCommand::Sync2 { data: 0x42 }
+    );
+    assert_eq!(
+        server.join().unwrap(),
+        TypedBgbCommand::Sync1 {
+            data: 0x24,
+            high_speed: false,
+            double_speed: true,
+            timestamp: 1234,
+        }
+    );
+}
+
+#[cfg(feature = "websocket")]
+#[test]
+fn websocket_ipv6() {
+    use super::stream::BgbStream;
+    use super::websocket::*;
+    use crate::commands::*;
+    use std::net::TcpListener;
+
+    let listener = BgbWsListener::wrap(TcpListener::bind("[::1]:0").unwrap());
+    let url = format!("ws://{}/", listener.local_addr().unwrap());
+    assert!(url.starts_with("ws://[::1]:"));
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.read().unwrap()
+    });
+
+    let mut client = BgbStream::connect_ws(&url).unwrap();
+    client.write(&TypedBgbCommand::Sync3Response).unwrap();
+    assert_eq!(server.join().unwrap(), TypedBgbCommand::Sync3Response);
+}
+
+#[cfg(feature = "websocket")]
+#[test]
+fn websocket_bridge() {
+    use super::listener::BgbListener;
+    use super::stream::BgbStream;
+    use super::websocket::*;
+    use crate::commands::*;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    let native = BgbListener::wrap(TcpListener::bind("127.0.0.1:0").unwrap());
+    let native_addr = native.local_addr().unwrap();
+    let bridge = BgbWsListener::wrap(TcpListener::bind("127.0.0.1:0").unwrap());
+    let bridge_addr = bridge.local_addr().unwrap();
+    let url = format!("ws://{}/", bridge_addr);
+    std::thread::spawn(move || bridge.bridge_to(native_addr));
+
+    // neither a client that stays silent nor one that leaves mid-handshake holds up the rest
+    let _silent = TcpStream::connect(bridge_addr).unwrap();
+    drop(tungstenite::client(url.as_str(), TcpStream::connect(bridge_addr).unwrap()).unwrap());
+
+    let mut client = BgbStream::connect_ws(&url).unwrap();
+    let (mut peer, _) = native.accept().unwrap();
+
+    // packets that the streams would consume themselves are passed on as they are
+    let offer = TypedBgbCommand::ExtensionOffer {
+        extensions: EXTENSION_INFRARED,
+    };
+    client.write(&offer).unwrap();
+    let mut forwarded = [0u8; 8];
+    peer.get_mut().read_exact(&mut forwarded).unwrap();
+    assert_eq!(forwarded, offer.serialize());
+
+    client
+        .write(&TypedBgbCommand::Joypad {
+            button_number: 3,
+            pressed: true,
+        })
+        .unwrap();
+    assert_eq!(
+        peer.read().unwrap(),
+        TypedBgbCommand::Joypad {
+            button_number: 3,
+            pressed: true,
+        }
+    );
+
+    peer.write(&TypedBgbCommand::Sync3Timestamp { timestamp: 99 })
+        .unwrap();
+    assert_eq!(
+        client.read().unwrap(),
+        TypedBgbCommand::Sync3Timestamp { timestamp: 99 }
+    );
+
+    // a packet that arrives in pieces is relayed once it's complete
+    let packet = TypedBgbCommand::Sync3Timestamp { timestamp: 100 }.serialize();
+    peer.get_mut().write_all(&packet[..3]).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    peer.get_mut().write_all(&packet[3..]).unwrap();
+    assert_eq!(
+        client.read().unwrap(),
+        TypedBgbCommand::Sync3Timestamp { timestamp: 100 }
+    );
+
+    client.write(&TypedBgbCommand::WantDisconnect).unwrap();
+    assert_eq!(peer.read().unwrap(), TypedBgbCommand::WantDisconnect);
+}
+
+#[test]
+fn buffered_batches() {
+    use super::buffered::BufferedTransport;
+    use super::stream::BgbStream;
+    use crate::commands::*;
+    use std::io::{self, Read, Write};
+
+    /// Hands out at most 5 bytes per read to split packets across reads.
+    struct Trickle {
+        input: io::Cursor<Vec<u8>>,
+        writes: Vec<Vec<u8>>,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(5);
+            self.input.read(&mut buf[..len])
+        }
+    }
+
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes.push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let commands = [
+        TypedBgbCommand::Sync2 { data: 1 },
+        TypedBgbCommand::Sync2 { data: 2 },
+        TypedBgbCommand::Sync3Response,
+    ];
+    let mut input = Vec::new();
+    encode_all(&commands, &mut input);
+    let mut stream = BgbStream::wrap(BufferedTransport::new(Trickle {
+        input: io::Cursor::new(input),
+        writes: Vec::new(),
+    }));
+
+    let mut received = Vec::new();
+    while received.len() < commands.len() {
+        received.extend(stream.read_batch().unwrap());
+    }
+    assert_eq!(received, commands);
+    assert_eq!(
+        stream.read_batch().unwrap_err().kind(),
+        io::ErrorKind::UnexpectedEof
+    );
+
+    // the packets before a malformed one are returned, and the malformed one fails by itself
+    let mut input = vec![0; 24];
+    input[..8].copy_from_slice(&commands[0].serialize());
+    input[16..].copy_from_slice(&commands[1].serialize());
+    let mut malformed = BgbStream::wrap(BufferedTransport::new(io::Cursor::new(input)));
+    assert_eq!(malformed.read_batch().unwrap(), &commands[..1]);
+    assert_eq!(
+        malformed.read_batch().unwrap_err().kind(),
+        io::ErrorKind::InvalidData
+    );
+    assert_eq!(malformed.read_batch().unwrap(), &commands[1..2]);
+    assert_eq!(malformed.stats().decode_errors, 1);
+
+    stream.write_all_commands(&commands).unwrap();
+    stream.write(&TypedBgbCommand::WantDisconnect).unwrap();
+    assert!(stream.get_ref().get_ref().writes.is_empty());
+    stream.flush().unwrap();
+    let writes = &stream.get_ref().get_ref().writes;
+    assert_eq!(writes.len(), 1);
+    assert_eq!(writes[0].len(), 32);
+}
+
+/// Connects two `BgbStream`s to each other over localhost, returning the accepted one first.
+#[cfg(test)]
+pub(crate) fn connected_pair() -> (
+    super::stream::BgbStream<std::net::TcpStream>,
+    super::stream::BgbStream<std::net::TcpStream>,
+) {
+    use super::listener::BgbListener;
+    use super::stream::BgbStream;
+    use std::net::TcpListener;
+
+    let listener = BgbListener::wrap(TcpListener::bind("127.0.0.1:0").unwrap());
+    let addr = listener.local_addr().unwrap();
+    let accepted = std::thread::spawn(move || listener.accept().unwrap().0);
+    let connected = BgbStream::connect(addr).unwrap();
+    (accepted.join().unwrap(), connected)
+}
+
+#[test]
+fn split_halves() {
+    use crate::commands::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let (mut peer, stream) = connected_pair();
+    let (mut reader, mut writer) = stream.split().unwrap();
+
+    // the reader is blocked before anything is written
+    let (sender, receiver) = mpsc::channel();
+    let reading = std::thread::spawn(move || {
+        sender.send(reader.read().unwrap()).unwrap();
+        reader
+    });
+    assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err());
+
+    writer
+        .write(&TypedBgbCommand::Joypad {
+            button_number: 1,
+            pressed: true,
+        })
+        .unwrap();
+    let received = peer.read().unwrap();
+    peer.write(&received).unwrap();
+    assert_eq!(
+        receiver.recv().unwrap(),
+        TypedBgbCommand::Joypad {
+            button_number: 1,
+            pressed: true,
+        }
+    );
+    // the reader carries on counting from the handshake, and the writer starts afresh
+    let reader = reading.join().unwrap();
+    assert_eq!(reader.stats().received.total_packets(), 2);
+    assert_eq!(writer.stats().sent.total_packets(), 1);
+}
+
+#[test]
+fn split_shared_halves() {
+    use super::stream::BgbStream;
+    use crate::commands::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let (mut peer, stream) = connected_pair();
+    let socket = stream.into_inner();
+    socket
+        .set_read_timeout(Some(Duration::from_millis(5)))
+        .unwrap();
+    let (mut reader, mut writer) = BgbStream::wrap_buffered(socket).split_shared();
+
+    // the waiting reader lets go of the lock whenever its read times out
+    let (sender, receiver) = mpsc::channel();
+    let reading = std::thread::spawn(move || {
+        sender.send(reader.read().unwrap()).unwrap();
+    });
+    assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err());
+
+    writer.write(&TypedBgbCommand::Sync2 { data: 7 }).unwrap();
+    writer.flush().unwrap();
+    let received = peer.read().unwrap();
+    peer.write(&received).unwrap();
+    assert_eq!(receiver.recv().unwrap(), TypedBgbCommand::Sync2 { data: 7 });
+    reading.join().unwrap();
+}
+
+#[test]
+fn serial_byte_stream() {
+    use super::serial::SerialStream;
+    use crate::commands::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    let (mut game, stream) = connected_pair();
+    let rom = std::thread::spawn(move || {
+        assert!(matches!(
+            game.read().unwrap(),
+            TypedBgbCommand::Status { .. }
+        ));
+        let mut received = Vec::new();
+        for &byte in b"hello\n" {
+            game.write(&TypedBgbCommand::Sync1 {
+                data: byte,
+                high_speed: false,
+                double_speed: false,
+                timestamp: 0,
+            })
+            .unwrap();
+            match game.read().unwrap() {
+                TypedBgbCommand::Sync2 { data } => received.push(data),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        let timestamp = TypedBgbCommand::Sync3Timestamp { timestamp: 42 };
+        game.write(&timestamp).unwrap();
+        assert_eq!(game.read().unwrap(), timestamp);
+        game.write(&TypedBgbCommand::WantDisconnect).unwrap();
+        received
+    });
+
+    let mut serial = SerialStream::new(stream).unwrap();
+    serial.set_idle(0x00);
+    serial.write_all(b"ok").unwrap();
+    assert_eq!(serial.pending(), 2);
+    serial.flush().unwrap();
+    assert_eq!(serial.pending(), 0);
+
+    // the bytes received while flushing are kept for reading
+    let mut reader = BufReader::new(serial);
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "hello\n");
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+
+    let mut serial = reader.into_inner();
+    serial.write_all(b"!").unwrap();
+    assert_eq!(
+        serial.flush().unwrap_err().kind(),
+        std::io::ErrorKind::WriteZero
+    );
+    assert_eq!(rom.join().unwrap(), b"ok\0\0\0\0");
+}
+
+#[test]
+fn heartbeat_latency() {
+    use super::heartbeat::HeartbeatStream;
+    use crate::commands::*;
+    use std::time::Duration;
+
+    let (mut peer, stream) = connected_pair();
+    let mut stream = HeartbeatStream::new(stream, Duration::from_millis(5)).unwrap();
+    let echo = std::thread::spawn(move || {
+        for i in 0..3 {
+            let heartbeat = peer.read().unwrap();
+            assert!(matches!(heartbeat, TypedBgbCommand::Sync3Timestamp { .. }));
+            std::thread::sleep(Duration::from_millis(2));
+            // like BGB, the peer also sends timestamps of its own, which aren't round trips
+            peer.write(&TypedBgbCommand::Sync3Timestamp {
+                timestamp: 0x4000_0000 + i * 4096,
+            })
+            .unwrap();
+            peer.write(&heartbeat).unwrap();
+        }
+        peer
+    });
+
+    for _ in 0..6 {
+        stream.read().unwrap();
+    }
+    let mut peer = echo.join().unwrap();
+    let stats = stream.stats();
+    assert_eq!(stats.samples, 3);
+    assert!(stats.min_rtt.unwrap() >= Duration::from_millis(2));
+    assert!(stats.min_rtt <= stats.mean_rtt && stats.mean_rtt <= stats.max_rtt);
+    assert!(stats.drift_ppm.is_some());
+
+    // the application can write alongside the heartbeats, some of which may have been sent
+    // since the peer stopped reading
+    stream.write(&TypedBgbCommand::Sync2 { data: 1 }).unwrap();
+    loop {
+        match peer.read().unwrap() {
+            TypedBgbCommand::Sync3Timestamp { .. } => {}
+            command => {
+                assert_eq!(command, TypedBgbCommand::Sync2 { data: 1 });
+                break;
+            }
+        }
+    }
+    drop(stream);
+}
+
+#[test]
+fn heartbeat_drift() {
+    use super::heartbeat::RemoteClock;
+    use crate::commands::*;
+    use std::time::{Duration, Instant};
+
+    // an hour of timestamps from a clock running 1% fast, which wraps several times
+    let start = Instant::now();
+    let mut timestamp = 0x7FFF_0000u32;
+    let mut clock = RemoteClock::new(timestamp, start);
+    let mut drift = None;
+    for minutes in 1..=60 {
+        let ticks = 60 * TIMESTAMP_RATE * 101 / 100;
+        timestamp = timestamp.wrapping_add(ticks as u32) & 0x7FFF_FFFF;
+        drift = clock.sample(timestamp, start + Duration::from_secs(60 * minutes));
+    }
+    assert!((drift.unwrap() - 10_000.0).abs() < 1.0);
+}
+
+#[test]
+fn heartbeat_stalled_peer() {
+    use super::heartbeat::HeartbeatStream;
+    use crate::commands::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let (peer, stream) = connected_pair();
+    let stream = &HeartbeatStream::new(stream, Duration::from_millis(1)).unwrap();
+    std::thread::scope(|scope| {
+        // the peer never reads, so the writes eventually block
+        scope.spawn(|| while stream.write(&TypedBgbCommand::Sync2 { data: 0 }).is_ok() {});
+        std::thread::sleep(Duration::from_millis(200));
+
+        let (sender, receiver) = mpsc::channel();
+        scope.spawn(move || sender.send(stream.stats()).unwrap());
+        let stats = receiver.recv_timeout(Duration::from_secs(1));
+        // hanging up unblocks the writes either way
+        drop(peer);
+        assert!(stats.is_ok(), "stats blocked behind a stalled write");
+    });
+}
+
+#[test]
+fn link_stats() {
+    use super::stats::CommandKind;
+    use crate::commands::*;
+
+    let (mut peer, mut stream) = connected_pair();
+    stream
+        .write_all_commands(&[
+            TypedBgbCommand::Sync1 {
+                data: 1,
+                high_speed: false,
+                double_speed: false,
+                timestamp: 0,
+            },
+            TypedBgbCommand::Sync3Timestamp { timestamp: 10 },
+        ])
+        .unwrap();
+    peer.write(&TypedBgbCommand::Sync2 { data: 2 }).unwrap();
+    peer.write(&RawBgbCommand {
+        b1: 246,
+        b2: 0,
+        b3: 0,
+        b4: 0,
+        i1: 0,
+    })
+    .unwrap();
+    assert_eq!(stream.read().unwrap(), TypedBgbCommand::Sync2 { data: 2 });
+    assert!(stream.read().is_err());
+
+    let stats = stream.stats();
+    assert_eq!(stats.sent.packets(CommandKind::Version), 1);
+    assert_eq!(stats.sent.packets(CommandKind::Sync1), 1);
+    assert_eq!(stats.sent.bytes(CommandKind::Sync3), 8);
+    assert_eq!(stats.sent.total_packets(), 3);
+    assert_eq!(stats.received.packets(CommandKind::Sync2), 1);
+    assert_eq!(stats.received.packets(CommandKind::Unknown), 1);
+    assert_eq!(stats.received.total_bytes(), 24);
+    assert_eq!(stats.decode_errors, 1);
+    assert_eq!(
+        (stats.serial_bytes_sent, stats.serial_bytes_received),
+        (1, 1)
+    );
+    assert_eq!(stats.handshakes, 1);
+    assert_eq!(stats.last_handshake, Some(stats.handshake_time));
+
+    let mut total = peer.stats();
+    total.merge(&stats);
+    assert_eq!(total.handshakes, 2);
+    let exported = total.to_prometheus(&[("server", "a \"b\"")]);
+    assert!(exported.contains(
+        "bgb_packets_total{server=\"a \\\"b\\\"\",direction=\"sent\",command=\"sync2\"} 1\n"
+    ));
+    assert!(exported.contains(
+        "bgb_bytes_total{server=\"a \\\"b\\\"\",direction=\"received\",command=\"sync2\"} 8\n"
+    ));
+    assert!(exported.contains("bgb_decode_errors_total{server=\"a \\\"b\\\"\"} 1\n"));
+    assert!(exported.contains("bgb_handshake_duration_seconds_count{server=\"a \\\"b\\\"\"} 2\n"));
+    assert!(peer
+        .stats()
+        .to_prometheus(&[])
+        .contains("bgb_decode_errors_total 0\n"));
+}
+
+#[test]
+fn handshake_errors() {
+    use super::handshake::*;
+    use super::stream::BgbStream;
+    use crate::commands::*;
+    use std::io::{self, Cursor, Read, Write};
+
+    struct Peer(Cursor<[u8; 8]>);
+
+    impl Read for Peer {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for Peer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let handshake = |packet| {
+        let error = BgbStream::wrap(Peer(Cursor::new(packet)))
+            .handshake()
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let handshake = HandshakeError::from_io(&error).unwrap().clone();
+        assert_eq!(error.to_string(), handshake.to_string());
+        assert_eq!(*handshake.received(), RawBgbCommand::deserialize(&packet));
+        handshake
+    };
+
+    let outdated = handshake([1, 1, 3, 0, 0, 0, 0, 0]);
+    assert_eq!(outdated.rule(), HandshakeRule::SupportedVersion);
+    assert_eq!(
+        outdated.decoded(),
+        Some(&TypedBgbCommand::Version { valid: false })
+    );
+    assert_eq!(
+        outdated.to_string(),
+        "peer speaks protocol 1.3, expected 1.4"
+    );
+
+    let padded = handshake([1, 1, 4, 0, 1, 0, 0, 0]);
+    assert_eq!(padded.rule(), HandshakeRule::ZeroPadding);
+
+    let status = handshake([108, 1, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(status.rule(), HandshakeRule::VersionFirst);
+    assert!(status.to_string().starts_with("peer sent Status"));
+
+    let unknown = handshake([42, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(unknown.rule(), HandshakeRule::KnownCommand);
+    assert_eq!(unknown.decoded(), None);
+    assert_eq!(
+        unknown.to_string(),
+        "peer sent unknown command 42 instead of its version"
+    );
+
+    assert!(BgbStream::wrap(Peer(Cursor::new([1, 1, 4, 0, 0, 0, 0, 0])))
+        .handshake()
+        .is_ok());
+    let mut empty = BgbStream::wrap(Peer(Cursor::new([0; 8])));
+    empty.get_mut().0.set_position(8);
+    let error = empty.handshake().unwrap_err();
+    assert!(HandshakeError::from_io(&error).is_none());
+    assert_eq!(empty.stats().failed_handshakes, 1);
+}
+
+#[test]
+fn infrared_extension() {
+    use super::listener::BgbListener;
+    use super::stream::BgbStream;
+    use crate::commands::*;
+    use std::io;
+    use std::net::TcpListener;
+
+    let listener = BgbListener::wrap(TcpListener::bind("127.0.0.1:0").unwrap());
+    let addr = listener.local_addr().unwrap();
+    let peer = std::thread::spawn(move || {
+        let (mut peer, _) = listener.accept_with_extensions(EXTENSION_INFRARED).unwrap();
+        // the offer is consumed on the way to the first packet after the handshake
+        assert_eq!(peer.read().unwrap(), TypedBgbCommand::WantDisconnect);
+        assert_eq!(peer.extensions(), EXTENSION_INFRARED);
+        peer.write_infrared(true, 100).unwrap();
+        // a peer that offers nothing can't be sent infrared packets
+        let (mut stock, _) = listener.accept_with_extensions(EXTENSION_INFRARED).unwrap();
+        assert_eq!(stock.read().unwrap(), TypedBgbCommand::WantDisconnect);
+        assert_eq!(stock.extensions(), 0);
+        assert_eq!(
+            stock.write_infrared(true, 0).unwrap_err().kind(),
+            io::ErrorKind::Unsupported
+        );
+    });
+
+    let mut stream = BgbStream::connect_with_extensions(addr, SUPPORTED_EXTENSIONS).unwrap();
+    assert_eq!(stream.extensions(), 0);
+    stream.write(&TypedBgbCommand::WantDisconnect).unwrap();
+    assert_eq!(
+        stream.read().unwrap(),
+        TypedBgbCommand::Infrared {
+            led_on: true,
+            timestamp: 100
+        }
+    );
+    assert_eq!(stream.extensions(), EXTENSION_INFRARED);
+
+    // a side that offers nothing still consumes the peer's offer instead of returning it
+    let mut stock = BgbStream::connect(addr).unwrap();
+    stock.write(&TypedBgbCommand::WantDisconnect).unwrap();
+    peer.join().unwrap();
+    assert_eq!(
+        stock.read().unwrap_err().kind(),
+        io::ErrorKind::UnexpectedEof
+    );
+    assert_eq!(stock.stats().received.total_packets(), 2);
+}
+
+#[test]
+fn extensions_with_older_peers() {
+    use super::stream::BgbStream;
+    use crate::commands::*;
+    use std::io::{self, Read, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    // a peer that reads packets as versions of this crate without extensions did, failing
+    // on any command number that BGB doesn't define
+    let peer = std::thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        socket.write_all(&[1, 1, 4, 0, 0, 0, 0, 0]).unwrap();
+        let mut read_stock = || {
+            let mut packet = [0u8; 8];
+            socket.read_exact(&mut packet).unwrap();
+            assert!(
+                [1, 101, 104, 105, 106, 108, 109].contains(&packet[0]),
+                "stock peer received {:?}",
+                packet
+            );
+            packet
+        };
+        assert_eq!(read_stock(), [1, 1, 4, 0, 0, 0, 0, 0]);
+        // the offer is a status saying that the other side is running
+        let offer = read_stock();
+        assert_eq!((offer[0], offer[1]), (108, 1));
+        assert_eq!(read_stock()[0], 109);
+        socket.write_all(&[105, 0x42, 0x80, 0, 0, 0, 0, 0]).unwrap();
+    });
+
+    let mut stream = BgbStream::connect_with_extensions(addr, SUPPORTED_EXTENSIONS).unwrap();
+    stream.write(&TypedBgbCommand::WantDisconnect).unwrap();
+    assert_eq!(
+        stream.read().unwrap(),
+        TypedBgbCommand::Sync2 { data: 0x42 }
+    );
+    peer.join().unwrap();
+    assert_eq!(stream.extensions(), 0);
+    assert_eq!(
+        stream.write_infrared(true, 0).unwrap_err().kind(),
+        io::ErrorKind::Unsupported
+    );
+}
+
+#[test]
+fn state_hash_desync() {
+    use super::desync::*;
+    use super::listener::BgbListener;
+    use super::stream::BgbStream;
+    use crate::commands::*;
+    use std::io;
+    use std::net::TcpListener;
+
+    let listener = BgbListener::wrap(TcpListener::bind("127.0.0.1:0").unwrap());
+    let addr = listener.local_addr().unwrap();
+    let ram = [0x12u8; 256];
+    let peer = std::thread::spawn(move || {
+        let (mut peer, _) = listener
+            .accept_with_extensions(EXTENSION_STATE_HASH)
+            .unwrap();
+        assert!(matches!(
+            peer.read().unwrap(),
+            TypedBgbCommand::Status { .. }
+        ));
+        for (i, &timestamp) in [100, 200, 300].iter().enumerate() {
+            let mut ram = ram;
+            // the peer's state diverges from the second hash onwards
+            ram[0] += (i > 0) as u8;
+            peer.write_state_hash(state_hash(&ram), timestamp).unwrap();
+        }
+        for _ in 0..3 {
+            peer.read().unwrap();
+        }
+        peer.first_desync()
+    });
+
+    let mut stream = BgbStream::connect_with_extensions(addr, SUPPORTED_EXTENSIONS).unwrap();
+    stream
+        .write(&TypedBgbCommand::Status {
+            running: true,
+            paused: false,
+            support_reconnect: false,
+        })
+        .unwrap();
+    assert_eq!(
+        stream.read().unwrap(),
+        TypedBgbCommand::StateHash {
+            hash: state_hash(&ram),
+            timestamp: 100
+        }
+    );
+    assert_eq!(stream.extensions(), EXTENSION_STATE_HASH);
+    stream.write_state_hash(state_hash(&ram), 100).unwrap();
+    for _ in 0..2 {
+        assert!(matches!(
+            stream.read().unwrap(),
+            TypedBgbCommand::StateHash { .. }
+        ));
+    }
+    assert_eq!(stream.first_desync(), None);
+    stream.write_state_hash(state_hash(&ram), 200).unwrap();
+    stream.write_state_hash(state_hash(&ram), 300).unwrap();
+    assert_eq!(stream.first_desync(), Some(200));
+    assert_eq!(peer.join().unwrap(), Some(200));
+
+    let mut stock = BgbStream::wrap(io::Cursor::new(Vec::new()));
+    assert_eq!(
+        stock.write_state_hash(0, 0).unwrap_err().kind(),
+        io::ErrorKind::Unsupported
+    );
+    assert!(stock.get_ref().get_ref().is_empty());
+
+    let mut detector = DesyncDetector::new();
+    detector.record_remote(5, 1);
+    detector.record_local(6, 2);
+    detector.record_local(5, 1);
+    assert_eq!(detector.first_mismatch(), None);
+    detector.record_remote(6, 3);
+    assert_eq!(detector.first_mismatch(), Some(6));
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn tracing_events() {
+    use super::listener::BgbListener;
+    use super::stream::BgbStream;
+    use crate::commands::*;
+    use std::fmt;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{dispatcher, Dispatch, Event, Metadata, Subscriber};
+
+    /// Collects the message of every event.
+    struct Messages(Arc<Mutex<Vec<String>>>);
+
+    struct MessageVisitor<'a>(&'a mut String);
+
+    impl Visit for MessageVisitor<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            if field.name() == "message" {
+                *self.0 = format!("{:?}", value);
+            }
+        }
+    }
+
+    impl Subscriber for Messages {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, _: &Attributes<'_>) -> Id {
+            Id::from_u64(1)
+        }
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, event: &Event<'_>) {
+            let mut message = String::new();
+            event.record(&mut MessageVisitor(&mut message));
+            self.0.lock().unwrap().push(message);
+        }
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let dispatch = Dispatch::new(Messages(Arc::clone(&messages)));
+
+    let listener = BgbListener::wrap(TcpListener::bind("127.0.0.1:0").unwrap());
+    let addr = listener.local_addr().unwrap();
+    let peer_dispatch = dispatch.clone();
+    let peer = std::thread::spawn(move || {
+        dispatcher::with_default(&peer_dispatch, || {
+            let (mut peer, _) = listener.accept().unwrap();
+            peer.write(&TypedBgbCommand::WantDisconnect).unwrap();
+        })
+    });
+    let outdated = TcpListener::bind("127.0.0.1:0").unwrap();
+    let outdated_addr = outdated.local_addr().unwrap();
+    std::thread::spawn(move || {
+        let (mut peer, _) = outdated.accept().unwrap();
+        peer.write_all(&[1, 1, 3, 0, 0, 0, 0, 0]).unwrap();
+        peer.read_exact(&mut [0; 8]).unwrap();
+    });
+
+    dispatcher::with_default(&dispatch, || {
+        let mut stream = BgbStream::connect(addr).unwrap();
+        assert_eq!(stream.read().unwrap(), TypedBgbCommand::WantDisconnect);
+        assert!(stream.read().is_err());
+        assert!(BgbStream::connect(outdated_addr).is_err());
+    });
+    peer.join().unwrap();
+
+    let messages = messages.lock().unwrap();
+    let count = |message: &str| messages.iter().filter(|m| *m == message).count();
+    // both ends of the first connection plus this end of the second
+    assert_eq!(count("sent"), 3);
+    assert_eq!(count("received"), 3);
+    assert_eq!(count("handshake completed"), 2);
+    assert_eq!(count("disconnecting"), 1);
+    assert_eq!(count("peer asked to disconnect"), 1);
+    assert_eq!(count("peer closed the connection"), 1);
+    assert_eq!(count("bad handshake"), 1);
+}
+
+#[test]
+fn slave_answers() {
+    use crate::commands::*;
+
+    let (mut peer, mut stream) = connected_pair();
+    let sync1 = |data| TypedBgbCommand::Sync1 {
+        data,
+        high_speed: false,
+        double_speed: false,
+        timestamp: 0,
+    };
+    let answered = stream.answer_as_slave(&sync1(0x12), |data| Ok(Some(!data)));
+    assert_eq!(answered.unwrap(), Some((0x12, 0xED)));
+    assert_eq!(peer.read().unwrap(), TypedBgbCommand::Sync2 { data: 0xED });
+    let refused = stream.answer_as_slave(&sync1(0x34), |_| Ok(None));
+    assert_eq!(refused.unwrap(), None);
+    assert_eq!(peer.read().unwrap(), TypedBgbCommand::Sync3Response);
+
+    let timestamp = TypedBgbCommand::Sync3Timestamp { timestamp: 77 };
+    assert_eq!(
+        stream
+            .answer_as_slave(&timestamp, |_| unreachable!())
+            .unwrap(),
+        None
+    );
+    assert_eq!(peer.read().unwrap(), timestamp);
+    // anything else is left to the caller
+    stream
+        .answer_as_slave(&TypedBgbCommand::WantDisconnect, |_| unreachable!())
+        .unwrap();
+    assert_eq!(stream.stats().sent.total_packets(), 4);
+}
@@ -0,0 +1,103 @@
+use super::handshake::HandshakeError;
+use crate::commands::*;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// The span that a connection's events are reported in, when the `tracing` feature is
+/// enabled.
+///
+/// Packets are reported at `TRACE`, disconnects at `DEBUG`, handshakes at `INFO` and anything
+/// the peer got wrong at `WARN`. Without the feature, all of this compiles to nothing.
+#[derive(Clone, Debug)]
+pub(crate) struct ConnectionSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+#[cfg(feature = "tracing")]
+impl ConnectionSpan {
+    pub(crate) fn new(peer: Option<SocketAddr>) -> ConnectionSpan {
+        let span = match peer {
+            Some(peer) => tracing::debug_span!("bgb_connection", %peer),
+            None => tracing::debug_span!("bgb_connection"),
+        };
+        ConnectionSpan { span }
+    }
+
+    pub(crate) fn sent(&self, raw: &RawBgbCommand) {
+        match TypedBgbCommand::from_raw(raw) {
+            Ok(TypedBgbCommand::WantDisconnect) => {
+                tracing::debug!(parent: &self.span, "disconnecting")
+            }
+            Ok(command) => tracing::trace!(parent: &self.span, ?command, "sent"),
+            Err(_) => tracing::trace!(parent: &self.span, ?raw, "sent"),
+        }
+    }
+
+    pub(crate) fn received(&self, raw: &RawBgbCommand) {
+        match TypedBgbCommand::from_raw(raw) {
+            Ok(TypedBgbCommand::WantDisconnect) => {
+                tracing::debug!(parent: &self.span, "peer asked to disconnect")
+            }
+            Ok(command) => tracing::trace!(parent: &self.span, ?command, "received"),
+            Err(e) => tracing::warn!(parent: &self.span, ?raw, error = %e, "malformed packet"),
+        }
+    }
+
+    pub(crate) fn read_failed(&self, error: &io::Error) {
+        if error.kind() == io::ErrorKind::UnexpectedEof {
+            tracing::debug!(parent: &self.span, "peer closed the connection");
+        } else {
+            tracing::debug!(parent: &self.span, %error, "read failed");
+        }
+    }
+
+    pub(crate) fn write_failed(&self, error: &io::Error) {
+        tracing::debug!(parent: &self.span, %error, "write failed");
+    }
+
+    pub(crate) fn handshake(&self, received: &RawBgbCommand, duration: Duration) {
+        let RawBgbCommand { b2, b3, b4, .. } = *received;
+        tracing::info!(
+            parent: &self.span,
+            ?duration,
+            version = %format_args!("{}.{}.{}", b2, b3, b4),
+            "handshake completed"
+        );
+    }
+
+    pub(crate) fn handshake_failed(&self, error: &HandshakeError) {
+        let RawBgbCommand { b1, b2, b3, b4, i1 } = *error.received();
+        tracing::warn!(
+            parent: &self.span,
+            b1,
+            b2,
+            b3,
+            b4,
+            i1,
+            decoded = ?error.decoded(),
+            %error,
+            "bad handshake"
+        );
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl ConnectionSpan {
+    pub(crate) fn new(_peer: Option<SocketAddr>) -> ConnectionSpan {
+        ConnectionSpan {}
+    }
+
+    pub(crate) fn sent(&self, _raw: &RawBgbCommand) {}
+
+    pub(crate) fn received(&self, _raw: &RawBgbCommand) {}
+
+    pub(crate) fn read_failed(&self, _error: &io::Error) {}
+
+    pub(crate) fn write_failed(&self, _error: &io::Error) {}
+
+    pub(crate) fn handshake(&self, _received: &RawBgbCommand, _duration: Duration) {}
+
+    pub(crate) fn handshake_failed(&self, _error: &HandshakeError) {}
+}
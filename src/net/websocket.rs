@@ -0,0 +1,345 @@
+use super::stream::BgbStream;
+use crate::commands::*;
+use std::collections::VecDeque;
+use std::io;
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tungstenite::client::IntoClientRequest;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+/// How long `bridge_to` waits on a client during the WebSocket and BGB handshakes.
+const BRIDGE_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Carries BGB packets as binary WebSocket messages.
+///
+/// Every write is sent as its own binary message, so a `BgbStream` over this transport sends
+/// one 8-byte packet per message. Received binary messages may hold any number of packets.
+/// Text messages are rejected with an error of kind `InvalidData`, and a close message is
+/// treated as the end of the stream.
+#[derive(Debug)]
+pub struct WsTransport<S: Read + Write> {
+    socket: WebSocket<S>,
+    received: VecDeque<u8>,
+}
+
+impl<S: Read + Write> WsTransport<S> {
+    /// Wraps a WebSocket that has already completed its opening handshake.
+    pub fn wrap(socket: WebSocket<S>) -> WsTransport<S> {
+        WsTransport {
+            socket,
+            received: VecDeque::new(),
+        }
+    }
+
+    /// Gets a reference to the underlying WebSocket.
+    pub fn get_ref(&self) -> &WebSocket<S> {
+        &self.socket
+    }
+
+    /// Gets a mutable reference to the underlying WebSocket.
+    pub fn get_mut(&mut self) -> &mut WebSocket<S> {
+        &mut self.socket
+    }
+
+    /// Receives messages until one carries data, returning `false` if the socket was closed.
+    fn receive(&mut self) -> io::Result<bool> {
+        loop {
+            match self.socket.read() {
+                Ok(Message::Binary(data)) => {
+                    self.received.extend(data.iter());
+                    return Ok(true);
+                }
+                Ok(Message::Text(_)) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "text message on BGB WebSocket",
+                    ))
+                }
+                Ok(Message::Close(_)) => return Ok(false),
+                Ok(_) => {}
+                Err(tungstenite::Error::ConnectionClosed) => return Ok(false),
+                Err(e) => return Err(ws_error(e)),
+            }
+        }
+    }
+}
+
+impl<S: Read + Write> Read for WsTransport<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.received.is_empty() && !self.receive()? {
+            return Ok(0);
+        }
+        let len = buf.len().min(self.received.len());
+        for (dst, src) in buf.iter_mut().zip(self.received.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+}
+
+impl<S: Read + Write> Write for WsTransport<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.socket
+            .send(Message::binary(buf.to_vec()))
+            .map_err(ws_error)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.socket.flush().map_err(ws_error)
+    }
+}
+
+impl BgbStream<WsTransport<TcpStream>> {
+    /// Opens a WebSocket connection to the given `ws://` URL and performs the BGB handshake
+    /// over it before returning.
+    ///
+    /// TLS isn't supported, so any other scheme, including `wss://`, returns an error of kind
+    /// `InvalidInput`. Like `connect`, this enables TCP_NODELAY. If the WebSocket handshake
+    /// fails, returns an error of kind `ConnectionRefused`; if the BGB handshake fails, returns
+    /// an error of kind `InvalidData` wrapping a `HandshakeError`.
+    pub fn connect_ws(url: &str) -> io::Result<BgbStream<WsTransport<TcpStream>>> {
+        let request = url.into_client_request().map_err(ws_error)?;
+        if request.uri().scheme_str() != Some("ws") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "only ws:// URLs are supported",
+            ));
+        }
+        let host = request
+            .uri()
+            .host()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "URL has no host"))?;
+        // IPv6 literals keep their brackets in the URL, but can't be resolved with them
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let port = request.uri().port_u16().unwrap_or(80);
+        let inner = TcpStream::connect((host, port))?;
+        inner.set_nodelay(true)?;
+        let peer = inner.peer_addr()?;
+        let (socket, _) = tungstenite::client(request, inner)
+            .map_err(|e| io::Error::new(io::ErrorKind::ConnectionRefused, e.to_string()))?;
+        let mut stream = BgbStream::wrap(WsTransport::wrap(socket));
+        stream.set_peer(peer);
+        stream.handshake()?;
+        Ok(stream)
+    }
+
+    /// Checks if a packet has arrived, and if so, reads and interprets the raw data.
+    /// `ExtensionOffer` packets are skipped as in `read_raw`.
+    ///
+    /// This only returns `None` if the underlying `TcpStream` is nonblocking or has a read
+    /// timeout set. Part of a packet that has arrived stays buffered until the rest does.
+    pub fn maybe_read_raw(&mut self) -> io::Result<Option<RawBgbCommand>> {
+        loop {
+            let transport = self.get_mut();
+            while transport.received.len() < 8 {
+                match transport.receive() {
+                    Ok(true) => {}
+                    Ok(false) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                    Err(e)
+                        if e.kind() == io::ErrorKind::WouldBlock
+                            || e.kind() == io::ErrorKind::TimedOut =>
+                    {
+                        return Ok(None)
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            let mut buf = [0u8; 8];
+            for (dst, src) in buf.iter_mut().zip(transport.received.drain(..8)) {
+                *dst = src;
+            }
+            let raw = RawBgbCommand::deserialize(&buf);
+            if self.record_received(&raw) {
+                return Ok(Some(raw));
+            }
+        }
+    }
+}
+
+/// Accepts BGB connections from WebSocket clients.
+#[derive(Debug)]
+pub struct BgbWsListener {
+    inner: TcpListener,
+}
+
+impl BgbWsListener {
+    /// Wraps the given `TcpListener` and listens for WebSocket connections.
+    pub fn wrap(inner: TcpListener) -> BgbWsListener {
+        BgbWsListener { inner }
+    }
+
+    /// Returns the local socket address of the underlying `TcpListener`.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    /// Accepts a connection and performs both the WebSocket and BGB handshakes before
+    /// returning.
+    ///
+    /// Like `BgbListener::accept`, this sets TCP_NODELAY. If the WebSocket handshake fails,
+    /// returns an error of kind `ConnectionRefused`; if the BGB handshake fails, returns an
+    /// error of kind `InvalidData` wrapping a `HandshakeError`.
+    pub fn accept(&self) -> io::Result<(BgbStream<WsTransport<TcpStream>>, SocketAddr)> {
+        let (stream, addr) = self.inner.accept()?;
+        stream.set_nodelay(true)?;
+        let socket = tungstenite::accept(stream)
+            .map_err(|e| io::Error::new(io::ErrorKind::ConnectionRefused, e.to_string()))?;
+        let mut stream = BgbStream::wrap(WsTransport::wrap(socket));
+        stream.set_peer(addr);
+        stream.handshake()?;
+        Ok((stream, addr))
+    }
+
+    /// Accepts WebSocket clients forever, connecting each one to a native BGB peer at `target`
+    /// and relaying packets between them on new threads.
+    ///
+    /// The handshakes with each client happen on its own thread, and a client that stays
+    /// silent through them is dropped after a few seconds. Packets are forwarded unchanged, so
+    /// the client and the native peer can negotiate extensions with each other.
+    ///
+    /// Only returns if accepting a connection fails. Failures on individual connections are
+    /// ignored.
+    pub fn bridge_to<A: ToSocketAddrs>(&self, target: A) -> io::Result<()> {
+        let target: Vec<SocketAddr> = target.to_socket_addrs()?.collect();
+        loop {
+            let (socket, addr) = self.inner.accept()?;
+            let target = target.clone();
+            thread::spawn(move || -> io::Result<()> {
+                let ws = accept_bridged(socket, addr)?;
+                let tcp = BgbStream::connect(&target[..])?;
+                relay(ws, tcp.into_inner())
+            });
+        }
+    }
+}
+
+/// The TCP connection under a bridged WebSocket, which both threads of `relay` write to.
+///
+/// Each write goes out whole under a lock, so frames from the forwarding thread and the
+/// replies to pings and closes sent by the reading thread never interleave.
+#[derive(Debug)]
+struct RelaySocket {
+    socket: TcpStream,
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl RelaySocket {
+    fn try_clone(&self) -> io::Result<RelaySocket> {
+        Ok(RelaySocket {
+            socket: self.socket.try_clone()?,
+            write_lock: Arc::clone(&self.write_lock),
+        })
+    }
+}
+
+impl Read for RelaySocket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.socket.read(buf)
+    }
+}
+
+impl Write for RelaySocket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let _guard = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        self.socket.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.socket.flush()
+    }
+}
+
+/// Performs both handshakes with a client of `bridge_to`, giving up if it stays silent for
+/// `BRIDGE_HANDSHAKE_TIMEOUT`.
+fn accept_bridged(socket: TcpStream, addr: SocketAddr) -> io::Result<WsTransport<RelaySocket>> {
+    socket.set_nodelay(true)?;
+    socket.set_read_timeout(Some(BRIDGE_HANDSHAKE_TIMEOUT))?;
+    let socket = RelaySocket {
+        socket,
+        write_lock: Arc::new(Mutex::new(())),
+    };
+    let socket = tungstenite::accept(socket)
+        .map_err(|e| io::Error::new(io::ErrorKind::ConnectionRefused, e.to_string()))?;
+    let mut stream = BgbStream::wrap(WsTransport::wrap(socket));
+    stream.set_peer(addr);
+    stream.handshake()?;
+    stream
+        .get_ref()
+        .get_ref()
+        .get_ref()
+        .socket
+        .set_read_timeout(None)?;
+    Ok(stream.into_inner())
+}
+
+/// Forwards packets between a bridged WebSocket client and a native BGB peer until either
+/// one sends `WantDisconnect` or closes the connection, with a thread blocking on each.
+///
+/// Both connections should have completed their handshakes.
+fn relay(ws: WsTransport<RelaySocket>, tcp: TcpStream) -> io::Result<()> {
+    let mut ws_reader = ws;
+    let ws_socket = ws_reader.get_ref().get_ref().try_clone()?;
+    let mut ws_writer = WsTransport::wrap(WebSocket::from_raw_socket(
+        ws_socket.try_clone()?,
+        Role::Server,
+        None,
+    ));
+    let mut tcp_reader = tcp.try_clone()?;
+    let mut tcp_writer = tcp.try_clone()?;
+
+    let (done, finished) = mpsc::channel();
+    let upstream = done.clone();
+    thread::spawn(move || upstream.send(forward(&mut tcp_reader, &mut ws_writer)));
+    thread::spawn(move || done.send(forward(&mut ws_reader, &mut tcp_writer)));
+    let result = finished
+        .recv()
+        .expect("both forwarding threads hang up only after reporting");
+    // wake the other thread, which would otherwise wait on its peer forever
+    let _ = ws_socket.socket.shutdown(Shutdown::Both);
+    let _ = tcp.shutdown(Shutdown::Both);
+    result
+}
+
+/// Copies 8-byte packets from one connection to the other, including the ones that
+/// `BgbStream` would consume itself, until a `WantDisconnect` or the end of the input.
+fn forward(from: &mut impl Read, to: &mut impl Write) -> io::Result<()> {
+    let mut packet = [0u8; 8];
+    loop {
+        match from.read_exact(&mut packet) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        to.write_all(&packet)?;
+        to.flush()?;
+        if is_disconnect(&RawBgbCommand::deserialize(&packet)) {
+            return Ok(());
+        }
+    }
+}
+
+fn is_disconnect(raw: &RawBgbCommand) -> bool {
+    matches!(
+        TypedBgbCommand::from_raw(raw),
+        Ok(TypedBgbCommand::WantDisconnect)
+    )
+}
+
+fn ws_error(e: tungstenite::Error) -> io::Error {
+    match e {
+        tungstenite::Error::Io(e) => e,
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
+            io::Error::new(io::ErrorKind::ConnectionAborted, e)
+        }
+        e => io::Error::other(e),
+    }
+}
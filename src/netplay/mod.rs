@@ -0,0 +1,222 @@
+mod tests;
+
+use crate::commands::*;
+use crate::lockstep::{timestamp, CYCLES_PER_FRAME};
+use crate::net::stream::BgbStream;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+
+/// The number of frames between reading an input and applying it, by default.
+pub const DEFAULT_DELAY: u32 = 2;
+
+/// The inputs to apply to a single frame, as returned by `Netplay::advance`.
+///
+/// Each input has one bit per button, numbered as in `TypedBgbCommand::Joypad`: right, left,
+/// up, down, A, B, select and start, from bit 0 to bit 7.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameInputs {
+    pub frame: u64,
+    pub local: u8,
+    pub remote: u8,
+}
+
+/// The error returned when the peer's inputs stop lining up with this side's frames.
+///
+/// `Netplay::advance` returns this wrapped in an `io::Error` of kind `InvalidData`. Use
+/// `Desync::from_io` to get it back out. It usually means the peers were configured with
+/// different input delays, or that one of them skipped a frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Desync {
+    frame: u64,
+    expected: u32,
+    received: u32,
+}
+
+impl Desync {
+    /// Finds the `Desync` inside an error returned by `Netplay::advance`, if that's why it
+    /// failed.
+    pub fn from_io(error: &io::Error) -> Option<&Desync> {
+        error.get_ref()?.downcast_ref()
+    }
+
+    /// Returns the frame whose inputs the peer was expected to send next.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Returns the timestamp that marks the end of that frame's inputs.
+    pub fn expected(&self) -> u32 {
+        self.expected
+    }
+
+    /// Returns the timestamp the peer sent instead.
+    pub fn received(&self) -> u32 {
+        self.received
+    }
+}
+
+impl fmt::Display for Desync {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "peer sent inputs for timestamp {}, expected frame {} at timestamp {}",
+            self.received, self.frame, self.expected
+        )
+    }
+}
+
+impl Error for Desync {}
+
+impl From<Desync> for io::Error {
+    fn from(error: Desync) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
+
+/// Links two emulators running the same ROM by exchanging only their inputs, for
+/// deterministic netplay.
+///
+/// Each frame, both sides read their local input and pass it to `advance`, which schedules it
+/// for the frame `delay` frames ahead and returns the inputs for the current frame once the
+/// peer's are known. Nothing else is emulated over the link, so both emulators must start
+/// from the same state and use the same input delay.
+///
+/// Inputs are sent as `Joypad` packets for the buttons that changed, followed by a
+/// `Sync3Timestamp` at the start of the frame they belong to, counting `CYCLES_PER_FRAME`
+/// cycles per frame. A peer whose timestamps don't follow on from each other has desynced.
+#[derive(Debug)]
+pub struct Netplay<T: Read + Write> {
+    stream: BgbStream<T>,
+    delay: u32,
+    frame: u64,
+    sent_buttons: u8,
+    local: VecDeque<u8>,
+    remote_buttons: u8,
+    remote_frame: u64,
+    remote: VecDeque<u8>,
+}
+
+impl<T: Read + Write> Netplay<T> {
+    /// Starts netplay over a connection that has completed its handshake, with
+    /// `DEFAULT_DELAY` frames of input delay.
+    pub fn new(stream: BgbStream<T>) -> io::Result<Netplay<T>> {
+        Netplay::with_delay(stream, DEFAULT_DELAY)
+    }
+
+    /// Starts netplay with the given number of frames of input delay, which must be the
+    /// same on both sides.
+    ///
+    /// More delay hides more latency, at the cost of the game responding more slowly. The
+    /// first `delay` frames have no buttons pressed.
+    pub fn with_delay(mut stream: BgbStream<T>, delay: u32) -> io::Result<Netplay<T>> {
+        stream.write(&TypedBgbCommand::Status {
+            running: true,
+            paused: false,
+            support_reconnect: false,
+        })?;
+        let idle = std::iter::repeat_n(0, delay as usize);
+        Ok(Netplay {
+            stream,
+            delay,
+            frame: 0,
+            sent_buttons: 0,
+            local: idle.clone().collect(),
+            remote_buttons: 0,
+            remote_frame: u64::from(delay),
+            remote: idle.collect(),
+        })
+    }
+
+    /// Returns the input delay in frames.
+    pub fn delay(&self) -> u32 {
+        self.delay
+    }
+
+    /// Returns the frame that the next call to `advance` returns the inputs for.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Gets a reference to the underlying stream.
+    pub fn get_ref(&self) -> &BgbStream<T> {
+        &self.stream
+    }
+
+    /// Sends this frame's local input and returns the inputs for the current frame, blocking
+    /// until the peer's have arrived.
+    ///
+    /// Returns `None` once the peer disconnects, and an error wrapping a `Desync` if its
+    /// inputs don't line up with this side's frames.
+    pub fn advance(&mut self, local: u8) -> io::Result<Option<FrameInputs>> {
+        let changed = local ^ self.sent_buttons;
+        let mut commands: Vec<TypedBgbCommand> = (0..8)
+            .filter(|button| changed & 1 << button != 0)
+            .map(|button| TypedBgbCommand::Joypad {
+                button_number: button,
+                pressed: local & 1 << button != 0,
+            })
+            .collect();
+        let scheduled = self.frame + u64::from(self.delay);
+        commands.push(TypedBgbCommand::Sync3Timestamp {
+            timestamp: timestamp(scheduled * CYCLES_PER_FRAME),
+        });
+        self.stream.write_all_commands(&commands)?;
+        self.stream.flush()?;
+        self.sent_buttons = local;
+        self.local.push_back(local);
+
+        while self.remote.is_empty() {
+            let command = match self.stream.read() {
+                Ok(command) => command,
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            match command {
+                TypedBgbCommand::Joypad {
+                    button_number,
+                    pressed,
+                } => {
+                    let bit = 1 << (button_number & 7);
+                    if pressed {
+                        self.remote_buttons |= bit;
+                    } else {
+                        self.remote_buttons &= !bit;
+                    }
+                }
+                TypedBgbCommand::Sync3Timestamp {
+                    timestamp: received,
+                } => {
+                    let expected = timestamp(self.remote_frame * CYCLES_PER_FRAME);
+                    if received != expected {
+                        return Err(Desync {
+                            frame: self.remote_frame,
+                            expected,
+                            received,
+                        }
+                        .into());
+                    }
+                    self.remote.push_back(self.remote_buttons);
+                    self.remote_frame += 1;
+                }
+                TypedBgbCommand::WantDisconnect => return Ok(None),
+                _ => {}
+            }
+        }
+
+        let inputs = FrameInputs {
+            frame: self.frame,
+            local: self.local.pop_front().unwrap_or(0),
+            remote: self.remote.pop_front().unwrap_or(0),
+        };
+        self.frame += 1;
+        Ok(Some(inputs))
+    }
+
+    /// Tells the peer that this side is disconnecting.
+    pub fn disconnect(&mut self) -> io::Result<()> {
+        self.stream.write(&TypedBgbCommand::WantDisconnect)
+    }
+}
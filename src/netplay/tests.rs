@@ -0,0 +1,113 @@
+#[test]
+fn netplay_inputs() {
+    use super::*;
+    use crate::net::tests::connected_pair;
+
+    use std::sync::{Arc, Barrier};
+
+    // presses A on frame 3 and start from frame 5 to 7, or the same shifted by a button
+    fn play(
+        mut netplay: Netplay<std::net::TcpStream>,
+        shift: u8,
+        done: Arc<Barrier>,
+    ) -> Vec<FrameInputs> {
+        let mut frames = Vec::new();
+        for frame in 0..10 {
+            let mut buttons = 0u8;
+            if frame == 3 {
+                buttons |= 1 << 4;
+            }
+            if (5..8).contains(&frame) {
+                buttons |= 1 << 7;
+            }
+            frames.push(netplay.advance(buttons >> shift).unwrap().unwrap());
+        }
+        // neither side hangs up until both have sent every frame
+        done.wait();
+        frames
+    }
+
+    let (a, b) = connected_pair();
+    let a = Netplay::with_delay(a, 3).unwrap();
+    let b = Netplay::with_delay(b, 3).unwrap();
+    let done = Arc::new(Barrier::new(2));
+    let b_done = done.clone();
+    let b = std::thread::spawn(move || play(b, 1, b_done));
+    let a = play(a, 0, done);
+    let b = b.join().unwrap();
+
+    for (a, b) in a.iter().zip(&b) {
+        assert_eq!((a.frame, a.local, a.remote), (b.frame, b.remote, b.local));
+    }
+    let pressed: Vec<(u64, u8, u8)> = a
+        .iter()
+        .filter(|inputs| inputs.local != 0)
+        .map(|inputs| (inputs.frame, inputs.local, inputs.remote))
+        .collect();
+    assert_eq!(pressed, [(6, 0x10, 0x08), (8, 0x80, 0x40), (9, 0x80, 0x40)]);
+}
+
+#[test]
+fn netplay_desync() {
+    use super::*;
+    use crate::lockstep::{timestamp, CYCLES_PER_FRAME};
+    use crate::net::tests::connected_pair;
+
+    let (mut peer, stream) = connected_pair();
+    let mut netplay = Netplay::with_delay(stream, 1).unwrap();
+    assert!(matches!(
+        peer.read().unwrap(),
+        TypedBgbCommand::Status { .. }
+    ));
+
+    // the first frame has no inputs on either side
+    peer.write(&TypedBgbCommand::Joypad {
+        button_number: 5,
+        pressed: true,
+    })
+    .unwrap();
+    peer.write(&TypedBgbCommand::Sync3Timestamp {
+        timestamp: timestamp(CYCLES_PER_FRAME),
+    })
+    .unwrap();
+    assert_eq!(
+        netplay.advance(0x01).unwrap(),
+        Some(FrameInputs {
+            frame: 0,
+            local: 0,
+            remote: 0,
+        })
+    );
+    assert_eq!(
+        peer.read().unwrap(),
+        TypedBgbCommand::Joypad {
+            button_number: 0,
+            pressed: true,
+        }
+    );
+    assert_eq!(
+        peer.read().unwrap(),
+        TypedBgbCommand::Sync3Timestamp {
+            timestamp: timestamp(CYCLES_PER_FRAME),
+        }
+    );
+    assert_eq!(
+        netplay.advance(0x01).unwrap(),
+        Some(FrameInputs {
+            frame: 1,
+            local: 0x01,
+            remote: 0x20,
+        })
+    );
+
+    // the peer skips frame 2
+    peer.write(&TypedBgbCommand::Sync3Timestamp {
+        timestamp: timestamp(3 * CYCLES_PER_FRAME),
+    })
+    .unwrap();
+    let error = netplay.advance(0).unwrap_err();
+    let desync = Desync::from_io(&error).unwrap();
+    assert_eq!(desync.frame(), 2);
+    assert_eq!(desync.expected(), timestamp(2 * CYCLES_PER_FRAME));
+    assert_eq!(desync.received(), timestamp(3 * CYCLES_PER_FRAME));
+}
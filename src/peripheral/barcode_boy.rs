@@ -0,0 +1,91 @@
+use super::{PeripheralLink, SerialDevice};
+use std::io;
+use std::io::{Read, Write};
+
+/// What the game sends to check that a Barcode Boy is connected.
+pub const HANDSHAKE: [u8; 4] = [0x10, 0x07, 0x10, 0x07];
+/// What the Barcode Boy answers the handshake with.
+pub const HANDSHAKE_REPLY: [u8; 4] = [0xFF, 0xFF, 0x10, 0x07];
+/// Sent before the digits of a barcode.
+pub const START_OF_TEXT: u8 = 0x02;
+/// Sent after the digits of a barcode.
+pub const END_OF_TEXT: u8 = 0x03;
+/// The number of digits in a barcode.
+pub const BARCODE_LENGTH: usize = 13;
+
+/// Emulates a Barcode Boy, the card scanner used by a handful of Japanese games.
+///
+/// The game starts by sending `HANDSHAKE`, which the Barcode Boy answers with
+/// `HANDSHAKE_REPLY`. After that the Barcode Boy stays quiet until a card is swiped, when it
+/// drives the clock itself to send the barcode's digits in ASCII between `START_OF_TEXT` and
+/// `END_OF_TEXT`. Cards are swiped with `PeripheralLink::swipe`.
+#[derive(Clone, Debug, Default)]
+pub struct BarcodeBoy {
+    handshake: usize,
+    swipes: Vec<String>,
+}
+
+impl BarcodeBoy {
+    /// Creates a Barcode Boy that hasn't been greeted by the game yet.
+    pub fn new() -> BarcodeBoy {
+        BarcodeBoy::default()
+    }
+
+    /// Returns whether the game has completed the handshake.
+    pub fn connected(&self) -> bool {
+        self.handshake == HANDSHAKE.len()
+    }
+
+    /// Returns every barcode that has been swiped, oldest first.
+    pub fn swipes(&self) -> &[String] {
+        &self.swipes
+    }
+}
+
+impl SerialDevice for BarcodeBoy {
+    fn transfer(&mut self, received: u8) -> u8 {
+        if self.connected() || received != HANDSHAKE[self.handshake] {
+            // a game starting over after a reset gets a fresh handshake
+            self.handshake = 0;
+            if received != HANDSHAKE[0] {
+                return 0xFF;
+            }
+        }
+        let reply = HANDSHAKE_REPLY[self.handshake];
+        self.handshake += 1;
+        reply
+    }
+}
+
+/// Encodes a barcode as the bytes the Barcode Boy sends for it.
+///
+/// Returns an error of kind `InvalidInput` unless the barcode is exactly 13 digits.
+pub fn encode_barcode(barcode: &str) -> io::Result<Vec<u8>> {
+    if barcode.len() != BARCODE_LENGTH || !barcode.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("barcode {:?} is not {} digits", barcode, BARCODE_LENGTH),
+        ));
+    }
+    let mut bytes = Vec::with_capacity(BARCODE_LENGTH + 2);
+    bytes.push(START_OF_TEXT);
+    bytes.extend_from_slice(barcode.as_bytes());
+    bytes.push(END_OF_TEXT);
+    Ok(bytes)
+}
+
+impl<T: Read + Write> PeripheralLink<BarcodeBoy, T> {
+    /// Swipes a card with the given 13-digit barcode through the Barcode Boy, sending it to
+    /// the game.
+    ///
+    /// Returns an error of kind `InvalidInput` if the barcode isn't 13 digits. Games only
+    /// listen for barcodes on certain screens, so each byte is sent again until the game
+    /// accepts it, as described in `PeripheralLink::send`.
+    pub fn swipe(&mut self, barcode: &str) -> io::Result<()> {
+        for byte in encode_barcode(barcode)? {
+            self.send(byte)?;
+        }
+        self.device.swipes.push(barcode.to_string());
+        Ok(())
+    }
+}
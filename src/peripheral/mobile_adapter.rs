@@ -0,0 +1,461 @@
+use super::SerialDevice;
+use std::collections::VecDeque;
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+/// The two bytes that start every packet.
+pub const MAGIC: [u8; 2] = [0x99, 0x66];
+/// What the Game Boy sends while it's waiting for a response.
+pub const GAME_BOY_IDLE: u8 = 0x4B;
+/// What the adapter sends while it has nothing to say.
+pub const ADAPTER_IDLE: u8 = 0xD2;
+/// The device ID the Game Boy sends at the end of its packets.
+pub const GAME_BOY_ID: u8 = 0x81;
+/// The device ID the adapter sends at the end of its packets, which is that of the blue
+/// adapter for PDC phones.
+pub const ADAPTER_ID: u8 = 0x88;
+/// Sent instead of an acknowledgement when a packet's checksum is wrong.
+pub const ACK_BAD_CHECKSUM: u8 = 0xF1;
+/// Sent instead of an acknowledgement when a packet's command isn't recognized.
+pub const ACK_UNKNOWN_COMMAND: u8 = 0xF0;
+
+/// The most data a packet can carry.
+pub const MAX_DATA_LENGTH: usize = 254;
+
+/// The connection ID that `TRANSFER_DATA` uses for the telephone line itself.
+pub const TELEPHONE_CONNECTION: u8 = 0xFF;
+
+/// The size of the adapter's configuration memory.
+pub const CONFIG_LENGTH: usize = 192;
+
+/// The commands the Game Boy can send.
+pub mod command {
+    pub const BEGIN_SESSION: u8 = 0x10;
+    pub const END_SESSION: u8 = 0x11;
+    pub const DIAL_TELEPHONE: u8 = 0x12;
+    pub const HANG_UP_TELEPHONE: u8 = 0x13;
+    pub const WAIT_FOR_TELEPHONE_CALL: u8 = 0x14;
+    pub const TRANSFER_DATA: u8 = 0x15;
+    pub const RESET: u8 = 0x16;
+    pub const TELEPHONE_STATUS: u8 = 0x17;
+    pub const SIO32_MODE: u8 = 0x18;
+    pub const READ_CONFIGURATION_DATA: u8 = 0x19;
+    pub const WRITE_CONFIGURATION_DATA: u8 = 0x1A;
+    /// Sent by the adapter instead of a `TRANSFER_DATA` response once the other end has
+    /// closed the connection.
+    pub const TRANSFER_DATA_END: u8 = 0x1F;
+    pub const ISP_LOGIN: u8 = 0x21;
+    pub const ISP_LOGOUT: u8 = 0x22;
+    pub const OPEN_TCP_CONNECTION: u8 = 0x23;
+    pub const CLOSE_TCP_CONNECTION: u8 = 0x24;
+    pub const OPEN_UDP_CONNECTION: u8 = 0x25;
+    pub const CLOSE_UDP_CONNECTION: u8 = 0x26;
+    pub const DNS_QUERY: u8 = 0x28;
+    /// Sent by the adapter when a command fails, along with the command and an error code.
+    pub const ERROR: u8 = 0x6E;
+}
+
+const SESSION_GREETING: &[u8] = b"NINTENDO";
+const STATUS_IDLE: u8 = 0x00;
+const STATUS_CALL: u8 = 0x05;
+const STATUS_FLAGS: [u8; 2] = [0x4D, 0x00];
+
+/// Error codes sent with `command::ERROR`.
+const ERROR_GENERAL: u8 = 0x01;
+const ERROR_NO_ANSWER: u8 = 0x02;
+const ERROR_NOT_CONNECTED: u8 = 0x03;
+
+/// What a `MobileAdapter` does when a game uses the telephone or the internet.
+///
+/// Connection IDs are chosen by the backend, except for `TELEPHONE_CONNECTION`.
+pub trait MobileBackend {
+    /// Calls the given number, returning whether anyone answered.
+    fn dial(&mut self, number: &str) -> bool;
+
+    /// Ends the current call.
+    fn hang_up(&mut self);
+
+    /// Waits for an incoming call, returning whether one arrived. By default there are
+    /// never any calls.
+    fn wait_for_call(&mut self) -> bool {
+        false
+    }
+
+    /// Logs in to the internet provider over the current call, returning the IP address
+    /// it assigned.
+    fn isp_login(&mut self, id: &[u8], password: &[u8]) -> Option<Ipv4Addr>;
+
+    /// Logs out of the internet provider.
+    fn isp_logout(&mut self);
+
+    /// Opens a TCP connection, returning its ID.
+    fn open_tcp(&mut self, address: SocketAddrV4) -> Option<u8>;
+
+    /// Opens a UDP connection, returning its ID. By default UDP isn't available.
+    fn open_udp(&mut self, address: SocketAddrV4) -> Option<u8> {
+        let _ = address;
+        None
+    }
+
+    /// Closes a TCP or UDP connection.
+    fn close(&mut self, connection: u8);
+
+    /// Sends data over a connection, returning whatever arrived in the meantime, or `None`
+    /// if the other end has closed it. More than `MAX_DATA_LENGTH - 1` bytes at once doesn't
+    /// fit in a packet alongside the connection ID, so the game is sent an error instead.
+    fn transfer(&mut self, connection: u8, data: &[u8]) -> Option<Vec<u8>>;
+
+    /// Looks up a host name, returning its address.
+    fn dns_query(&mut self, name: &[u8]) -> Option<Ipv4Addr>;
+}
+
+/// The part of a packet that a `MobileAdapter` is waiting for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Receiving {
+    Magic(usize),
+    Header,
+    Data,
+    Checksum,
+    DeviceId,
+    Acknowledgement,
+}
+
+/// Emulates a Mobile Adapter GB, forwarding the telephone and internet to a `MobileBackend`.
+///
+/// Every packet starts with `MAGIC`, followed by a header holding the command, a zero byte
+/// and the big-endian length of the data, then the data itself and a big-endian sum of the
+/// header and data. Whoever sends a packet finishes it by sending its device ID and a zero
+/// byte, while the receiver answers with its own device ID and an acknowledgement: the
+/// command with its top bit flipped.
+///
+/// The Game Boy always drives the clock. It sends a command packet while the adapter sends
+/// `ADAPTER_IDLE`, then sends `GAME_BOY_IDLE` while the adapter sends its response packet,
+/// whose command also has its top bit flipped. If the Game Boy answers the response with
+/// `ACK_BAD_CHECKSUM`, the adapter sends it again.
+#[derive(Debug)]
+pub struct MobileAdapter<B: MobileBackend> {
+    backend: B,
+    config: [u8; CONFIG_LENGTH],
+    receiving: Receiving,
+    packet: Vec<u8>,
+    checksum: Vec<u8>,
+    response: VecDeque<u8>,
+    last_response: Vec<u8>,
+    session: bool,
+    in_call: bool,
+}
+
+impl<B: MobileBackend> MobileAdapter<B> {
+    /// Creates an adapter with blank configuration memory.
+    pub fn new(backend: B) -> MobileAdapter<B> {
+        MobileAdapter::with_config(backend, [0; CONFIG_LENGTH])
+    }
+
+    /// Creates an adapter with the given configuration memory, as saved from `config`.
+    pub fn with_config(backend: B, config: [u8; CONFIG_LENGTH]) -> MobileAdapter<B> {
+        MobileAdapter {
+            backend,
+            config,
+            receiving: Receiving::Magic(0),
+            packet: Vec::new(),
+            checksum: Vec::new(),
+            response: VecDeque::new(),
+            last_response: Vec::new(),
+            session: false,
+            in_call: false,
+        }
+    }
+
+    /// Gets a reference to the backend.
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Gets a mutable reference to the backend.
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    /// Returns the configuration memory, which games use to store the user's settings.
+    pub fn config(&self) -> &[u8; CONFIG_LENGTH] {
+        &self.config
+    }
+
+    /// Returns whether a game has begun a session and not yet ended it.
+    pub fn in_session(&self) -> bool {
+        self.session
+    }
+
+    fn receive(&mut self, received: u8) -> u8 {
+        match self.receiving {
+            Receiving::Magic(i) => {
+                self.receiving = if received == MAGIC[i] && i + 1 == MAGIC.len() {
+                    self.packet.clear();
+                    self.checksum.clear();
+                    Receiving::Header
+                } else if received == MAGIC[i] {
+                    Receiving::Magic(i + 1)
+                } else if received == MAGIC[0] {
+                    Receiving::Magic(1)
+                } else {
+                    Receiving::Magic(0)
+                };
+            }
+            Receiving::Header => {
+                self.packet.push(received);
+                if self.packet.len() == 4 {
+                    self.receiving = if self.data_length() == 0 {
+                        Receiving::Checksum
+                    } else {
+                        Receiving::Data
+                    };
+                }
+            }
+            Receiving::Data => {
+                self.packet.push(received);
+                if self.packet.len() == 4 + self.data_length() {
+                    self.receiving = Receiving::Checksum;
+                }
+            }
+            Receiving::Checksum => {
+                self.checksum.push(received);
+                if self.checksum.len() == 2 {
+                    self.receiving = Receiving::DeviceId;
+                }
+            }
+            Receiving::DeviceId => {
+                self.receiving = Receiving::Acknowledgement;
+                return ADAPTER_ID;
+            }
+            Receiving::Acknowledgement => {
+                self.receiving = Receiving::Magic(0);
+                return self.acknowledge();
+            }
+        }
+        ADAPTER_IDLE
+    }
+
+    fn data_length(&self) -> usize {
+        usize::from(u16::from_be_bytes([self.packet[2], self.packet[3]]))
+    }
+
+    fn acknowledge(&mut self) -> u8 {
+        let command = self.packet[0];
+        if checksum(&self.packet) != self.checksum[..] {
+            return ACK_BAD_CHECKSUM;
+        }
+        let data = self.packet.split_off(4);
+        match self.execute(command, &data) {
+            Some((_, data)) if data.len() > MAX_DATA_LENGTH => {
+                self.respond(command::ERROR, &[command, ERROR_GENERAL]);
+                command ^ 0x80
+            }
+            Some((response, data)) => {
+                self.respond(response, &data);
+                command ^ 0x80
+            }
+            None => ACK_UNKNOWN_COMMAND,
+        }
+    }
+
+    /// Carries out a command, returning the command and data to respond with, or `None` if
+    /// the command isn't recognized. A recognized command with malformed data is answered
+    /// with an error packet.
+    fn execute(&mut self, command: u8, data: &[u8]) -> Option<(u8, Vec<u8>)> {
+        let error = |code: u8| Some((command::ERROR, vec![command, code]));
+        let ok = |data: Vec<u8>| Some((command, data));
+        match command {
+            command::BEGIN_SESSION if data == SESSION_GREETING => {
+                self.session = true;
+                ok(data.to_vec())
+            }
+            command::BEGIN_SESSION => error(ERROR_GENERAL),
+            command::END_SESSION => {
+                if self.in_call {
+                    self.backend.hang_up();
+                    self.in_call = false;
+                }
+                self.session = false;
+                ok(Vec::new())
+            }
+            command::DIAL_TELEPHONE => {
+                // the first byte describes the kind of phone, which doesn't matter here
+                let number = String::from_utf8_lossy(data.get(1..).unwrap_or(&[])).into_owned();
+                self.in_call = self.backend.dial(&number);
+                if self.in_call {
+                    ok(Vec::new())
+                } else {
+                    error(ERROR_NO_ANSWER)
+                }
+            }
+            command::HANG_UP_TELEPHONE => {
+                self.backend.hang_up();
+                self.in_call = false;
+                ok(Vec::new())
+            }
+            command::WAIT_FOR_TELEPHONE_CALL => {
+                self.in_call = self.backend.wait_for_call();
+                if self.in_call {
+                    ok(Vec::new())
+                } else {
+                    error(ERROR_GENERAL)
+                }
+            }
+            command::TRANSFER_DATA => {
+                let (&connection, payload) = match data.split_first() {
+                    Some(split) => split,
+                    None => return error(ERROR_GENERAL),
+                };
+                if connection == TELEPHONE_CONNECTION && !self.in_call {
+                    return error(ERROR_NOT_CONNECTED);
+                }
+                match self.backend.transfer(connection, payload) {
+                    Some(mut received) => {
+                        received.insert(0, connection);
+                        ok(received)
+                    }
+                    None => Some((command::TRANSFER_DATA_END, vec![connection])),
+                }
+            }
+            command::RESET => {
+                self.session = false;
+                self.in_call = false;
+                ok(Vec::new())
+            }
+            command::TELEPHONE_STATUS => {
+                let status = if self.in_call {
+                    STATUS_CALL
+                } else {
+                    STATUS_IDLE
+                };
+                ok(vec![status, STATUS_FLAGS[0], STATUS_FLAGS[1]])
+            }
+            // only 8-bit transfers are emulated, but there's no harm in agreeing
+            command::SIO32_MODE => ok(Vec::new()),
+            command::READ_CONFIGURATION_DATA => {
+                let (offset, length) = match *data {
+                    [offset, length] => (usize::from(offset), usize::from(length)),
+                    _ => return error(ERROR_GENERAL),
+                };
+                match self.config.get(offset..offset + length) {
+                    Some(config) => {
+                        let mut response = vec![offset as u8];
+                        response.extend_from_slice(config);
+                        ok(response)
+                    }
+                    None => error(ERROR_GENERAL),
+                }
+            }
+            command::WRITE_CONFIGURATION_DATA => {
+                let (&offset, config) = match data.split_first() {
+                    Some(split) => split,
+                    None => return error(ERROR_GENERAL),
+                };
+                let offset = usize::from(offset);
+                match self.config.get_mut(offset..offset + config.len()) {
+                    Some(target) => {
+                        target.copy_from_slice(config);
+                        ok(vec![offset as u8, config.len() as u8])
+                    }
+                    None => error(ERROR_GENERAL),
+                }
+            }
+            command::ISP_LOGIN => {
+                let fields = length_prefixed(data).and_then(|(id, rest)| {
+                    length_prefixed(rest).map(|(password, dns)| (id, password, dns))
+                });
+                let (id, password, dns) = match fields {
+                    Some((id, password, dns)) if dns.len() == 8 => (id, password, dns),
+                    _ => return error(ERROR_GENERAL),
+                };
+                match self.backend.isp_login(id, password) {
+                    Some(ip) => {
+                        let mut response = ip.octets().to_vec();
+                        response.extend_from_slice(dns);
+                        ok(response)
+                    }
+                    None => error(ERROR_GENERAL),
+                }
+            }
+            command::ISP_LOGOUT => {
+                self.backend.isp_logout();
+                ok(Vec::new())
+            }
+            command::OPEN_TCP_CONNECTION | command::OPEN_UDP_CONNECTION => {
+                let address = match *data {
+                    [a, b, c, d, port_high, port_low] => SocketAddrV4::new(
+                        Ipv4Addr::new(a, b, c, d),
+                        u16::from_be_bytes([port_high, port_low]),
+                    ),
+                    _ => return error(ERROR_GENERAL),
+                };
+                let connection = if command == command::OPEN_TCP_CONNECTION {
+                    self.backend.open_tcp(address)
+                } else {
+                    self.backend.open_udp(address)
+                };
+                match connection {
+                    Some(connection) => ok(vec![connection]),
+                    None => error(ERROR_NOT_CONNECTED),
+                }
+            }
+            command::CLOSE_TCP_CONNECTION | command::CLOSE_UDP_CONNECTION => {
+                let connection = match data.first() {
+                    Some(&connection) => connection,
+                    None => return error(ERROR_GENERAL),
+                };
+                self.backend.close(connection);
+                ok(vec![connection])
+            }
+            command::DNS_QUERY => match self.backend.dns_query(data) {
+                Some(ip) => ok(ip.octets().to_vec()),
+                None => error(ERROR_GENERAL),
+            },
+            _ => None,
+        }
+    }
+
+    fn respond(&mut self, command: u8, data: &[u8]) {
+        let length = (data.len() as u16).to_be_bytes();
+        let mut packet = vec![command ^ 0x80, 0, length[0], length[1]];
+        packet.extend_from_slice(data);
+        self.last_response.clear();
+        self.last_response.extend_from_slice(&MAGIC);
+        self.last_response.extend_from_slice(&packet);
+        self.last_response.extend_from_slice(&checksum(&packet));
+        self.last_response.extend_from_slice(&[ADAPTER_ID, 0]);
+        self.response.extend(&self.last_response);
+    }
+}
+
+impl<B: MobileBackend> SerialDevice for MobileAdapter<B> {
+    fn transfer(&mut self, received: u8) -> u8 {
+        match self.response.pop_front() {
+            Some(byte) => {
+                // the Game Boy acknowledges the response while its last byte is sent
+                if self.response.is_empty() && received == ACK_BAD_CHECKSUM {
+                    self.response.extend(&self.last_response);
+                }
+                byte
+            }
+            None => self.receive(received),
+        }
+    }
+}
+
+/// Returns the big-endian sum of a packet's header and data.
+pub fn checksum(packet: &[u8]) -> [u8; 2] {
+    packet
+        .iter()
+        .fold(0u16, |sum, &byte| sum.wrapping_add(u16::from(byte)))
+        .to_be_bytes()
+}
+
+/// Splits a string prefixed with its length off the front of some data.
+fn length_prefixed(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (&length, rest) = data.split_first()?;
+    let length = usize::from(length);
+    if rest.len() < length {
+        return None;
+    }
+    Some(rest.split_at(length))
+}
@@ -0,0 +1,138 @@
+pub mod barcode_boy;
+pub mod mobile_adapter;
+pub mod pokemon;
+pub mod printer;
+mod tests;
+pub mod tetris;
+
+use crate::commands::*;
+use crate::net::stream::BgbStream;
+use std::io;
+use std::io::{Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long `PeripheralLink::send` waits before trying again when the game isn't ready.
+const RETRY_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Something that can sit on the other end of a link cable and exchange bytes with a game.
+pub trait SerialDevice {
+    /// Exchanges a byte with a game that is driving the clock, returning the byte the game
+    /// receives.
+    ///
+    /// Real hardware has to load its reply before the transfer starts, so a faithful device
+    /// decides each reply from the bytes it received before `received`. Nothing enforces
+    /// this, but games written against real hardware rarely need an immediate answer.
+    fn transfer(&mut self, received: u8) -> u8;
+}
+
+/// What happened during a single call to `PeripheralLink::step`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PeripheralEvent {
+    /// The game clocked a byte and the device answered.
+    Transfer { received: u8, sent: u8 },
+    /// A packet that doesn't involve the device was handled.
+    Other(TypedBgbCommand),
+    /// The remote BGB instance asked to disconnect.
+    Disconnected,
+}
+
+/// Plays the part of a `SerialDevice` for a game running in BGB.
+///
+/// Usually the game drives the clock. Each `Sync1` packet from BGB is passed to the device
+/// and its answer is sent back in a `Sync2`. Devices that drive the clock themselves, like the
+/// Barcode Boy, send bytes to the game with `send`.
+#[derive(Debug)]
+pub struct PeripheralLink<D: SerialDevice, T: Read + Write> {
+    device: D,
+    stream: BgbStream<T>,
+    started: Instant,
+}
+
+impl<D: SerialDevice, T: Read + Write> PeripheralLink<D, T> {
+    /// Attaches the device to an already connected `BgbStream`.
+    pub fn new(device: D, mut stream: BgbStream<T>) -> io::Result<Self> {
+        stream.write(&TypedBgbCommand::Status {
+            running: true,
+            paused: false,
+            support_reconnect: false,
+        })?;
+        Ok(PeripheralLink {
+            device,
+            stream,
+            started: Instant::now(),
+        })
+    }
+
+    /// Gets a reference to the device.
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+
+    /// Gets a mutable reference to the device.
+    pub fn device_mut(&mut self) -> &mut D {
+        &mut self.device
+    }
+
+    /// Handles a single packet from BGB.
+    pub fn step(&mut self) -> io::Result<PeripheralEvent> {
+        let command = self.stream.read()?;
+        self.handle(command)
+    }
+
+    /// Sends a byte to the game using the device's own clock, returning the byte the game
+    /// answered with.
+    ///
+    /// If the game isn't ready for a transfer, the byte is sent again until it is. Packets
+    /// that arrive in the meantime are handled just like in `step`. Returns an error of kind
+    /// `ConnectionAborted` if BGB disconnects first.
+    pub fn send(&mut self, data: u8) -> io::Result<u8> {
+        loop {
+            self.stream.write(&TypedBgbCommand::Sync1 {
+                data,
+                high_speed: false,
+                double_speed: false,
+                timestamp: real_timestamp(self.started.elapsed()),
+            })?;
+            loop {
+                match self.stream.read()? {
+                    TypedBgbCommand::Sync2 { data } => return Ok(data),
+                    TypedBgbCommand::Sync3Response => break,
+                    TypedBgbCommand::WantDisconnect => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::ConnectionAborted,
+                            "BGB disconnected during a transfer",
+                        ))
+                    }
+                    other => {
+                        self.handle(other)?;
+                    }
+                }
+            }
+            thread::sleep(RETRY_INTERVAL);
+        }
+    }
+
+    fn handle(&mut self, command: TypedBgbCommand) -> io::Result<PeripheralEvent> {
+        let device = &mut self.device;
+        let answered = self
+            .stream
+            .answer_as_slave(&command, |data| Ok(Some(device.transfer(data))))?;
+        match (answered, command) {
+            (Some((received, sent)), _) => Ok(PeripheralEvent::Transfer { received, sent }),
+            (None, TypedBgbCommand::WantDisconnect) => Ok(PeripheralEvent::Disconnected),
+            (None, other) => Ok(PeripheralEvent::Other(other)),
+        }
+    }
+
+    /// Calls `step` until BGB disconnects or an error occurs.
+    pub fn run(&mut self) -> io::Result<()> {
+        while self.step()? != PeripheralEvent::Disconnected {}
+        Ok(())
+    }
+
+    /// Consumes the link, returning the device and the stream.
+    pub fn into_inner(self) -> (D, BgbStream<T>) {
+        (self.device, self.stream)
+    }
+}
@@ -0,0 +1,583 @@
+use super::SerialDevice;
+use std::collections::VecDeque;
+use std::io;
+
+/// The length of a trainer name or nickname, including its terminator.
+pub const NAME_LENGTH: usize = 11;
+/// The most Pokémon a party can hold.
+pub const PARTY_LENGTH: usize = 6;
+
+/// What the game driving the clock sends to establish a connection.
+pub const ESTABLISH_INTERNAL_CLOCK: u8 = 0x01;
+/// What the other game answers with to establish a connection.
+pub const ESTABLISH_EXTERNAL_CLOCK: u8 = 0x02;
+/// Sent by both games to agree on the Trade Center.
+pub const TRADE_CENTER: u8 = 0xD4;
+/// Sent by both games to agree on the Colosseum.
+pub const COLOSSEUM: u8 = 0xD5;
+/// Sent to back out of the room selection.
+pub const CANCEL_ROOM: u8 = 0xD6;
+/// Padding sent before each block of data, which the receiver skips.
+pub const PREAMBLE: u8 = 0xFD;
+/// Sent when a game has nothing to say. Never appears in a block's data.
+pub const NO_DATA: u8 = 0xFE;
+/// Padding sent before the mail block in Generation II.
+pub const MAIL_PREAMBLE: u8 = 0x20;
+/// The upper nybble of every byte sent while choosing trades in the Trade Center.
+pub const NYBBLE_EXCHANGE: u8 = 0x60;
+/// The nybble sent instead of a party index to leave the Trade Center.
+pub const LEAVE_NYBBLE: u8 = 0xF;
+/// The nybble sent to accept a trade. Anything else cancels it.
+pub const CONFIRM_NYBBLE: u8 = 0x0;
+
+const TERMINATOR: u8 = 0x50;
+const RN_PREAMBLE_LENGTH: usize = 7;
+const RNS_LENGTH: usize = 10;
+const DATA_PREAMBLE_LENGTH: usize = 6;
+const PATCH_PREAMBLE_LENGTH: usize = 3;
+const PATCH_LIST_LENGTH: usize = 200;
+const PATCH_PART_LENGTH: usize = 0xFC;
+const PATCH_END: u8 = 0xFF;
+const MAIL_PREAMBLE_LENGTH: usize = 5;
+const MAIL_LENGTH: usize = 0x2F;
+
+/// Which games are on the other end of the link.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Generation {
+    /// Red, Green, Blue and Yellow.
+    One,
+    /// Gold, Silver and Crystal.
+    Two,
+}
+
+impl Generation {
+    /// The length of a Pokémon's data in a party.
+    pub fn mon_length(self) -> usize {
+        match self {
+            Generation::One => 44,
+            Generation::Two => 48,
+        }
+    }
+
+    /// The length of the block of party data each game sends.
+    pub fn party_block_length(self) -> usize {
+        self.mons_offset() + PARTY_LENGTH * (self.mon_length() + 2 * NAME_LENGTH)
+    }
+
+    /// Where the Pokémon's data starts in the party block, after the trainer name, the
+    /// species list and, in Generation II, the trainer ID.
+    fn mons_offset(self) -> usize {
+        match self {
+            Generation::One => NAME_LENGTH + 1 + PARTY_LENGTH + 1,
+            Generation::Two => NAME_LENGTH + 1 + PARTY_LENGTH + 1 + 2,
+        }
+    }
+
+    fn level_offset(self) -> usize {
+        match self {
+            Generation::One => 0x21,
+            Generation::Two => 0x1F,
+        }
+    }
+
+    fn moves_offset(self) -> usize {
+        match self {
+            Generation::One => 0x08,
+            Generation::Two => 0x02,
+        }
+    }
+
+    fn ot_id_offset(self) -> usize {
+        match self {
+            Generation::One => 0x0C,
+            Generation::Two => 0x06,
+        }
+    }
+}
+
+/// Encodes text in the games' character set, padded with terminators to `NAME_LENGTH`.
+///
+/// Only letters, digits and spaces are supported; anything else becomes a space. Text that
+/// is too long is cut short to leave room for a terminator.
+pub fn encode_text(text: &str) -> [u8; NAME_LENGTH] {
+    let mut encoded = [TERMINATOR; NAME_LENGTH];
+    for (byte, c) in encoded.iter_mut().zip(text.chars()).take(NAME_LENGTH - 1) {
+        *byte = match c {
+            'A'..='Z' => 0x80 + (c as u8 - b'A'),
+            'a'..='z' => 0xA0 + (c as u8 - b'a'),
+            '0'..='9' => 0xF6 + (c as u8 - b'0'),
+            _ => 0x7F,
+        };
+    }
+    encoded
+}
+
+/// Decodes text in the games' character set up to its terminator, the reverse of
+/// `encode_text`. Unsupported characters become `?`.
+pub fn decode_text(text: &[u8]) -> String {
+    text.iter()
+        .take_while(|&&byte| byte != TERMINATOR)
+        .map(|&byte| match byte {
+            0x80..=0x99 => (b'A' + (byte - 0x80)) as char,
+            0xA0..=0xB9 => (b'a' + (byte - 0xA0)) as char,
+            0xF6..=0xFF => (b'0' + (byte - 0xF6)) as char,
+            0x7F => ' ',
+            _ => '?',
+        })
+        .collect()
+}
+
+/// A Pokémon in a party, as it's sent over the link.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PartyMon {
+    /// The games the data is laid out for.
+    pub generation: Generation,
+    /// The Pokémon's data, `generation.mon_length()` bytes long.
+    pub data: Vec<u8>,
+    /// The name of its original trainer, as encoded by `encode_text`.
+    pub ot_name: [u8; NAME_LENGTH],
+    /// Its nickname, as encoded by `encode_text`.
+    pub nickname: [u8; NAME_LENGTH],
+}
+
+impl PartyMon {
+    /// Returns the species' index number, or `None` if `data` is too short to hold it.
+    pub fn species(&self) -> Option<u8> {
+        self.data.first().copied()
+    }
+
+    /// Returns the Pokémon's level, or `None` if `data` is too short to hold it.
+    pub fn level(&self) -> Option<u8> {
+        self.data.get(self.generation.level_offset()).copied()
+    }
+
+    /// Returns the index numbers of its moves, with zero for empty slots, or `None` if
+    /// `data` is too short to hold them.
+    pub fn moves(&self) -> Option<[u8; 4]> {
+        let offset = self.generation.moves_offset();
+        let mut moves = [0; 4];
+        moves.copy_from_slice(self.data.get(offset..offset + 4)?);
+        Some(moves)
+    }
+
+    /// Returns the ID of its original trainer, or `None` if `data` is too short to hold it.
+    pub fn ot_id(&self) -> Option<u16> {
+        let offset = self.generation.ot_id_offset();
+        let id = self.data.get(offset..offset + 2)?;
+        Some(u16::from_be_bytes([id[0], id[1]]))
+    }
+}
+
+/// A trainer's party, as it's sent over the link.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Party {
+    /// The games the party is laid out for.
+    pub generation: Generation,
+    /// The trainer's name, as encoded by `encode_text`.
+    pub trainer_name: [u8; NAME_LENGTH],
+    /// The trainer's ID. Only sent separately in Generation II.
+    pub trainer_id: u16,
+    /// Up to `PARTY_LENGTH` Pokémon.
+    pub mons: Vec<PartyMon>,
+}
+
+impl Party {
+    /// Checks that the party can be sent: it holds at most `PARTY_LENGTH` Pokémon, each
+    /// laid out for the party's generation with `mon_length()` bytes of data.
+    ///
+    /// Returns an error of kind `InvalidInput` describing the first problem found.
+    pub fn validate(&self) -> io::Result<()> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+        if self.mons.len() > PARTY_LENGTH {
+            return Err(invalid(format!(
+                "a party holds at most {} Pokémon, not {}",
+                PARTY_LENGTH,
+                self.mons.len()
+            )));
+        }
+        for (i, mon) in self.mons.iter().enumerate() {
+            if mon.generation != self.generation {
+                return Err(invalid(format!(
+                    "Pokémon {} is laid out for {:?}, not {:?}",
+                    i, mon.generation, self.generation
+                )));
+            }
+            if mon.data.len() != self.generation.mon_length() {
+                return Err(invalid(format!(
+                    "Pokémon {} has {} bytes of data, expected {}",
+                    i,
+                    mon.data.len(),
+                    self.generation.mon_length()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Lays the party out as a party block, before any bytes are patched out.
+    ///
+    /// Returns an error of kind `InvalidInput` if the party fails `validate`.
+    pub fn encode(&self) -> io::Result<Vec<u8>> {
+        self.validate()?;
+        let generation = self.generation;
+        let mon_length = generation.mon_length();
+        let mut block = vec![0; generation.party_block_length()];
+        block[..NAME_LENGTH].copy_from_slice(&self.trainer_name);
+        block[NAME_LENGTH] = self.mons.len() as u8;
+        let species = &mut block[NAME_LENGTH + 1..NAME_LENGTH + PARTY_LENGTH + 2];
+        for byte in species.iter_mut() {
+            *byte = 0xFF;
+        }
+        for (byte, mon) in species.iter_mut().zip(&self.mons) {
+            *byte = mon.data[0];
+        }
+        if generation == Generation::Two {
+            let offset = NAME_LENGTH + PARTY_LENGTH + 2;
+            block[offset..offset + 2].copy_from_slice(&self.trainer_id.to_be_bytes());
+        }
+
+        let mons = generation.mons_offset();
+        let ot_names = mons + PARTY_LENGTH * mon_length;
+        let nicknames = ot_names + PARTY_LENGTH * NAME_LENGTH;
+        for (i, mon) in self.mons.iter().enumerate() {
+            let data = mons + i * mon_length;
+            block[data..data + mon_length].copy_from_slice(&mon.data);
+            let ot_name = ot_names + i * NAME_LENGTH;
+            block[ot_name..ot_name + NAME_LENGTH].copy_from_slice(&mon.ot_name);
+            let nickname = nicknames + i * NAME_LENGTH;
+            block[nickname..nickname + NAME_LENGTH].copy_from_slice(&mon.nickname);
+        }
+        Ok(block)
+    }
+
+    /// Reads a party block that has already had its patches applied, returning `None` if
+    /// it's the wrong length or claims more than `PARTY_LENGTH` Pokémon.
+    pub fn decode(generation: Generation, block: &[u8]) -> Option<Party> {
+        if block.len() != generation.party_block_length()
+            || block[NAME_LENGTH] as usize > PARTY_LENGTH
+        {
+            return None;
+        }
+        let mon_length = generation.mon_length();
+        let mons = generation.mons_offset();
+        let ot_names = mons + PARTY_LENGTH * mon_length;
+        let nicknames = ot_names + PARTY_LENGTH * NAME_LENGTH;
+        let name = |offset: usize| {
+            let mut name = [0; NAME_LENGTH];
+            name.copy_from_slice(&block[offset..offset + NAME_LENGTH]);
+            name
+        };
+        let trainer_id = match generation {
+            Generation::One => 0,
+            Generation::Two => {
+                let offset = NAME_LENGTH + PARTY_LENGTH + 2;
+                u16::from_be_bytes([block[offset], block[offset + 1]])
+            }
+        };
+        Some(Party {
+            generation,
+            trainer_name: name(0),
+            trainer_id,
+            mons: (0..block[NAME_LENGTH] as usize)
+                .map(|i| PartyMon {
+                    generation,
+                    data: block[mons + i * mon_length..mons + (i + 1) * mon_length].to_vec(),
+                    ot_name: name(ot_names + i * NAME_LENGTH),
+                    nickname: name(nicknames + i * NAME_LENGTH),
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Replaces every `NO_DATA` byte in the Pokémon data of a party block, which the games
+/// can't send, and returns the patch list that undoes it.
+///
+/// The data is split into parts of 252 bytes. For each part, the patch list holds one more
+/// than the offset of each replaced byte, followed by `0xFF`.
+pub fn patch(generation: Generation, block: &mut [u8]) -> Vec<u8> {
+    let mons = generation.mons_offset();
+    let data = &mut block[mons..mons + PARTY_LENGTH * generation.mon_length()];
+    let mut patches = Vec::with_capacity(PATCH_LIST_LENGTH);
+    for part in data.chunks_mut(PATCH_PART_LENGTH) {
+        for (offset, byte) in part.iter_mut().enumerate() {
+            if *byte == NO_DATA {
+                *byte = 0xFF;
+                patches.push(offset as u8 + 1);
+            }
+        }
+        patches.push(PATCH_END);
+    }
+    patches.resize(PATCH_LIST_LENGTH, 0);
+    patches
+}
+
+/// Restores the bytes that `patch` replaced, given its patch list.
+///
+/// The patch list comes from the other game, so entries that don't point into the data,
+/// including zero, are skipped, and a block too short to hold a party is left alone.
+pub fn unpatch(generation: Generation, block: &mut [u8], patches: &[u8]) {
+    let mons = generation.mons_offset();
+    let data = match block.get_mut(mons..mons + PARTY_LENGTH * generation.mon_length()) {
+        Some(data) => data,
+        None => return,
+    };
+    let mut patches = patches.iter();
+    for part in data.chunks_mut(PATCH_PART_LENGTH) {
+        for &entry in patches.by_ref().take_while(|&&entry| entry != PATCH_END) {
+            let byte = usize::from(entry)
+                .checked_sub(1)
+                .and_then(|offset| part.get_mut(offset));
+            if let Some(byte) = byte {
+                *byte = NO_DATA;
+            }
+        }
+    }
+}
+
+/// A trade completed by a `TradeBot`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trade {
+    /// The Pokémon the bot gave away.
+    pub sent: PartyMon,
+    /// The Pokémon the bot received.
+    pub received: PartyMon,
+}
+
+/// How far a `TradeBot` has gotten with the game.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TradeState {
+    /// Waiting for the game to establish a connection.
+    Connecting,
+    /// Waiting for the player to pick the Trade Center.
+    Menu,
+    /// Exchanging random numbers, parties, patch lists and, in Generation II, mail.
+    Exchanging,
+    /// Waiting for the player to pick a Pokémon to trade.
+    Selecting,
+    /// Waiting for the player to confirm or cancel the trade.
+    Confirming,
+}
+
+/// Trades with a Generation I or II Pokémon game through the Cable Club.
+///
+/// The game must be the one to talk to the Cable Club attendant, so that it drives the
+/// clock. The bot agrees to the Trade Center and refuses the Colosseum, sends its party while
+/// receiving the player's, and then offers the Pokémon chosen with `set_offer` for whatever
+/// the player picks. Completed trades update both parties and are recorded in `trades`.
+///
+/// Choices in the Trade Center are made by exchanging a nybble in the lower half of bytes
+/// starting with `NYBBLE_EXCHANGE`. Each exchange lasts until the game sends something else.
+#[derive(Clone, Debug)]
+pub struct TradeBot {
+    party: Party,
+    offer: usize,
+    state: TradeState,
+    room_agreed: bool,
+    outgoing: VecDeque<u8>,
+    expected: VecDeque<usize>,
+    block: Vec<u8>,
+    blocks: Vec<Vec<u8>>,
+    peer_party: Option<Party>,
+    nybble: Option<u8>,
+    choice: Option<usize>,
+    trades: Vec<Trade>,
+}
+
+impl TradeBot {
+    /// Creates a bot that offers the first Pokémon in its party.
+    ///
+    /// Returns an error of kind `InvalidInput` if the party fails `Party::validate`.
+    pub fn new(party: Party) -> io::Result<TradeBot> {
+        party.validate()?;
+        Ok(TradeBot {
+            party,
+            offer: 0,
+            state: TradeState::Connecting,
+            room_agreed: false,
+            outgoing: VecDeque::new(),
+            expected: VecDeque::new(),
+            block: Vec::new(),
+            blocks: Vec::new(),
+            peer_party: None,
+            nybble: None,
+            choice: None,
+            trades: Vec::new(),
+        })
+    }
+
+    /// Returns how far the bot has gotten with the game.
+    pub fn state(&self) -> TradeState {
+        self.state
+    }
+
+    /// Returns the bot's party, including any Pokémon it has traded for.
+    pub fn party(&self) -> &Party {
+        &self.party
+    }
+
+    /// Returns the player's party once it has been received.
+    pub fn peer_party(&self) -> Option<&Party> {
+        self.peer_party.as_ref()
+    }
+
+    /// Sets which Pokémon in the bot's party is offered in the next trade.
+    ///
+    /// Indexes past the end of the party are clamped to the last Pokémon.
+    pub fn set_offer(&mut self, index: usize) {
+        self.offer = index.min(self.party.mons.len().saturating_sub(1));
+    }
+
+    /// Returns the trades completed so far.
+    pub fn trades(&self) -> &[Trade] {
+        &self.trades
+    }
+
+    fn menu(&mut self, received: u8) -> u8 {
+        match received {
+            ESTABLISH_INTERNAL_CLOCK => ESTABLISH_EXTERNAL_CLOCK,
+            TRADE_CENTER => {
+                self.room_agreed = true;
+                TRADE_CENTER
+            }
+            COLOSSEUM | CANCEL_ROOM => {
+                self.room_agreed = false;
+                CANCEL_ROOM
+            }
+            PREAMBLE if self.room_agreed => {
+                self.start_exchange();
+                self.exchange(received)
+            }
+            _ => NO_DATA,
+        }
+    }
+
+    fn start_exchange(&mut self) {
+        let generation = self.party.generation;
+        // the party was validated by `new`, and trades only swap in Pokémon decoded for the
+        // same generation
+        let mut party = self
+            .party
+            .encode()
+            .expect("the bot's party is always valid");
+        let patches = patch(generation, &mut party);
+        self.outgoing.clear();
+        self.outgoing
+            .extend(std::iter::repeat_n(PREAMBLE, RN_PREAMBLE_LENGTH));
+        self.outgoing.extend(std::iter::repeat_n(0, RNS_LENGTH));
+        self.outgoing
+            .extend(std::iter::repeat_n(PREAMBLE, DATA_PREAMBLE_LENGTH));
+        self.outgoing.extend(party);
+        self.outgoing
+            .extend(std::iter::repeat_n(PREAMBLE, PATCH_PREAMBLE_LENGTH));
+        self.outgoing.extend(patches);
+        self.expected = vec![
+            RNS_LENGTH,
+            generation.party_block_length(),
+            PATCH_LIST_LENGTH,
+        ]
+        .into();
+        if generation == Generation::Two {
+            self.outgoing
+                .extend(std::iter::repeat_n(MAIL_PREAMBLE, MAIL_PREAMBLE_LENGTH));
+            self.outgoing
+                .extend(std::iter::repeat_n(0, PARTY_LENGTH * MAIL_LENGTH));
+            self.expected.push_back(PARTY_LENGTH * MAIL_LENGTH);
+        }
+        self.block.clear();
+        self.blocks.clear();
+        self.state = TradeState::Exchanging;
+    }
+
+    fn exchange(&mut self, received: u8) -> u8 {
+        let sent = self.outgoing.pop_front().unwrap_or(NO_DATA);
+        let skipping = self.block.is_empty()
+            && (received == PREAMBLE || received == NO_DATA || received == MAIL_PREAMBLE);
+        if !skipping {
+            self.block.push(received);
+        }
+        if Some(&self.block.len()) == self.expected.front() {
+            self.expected.pop_front();
+            self.blocks.push(std::mem::take(&mut self.block));
+        }
+        if self.expected.is_empty() {
+            self.finish_exchange();
+        }
+        sent
+    }
+
+    fn finish_exchange(&mut self) {
+        let generation = self.party.generation;
+        let mut party = std::mem::take(&mut self.blocks[1]);
+        unpatch(generation, &mut party, &self.blocks[2]);
+        self.peer_party = Party::decode(generation, &party);
+        self.outgoing.clear();
+        self.blocks.clear();
+        self.nybble = None;
+        self.state = TradeState::Selecting;
+    }
+
+    fn trade_center(&mut self, received: u8) -> u8 {
+        if received & 0xF0 == NYBBLE_EXCHANGE {
+            self.nybble = Some(received & 0x0F);
+            return match self.state {
+                TradeState::Selecting => NYBBLE_EXCHANGE | self.offer as u8,
+                _ => NYBBLE_EXCHANGE | CONFIRM_NYBBLE,
+            };
+        }
+        if let Some(nybble) = self.nybble.take() {
+            self.finish_nybble(nybble);
+        }
+        // anything else keeps the games in step, so it's sent straight back
+        received
+    }
+
+    fn finish_nybble(&mut self, nybble: u8) {
+        let peer_count = self.peer_party.as_ref().map_or(0, |party| party.mons.len());
+        match self.state {
+            TradeState::Selecting if nybble == LEAVE_NYBBLE => {
+                self.room_agreed = false;
+                self.state = TradeState::Menu;
+            }
+            TradeState::Selecting if (nybble as usize) < peer_count => {
+                self.choice = Some(nybble as usize);
+                self.state = TradeState::Confirming;
+            }
+            TradeState::Confirming => {
+                if let (CONFIRM_NYBBLE, Some(choice)) = (nybble, self.choice) {
+                    self.trade(choice);
+                }
+                self.choice = None;
+                self.state = TradeState::Selecting;
+            }
+            _ => {}
+        }
+    }
+
+    fn trade(&mut self, choice: usize) {
+        let (sent, received) = match (self.party.mons.get_mut(self.offer), &mut self.peer_party) {
+            (Some(sent), Some(peer)) => (sent, &mut peer.mons[choice]),
+            _ => return,
+        };
+        std::mem::swap(sent, received);
+        self.trades.push(Trade {
+            sent: received.clone(),
+            received: sent.clone(),
+        });
+    }
+}
+
+impl SerialDevice for TradeBot {
+    fn transfer(&mut self, received: u8) -> u8 {
+        match self.state {
+            TradeState::Connecting if received == ESTABLISH_INTERNAL_CLOCK => {
+                self.state = TradeState::Menu;
+                ESTABLISH_EXTERNAL_CLOCK
+            }
+            TradeState::Connecting => NO_DATA,
+            TradeState::Menu => self.menu(received),
+            TradeState::Exchanging => self.exchange(received),
+            TradeState::Selecting | TradeState::Confirming => self.trade_center(received),
+        }
+    }
+}
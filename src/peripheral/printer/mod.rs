@@ -0,0 +1,283 @@
+#[cfg(feature = "image")]
+pub mod png;
+
+use super::SerialDevice;
+
+/// The two bytes that start every packet.
+pub const MAGIC: [u8; 2] = [0x88, 0x33];
+/// What the printer sends in place of the first byte after a packet's checksum.
+pub const DEVICE_ID: u8 = 0x81;
+
+/// Clears any image data and errors.
+pub const COMMAND_INIT: u8 = 0x01;
+/// Prints the image data received so far.
+pub const COMMAND_PRINT: u8 = 0x02;
+/// Sends up to two rows of tiles. An empty packet marks the end of the image data.
+pub const COMMAND_DATA: u8 = 0x04;
+/// Cancels a print and clears the image data.
+pub const COMMAND_BREAK: u8 = 0x08;
+/// Asks for the printer's status without doing anything else.
+pub const COMMAND_STATUS: u8 = 0x0F;
+
+/// Set in the status byte when a packet's checksum was wrong.
+pub const STATUS_CHECKSUM_ERROR: u8 = 1 << 0;
+/// Set in the status byte while the printer is printing.
+pub const STATUS_BUSY: u8 = 1 << 1;
+/// Set in the status byte while there's image data that hasn't been printed.
+pub const STATUS_UNPROCESSED: u8 = 1 << 3;
+/// Set in the status byte when a packet's command wasn't recognized.
+pub const STATUS_PACKET_ERROR: u8 = 1 << 4;
+
+/// The width of a print in pixels.
+pub const WIDTH: usize = 160;
+/// The number of bytes in a row of 20 tiles.
+const TILE_ROW_LENGTH: usize = WIDTH / 8 * 16;
+/// The palette games mean when they send a palette of 0.
+const DEFAULT_PALETTE: u8 = 0xE4;
+
+/// An image that a game asked the printer to print.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Print {
+    /// The image data, as rows of 20 tiles in the Game Boy's 2bpp format.
+    pub data: Vec<u8>,
+    /// The number of copies, where 0 only feeds the paper.
+    pub sheets: u8,
+    /// The number of blank feeds before the image.
+    pub margin_before: u8,
+    /// The number of blank feeds after the image. Games that print a long image over several
+    /// packets only leave a margin after the last one.
+    pub margin_after: u8,
+    /// Maps each of the image's colors to a shade, two bits each starting from color 0.
+    pub palette: u8,
+    /// How dark to print, from 0 for lightest to `0x7F` for darkest, with `0x40` as normal.
+    pub exposure: u8,
+}
+
+impl Print {
+    /// Returns the height of the image in pixels.
+    pub fn height(&self) -> usize {
+        self.data.len() / TILE_ROW_LENGTH * 8
+    }
+
+    /// Decodes the image into one shade per pixel, from 0 for white to 3 for black, row by
+    /// row.
+    pub fn shades(&self) -> Vec<u8> {
+        let palette = if self.palette == 0 {
+            DEFAULT_PALETTE
+        } else {
+            self.palette
+        };
+        let mut shades = Vec::with_capacity(WIDTH * self.height());
+        for y in 0..self.height() {
+            let row = &self.data[y / 8 * TILE_ROW_LENGTH..];
+            for x in 0..WIDTH {
+                let offset = x / 8 * 16 + y % 8 * 2;
+                let bit = 7 - x % 8;
+                let color = (row[offset] >> bit & 1) | (row[offset + 1] >> bit & 1) << 1;
+                shades.push(palette >> (color * 2) & 3);
+            }
+        }
+        shades
+    }
+}
+
+/// Groups prints into the sheets of paper they came out on.
+///
+/// Each sheet ends with a print that leaves a margin after it, so that a long image sent as
+/// several prints ends up on one sheet.
+pub fn sheets(prints: &[Print]) -> Vec<&[Print]> {
+    prints
+        .split_inclusive(|print| print.margin_after > 0)
+        .collect()
+}
+
+/// Decompresses image data sent with compression enabled.
+///
+/// Each run starts with a byte that is either 0 to `0x7F` for that many plus one literal
+/// bytes, or `0x80` and up for the next byte repeated that minus `0x80` plus two times.
+pub fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut bytes = data.iter();
+    while let Some(&control) = bytes.next() {
+        if control & 0x80 != 0 {
+            if let Some(&byte) = bytes.next() {
+                let count = usize::from(control & 0x7F) + 2;
+                output.extend(std::iter::repeat_n(byte, count));
+            }
+        } else {
+            output.extend(bytes.by_ref().take(usize::from(control) + 1));
+        }
+    }
+    output
+}
+
+/// The part of a packet that a `GameBoyPrinter` is waiting for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Receiving {
+    Magic(usize),
+    Header,
+    Data,
+    Checksum,
+    Alive,
+    Status,
+}
+
+/// Emulates a Game Boy Printer, keeping what it prints for inspection.
+///
+/// Every packet starts with `MAGIC`, followed by the command, whether the data is compressed,
+/// the little-endian length of the data, the data itself and a little-endian sum of
+/// everything after the magic bytes. The game then sends two zero bytes, to which the printer
+/// answers with `DEVICE_ID` and its status. It answers every other byte with 0.
+///
+/// Printing takes no time, but the first status after a print still reports `STATUS_BUSY`
+/// since games wait to see it.
+#[derive(Clone, Debug)]
+pub struct GameBoyPrinter {
+    receiving: Receiving,
+    header: Vec<u8>,
+    data: Vec<u8>,
+    checksum: Vec<u8>,
+    image: Vec<u8>,
+    status: u8,
+    busy: bool,
+    prints: Vec<Print>,
+}
+
+impl Default for GameBoyPrinter {
+    fn default() -> Self {
+        GameBoyPrinter::new()
+    }
+}
+
+impl GameBoyPrinter {
+    /// Creates a printer with nothing printed yet.
+    pub fn new() -> GameBoyPrinter {
+        GameBoyPrinter {
+            receiving: Receiving::Magic(0),
+            header: Vec::new(),
+            data: Vec::new(),
+            checksum: Vec::new(),
+            image: Vec::new(),
+            status: 0,
+            busy: false,
+            prints: Vec::new(),
+        }
+    }
+
+    /// Returns everything printed so far, oldest first.
+    pub fn prints(&self) -> &[Print] {
+        &self.prints
+    }
+
+    /// Removes and returns everything printed so far.
+    pub fn take_prints(&mut self) -> Vec<Print> {
+        std::mem::take(&mut self.prints)
+    }
+
+    fn data_length(&self) -> usize {
+        usize::from(u16::from_le_bytes([self.header[2], self.header[3]]))
+    }
+
+    fn execute(&mut self) {
+        let sum = self
+            .header
+            .iter()
+            .chain(&self.data)
+            .fold(0u16, |sum, &byte| sum.wrapping_add(u16::from(byte)));
+        if sum.to_le_bytes() != self.checksum[..] {
+            self.status |= STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !(STATUS_CHECKSUM_ERROR | STATUS_PACKET_ERROR);
+        match self.header[0] {
+            COMMAND_INIT => {
+                self.image.clear();
+                self.status = 0;
+                self.busy = false;
+            }
+            COMMAND_DATA if self.header[1] != 0 => self.image.extend(decompress(&self.data)),
+            COMMAND_DATA => self.image.extend_from_slice(&self.data),
+            COMMAND_PRINT => match self.data[..] {
+                [sheets, margins, palette, exposure] => {
+                    self.prints.push(Print {
+                        data: std::mem::take(&mut self.image),
+                        sheets,
+                        margin_before: margins >> 4,
+                        margin_after: margins & 0xF,
+                        palette,
+                        exposure: exposure & 0x7F,
+                    });
+                    self.busy = true;
+                }
+                _ => self.status |= STATUS_PACKET_ERROR,
+            },
+            COMMAND_BREAK => self.image.clear(),
+            COMMAND_STATUS => {}
+            _ => self.status |= STATUS_PACKET_ERROR,
+        }
+    }
+
+    fn status(&mut self) -> u8 {
+        let mut status = self.status;
+        if !self.image.is_empty() {
+            status |= STATUS_UNPROCESSED;
+        }
+        if self.header[0] == COMMAND_STATUS && std::mem::take(&mut self.busy) {
+            status |= STATUS_BUSY;
+        }
+        status
+    }
+}
+
+impl SerialDevice for GameBoyPrinter {
+    fn transfer(&mut self, received: u8) -> u8 {
+        match self.receiving {
+            Receiving::Magic(i) => {
+                self.receiving = if received == MAGIC[i] && i + 1 == MAGIC.len() {
+                    self.header.clear();
+                    self.data.clear();
+                    self.checksum.clear();
+                    Receiving::Header
+                } else if received == MAGIC[i] {
+                    Receiving::Magic(i + 1)
+                } else if received == MAGIC[0] {
+                    Receiving::Magic(1)
+                } else {
+                    Receiving::Magic(0)
+                };
+            }
+            Receiving::Header => {
+                self.header.push(received);
+                if self.header.len() == 4 {
+                    self.receiving = if self.data_length() == 0 {
+                        Receiving::Checksum
+                    } else {
+                        Receiving::Data
+                    };
+                }
+            }
+            Receiving::Data => {
+                self.data.push(received);
+                if self.data.len() == self.data_length() {
+                    self.receiving = Receiving::Checksum;
+                }
+            }
+            Receiving::Checksum => {
+                self.checksum.push(received);
+                if self.checksum.len() == 2 {
+                    self.execute();
+                    self.receiving = Receiving::Alive;
+                }
+            }
+            Receiving::Alive => {
+                self.receiving = Receiving::Status;
+                return DEVICE_ID;
+            }
+            Receiving::Status => {
+                self.receiving = Receiving::Magic(0);
+                return self.status();
+            }
+        }
+        0
+    }
+}
@@ -0,0 +1,63 @@
+use super::{Print, WIDTH};
+use image::codecs::png::PngEncoder;
+use image::{ExtendedColorType, GrayImage, ImageEncoder, ImageResult};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// The number of blank pixel rows drawn for each feed of margin.
+pub const MARGIN_ROWS: u32 = 16;
+
+/// How dark each shade is at normal exposure, from white to black.
+const DARKNESS: [u32; 4] = [0, 85, 170, 255];
+/// The exposure at which shades are printed as they are in `DARKNESS`. Each step away from
+/// it makes shades darker or lighter by a quarter of this, so exposure `0x00` prints them at
+/// 75% and `0x7F` at nearly 125%.
+const NORMAL_EXPOSURE: u32 = 0x40;
+
+/// Draws a sheet of prints, as grouped by `sheets`, as a single grayscale image.
+///
+/// Each print is drawn with its own palette and exposure, below its margin before and above
+/// its margin after.
+pub fn render(sheet: &[Print]) -> GrayImage {
+    let height = sheet
+        .iter()
+        .map(|print| {
+            (u32::from(print.margin_before) + u32::from(print.margin_after)) * MARGIN_ROWS
+                + print.height() as u32
+        })
+        .sum();
+    let mut image = GrayImage::from_pixel(WIDTH as u32, height, [255].into());
+    let mut y = 0;
+    for print in sheet {
+        y += u32::from(print.margin_before) * MARGIN_ROWS;
+        let exposure = 3 * NORMAL_EXPOSURE + u32::from(print.exposure & 0x7F);
+        for (i, shade) in print.shades().into_iter().enumerate() {
+            let darkness =
+                (DARKNESS[usize::from(shade)] * exposure / (4 * NORMAL_EXPOSURE)).min(255);
+            let (x, row) = ((i % WIDTH) as u32, (i / WIDTH) as u32);
+            image.put_pixel(x, y + row, [255 - darkness as u8].into());
+        }
+        y += print.height() as u32 + u32::from(print.margin_after) * MARGIN_ROWS;
+    }
+    image
+}
+
+/// Draws a sheet of prints with `render` and encodes it as a PNG.
+pub fn write_png<W: Write>(sheet: &[Print], writer: W) -> ImageResult<()> {
+    let image = render(sheet);
+    PngEncoder::new(writer).write_image(
+        image.as_raw(),
+        image.width(),
+        image.height(),
+        ExtendedColorType::L8,
+    )
+}
+
+/// Draws a sheet of prints with `render` and saves it as a PNG file.
+pub fn save_png<P: AsRef<Path>>(sheet: &[Print], path: P) -> ImageResult<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_png(sheet, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
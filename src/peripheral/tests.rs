@@ -0,0 +1,710 @@
+#[cfg(test)]
+fn test_party(
+    generation: super::pokemon::Generation,
+    name: &str,
+    species: &[u8],
+) -> super::pokemon::Party {
+    use super::pokemon::*;
+
+    Party {
+        generation,
+        trainer_name: encode_text(name),
+        // Generation I doesn't send the trainer ID outside of the Pokémon's data
+        trainer_id: if generation == Generation::Two {
+            1234
+        } else {
+            0
+        },
+        mons: species
+            .iter()
+            .map(|&species| {
+                let mut data = vec![0; generation.mon_length()];
+                data[0] = species;
+                // a byte the games can't send, so it has to be patched
+                data[1] = NO_DATA;
+                PartyMon {
+                    generation,
+                    data,
+                    ot_name: encode_text(name),
+                    nickname: encode_text("MON"),
+                }
+            })
+            .collect(),
+    }
+}
+
+#[test]
+fn peripheral_link() {
+    use super::*;
+    use crate::net::listener::BgbListener;
+    use std::net::TcpListener;
+
+    struct Inverter;
+
+    impl SerialDevice for Inverter {
+        fn transfer(&mut self, received: u8) -> u8 {
+            !received
+        }
+    }
+
+    let listener = BgbListener::wrap(TcpListener::bind("127.0.0.1:0").unwrap());
+    let addr = listener.local_addr().unwrap();
+    let game = std::thread::spawn(move || {
+        let mut stream = BgbStream::connect(addr).unwrap();
+        assert!(matches!(
+            stream.read().unwrap(),
+            TypedBgbCommand::Status { .. }
+        ));
+        stream
+            .write(&TypedBgbCommand::Sync1 {
+                data: 0x0F,
+                high_speed: false,
+                double_speed: false,
+                timestamp: 0,
+            })
+            .unwrap();
+        assert_eq!(
+            stream.read().unwrap(),
+            TypedBgbCommand::Sync2 { data: 0xF0 }
+        );
+        stream.write(&TypedBgbCommand::WantDisconnect).unwrap();
+    });
+
+    let (stream, _) = listener.accept().unwrap();
+    let mut link = PeripheralLink::new(Inverter, stream).unwrap();
+    assert_eq!(
+        link.step().unwrap(),
+        PeripheralEvent::Transfer {
+            received: 0x0F,
+            sent: 0xF0
+        }
+    );
+    assert_eq!(link.step().unwrap(), PeripheralEvent::Disconnected);
+    game.join().unwrap();
+}
+
+#[test]
+fn pokemon_patch_lists() {
+    use super::pokemon::*;
+
+    let party = test_party(Generation::Two, "GOLD", &[152, 155, 158]);
+    let mut block = party.encode().unwrap();
+    assert_eq!(block.len(), Generation::Two.party_block_length());
+    let patches = patch(Generation::Two, &mut block);
+    assert!(!block.contains(&NO_DATA));
+    // 48-byte Pokémon put the third one's patched byte in the first part
+    assert_eq!(&patches[..5], [2, 50, 98, 0xFF, 0xFF]);
+    unpatch(Generation::Two, &mut block, &patches);
+    assert_eq!(Party::decode(Generation::Two, &block), Some(party.clone()));
+    assert_eq!(decode_text(&encode_text("Red 2")), "Red 2");
+
+    // patch lists come from the peer, so entries pointing nowhere are skipped
+    let mut damaged = vec![0, 0, 253, 0xFF];
+    damaged.resize(patches.len(), 0);
+    let before = block.clone();
+    unpatch(Generation::Two, &mut block, &damaged);
+    assert_eq!(block, before);
+    unpatch(Generation::Two, &mut block[..10], &patches);
+
+    let mut too_big = party.clone();
+    too_big.mons.push(too_big.mons[0].clone());
+    too_big.mons[1].data.pop();
+    assert_eq!(
+        too_big.encode().unwrap_err().kind(),
+        std::io::ErrorKind::InvalidInput
+    );
+    too_big.mons[1].data.push(0);
+    too_big
+        .mons
+        .extend(std::iter::repeat_n(party.mons[0].clone(), 4));
+    assert!(too_big.encode().is_err());
+    assert!(TradeBot::new(too_big).is_err());
+    assert_eq!(
+        PartyMon {
+            data: vec![],
+            ..party.mons[0].clone()
+        }
+        .level(),
+        None
+    );
+}
+
+#[test]
+fn pokemon_trade() {
+    use super::pokemon::*;
+    use super::SerialDevice;
+
+    let generation = Generation::One;
+    let player = test_party(generation, "RED", &[0x99, 0xB1]);
+    let mut bot = TradeBot::new(test_party(generation, "BOT", &[0x54, 0x24, 0x15])).unwrap();
+    bot.set_offer(2);
+
+    fn exchange(bot: &mut TradeBot, bytes: &[u8]) -> Vec<u8> {
+        bytes.iter().map(|&byte| bot.transfer(byte)).collect()
+    }
+
+    assert_eq!(
+        exchange(
+            &mut bot,
+            &[ESTABLISH_INTERNAL_CLOCK, 0, TRADE_CENTER, TRADE_CENTER]
+        ),
+        [
+            ESTABLISH_EXTERNAL_CLOCK,
+            NO_DATA,
+            TRADE_CENTER,
+            TRADE_CENTER
+        ]
+    );
+
+    // the game sends its blocks just like the bot does
+    let mut party = player.encode().unwrap();
+    let patches = patch(generation, &mut party);
+    let mut blocks = vec![PREAMBLE; 7];
+    blocks.extend_from_slice(&[0x12; 10]);
+    blocks.extend_from_slice(&[PREAMBLE; 6]);
+    blocks.extend_from_slice(&party);
+    blocks.extend_from_slice(&[PREAMBLE; 3]);
+    blocks.extend_from_slice(&patches);
+    let received = exchange(&mut bot, &blocks);
+
+    // and receives the bot's party after the random numbers and preambles
+    let start = 7 + 10 + 6;
+    let mut bot_party = received[start..start + party.len()].to_vec();
+    let bot_patches = &received[start + party.len() + 3..];
+    unpatch(generation, &mut bot_party, bot_patches);
+    let bot_party = Party::decode(generation, &bot_party).unwrap();
+    assert_eq!(bot_party, *bot.party());
+    assert_eq!(bot.peer_party(), Some(&player));
+    assert_eq!(bot.state(), TradeState::Selecting);
+
+    // the player picks their second Pokémon and confirms
+    let offer = NYBBLE_EXCHANGE | 2;
+    assert_eq!(
+        exchange(&mut bot, &[0x61, 0x61, 0x00]),
+        [offer, offer, 0x00]
+    );
+    assert_eq!(bot.state(), TradeState::Confirming);
+    assert_eq!(exchange(&mut bot, &[0x60, 0x60, 0x00]), [0x60, 0x60, 0x00]);
+    assert_eq!(bot.state(), TradeState::Selecting);
+
+    let trades = bot.trades();
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0].sent.species(), Some(0x15));
+    assert_eq!(trades[0].received.species(), Some(0xB1));
+    assert_eq!(decode_text(&trades[0].received.ot_name), "RED");
+    assert_eq!(bot.party().mons[2].species(), Some(0xB1));
+
+    // then leaves the Trade Center
+    exchange(&mut bot, &[0x6F, 0x00]);
+    assert_eq!(bot.state(), TradeState::Menu);
+}
+
+#[test]
+fn tetris_match() {
+    use super::tetris::*;
+    use super::SerialDevice;
+
+    fn exchange(bot: &mut TetrisBot, bytes: &[u8]) -> Vec<u8> {
+        bytes.iter().map(|&byte| bot.transfer(byte)).collect()
+    }
+
+    let mut bot = TetrisBot::new(3);
+    assert_eq!(exchange(&mut bot, &[0x00, MASTER_SYNC]), [0, SLAVE_SYNC]);
+    exchange(&mut bot, &[MUSIC_A + 2, MUSIC_A + 1, MUSIC_CONFIRM]);
+    assert_eq!(bot.music(), Some(1));
+    // the player picks height 0 and the bot answers with its own
+    assert_eq!(
+        exchange(&mut bot, &[0, 0, HEIGHT_CONFIRM]),
+        [3, 3, SLAVE_SYNC]
+    );
+    assert_eq!(bot.state(), TetrisState::Starting);
+
+    let mut start = vec![MASTER_SYNC, 0x2F, 0x80, MASTER_SYNC, 0x04, 0x0C, 0x18];
+    exchange(&mut bot, &start);
+    exchange(&mut bot, &[0x30, 0x00, 0x02, 0x02, 0x20]);
+    assert_eq!(bot.state(), TetrisState::Playing);
+    assert_eq!(bot.start_data(), &start[..]);
+
+    bot.set_stack_height(2);
+    bot.clear_lines(4);
+    assert_eq!(exchange(&mut bot, &[1, ATTACK + 2, 4]), [ATTACK + 4, 2, 2]);
+    assert_eq!(bot.peer_stack_height(), 4);
+    assert_eq!(bot.garbage_received(), 2);
+    assert_eq!(exchange(&mut bot, &[TOPPED_OUT]), [2]);
+    assert_eq!(bot.outcome(), Some(Outcome::Won));
+
+    // a rematch starts from the height selection, and this time the bot gives up
+    exchange(&mut bot, &[0, HEIGHT_CONFIRM]);
+    start.extend_from_slice(&[0x30, 0x00, 0x02, 0x02, 0x20]);
+    exchange(&mut bot, &start);
+    assert_eq!(bot.outcome(), None);
+    bot.top_out();
+    assert_eq!(exchange(&mut bot, &[0]), [TOPPED_OUT]);
+    assert_eq!(bot.outcome(), Some(Outcome::Lost));
+}
+
+#[test]
+fn mobile_adapter_session() {
+    use super::mobile_adapter::*;
+    use super::SerialDevice;
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    #[derive(Debug, Default)]
+    struct Stub {
+        dialed: Vec<String>,
+        opened: Vec<SocketAddrV4>,
+        sent: Vec<u8>,
+    }
+
+    impl MobileBackend for Stub {
+        fn dial(&mut self, number: &str) -> bool {
+            self.dialed.push(number.to_string());
+            number == "0755311973"
+        }
+
+        fn hang_up(&mut self) {}
+
+        fn isp_login(&mut self, id: &[u8], password: &[u8]) -> Option<Ipv4Addr> {
+            if id == b"g000" && password == b"pass" {
+                Some(Ipv4Addr::new(10, 0, 0, 2))
+            } else {
+                None
+            }
+        }
+
+        fn isp_logout(&mut self) {}
+
+        fn open_tcp(&mut self, address: SocketAddrV4) -> Option<u8> {
+            self.opened.push(address);
+            Some(1)
+        }
+
+        fn close(&mut self, _connection: u8) {}
+
+        fn transfer(&mut self, connection: u8, data: &[u8]) -> Option<Vec<u8>> {
+            self.sent.extend_from_slice(data);
+            if connection == 1 && data.is_empty() {
+                None
+            } else if data == b"LIST" {
+                Some(vec![b'.'; MAX_DATA_LENGTH])
+            } else {
+                Some(b"+OK".to_vec())
+            }
+        }
+
+        fn dns_query(&mut self, name: &[u8]) -> Option<Ipv4Addr> {
+            if name == b"example.com" {
+                Some(Ipv4Addr::new(93, 184, 216, 34))
+            } else {
+                None
+            }
+        }
+    }
+
+    // sends a packet as the game would, returning the acknowledgement and the response
+    fn send(adapter: &mut MobileAdapter<Stub>, command: u8, data: &[u8]) -> (u8, u8, Vec<u8>) {
+        let ack = send_packet(adapter, command, data);
+        if ack != command ^ 0x80 {
+            return (ack, 0, Vec::new());
+        }
+        let (response, data) = receive(adapter, false);
+        (ack, response, data)
+    }
+
+    // sends a packet as the game would, returning the acknowledgement
+    fn send_packet(adapter: &mut MobileAdapter<Stub>, command: u8, data: &[u8]) -> u8 {
+        let length = (data.len() as u16).to_be_bytes();
+        let mut packet = vec![command, 0, length[0], length[1]];
+        packet.extend_from_slice(data);
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&packet);
+        bytes.extend_from_slice(&checksum(&packet));
+        for &byte in &bytes {
+            assert_eq!(adapter.transfer(byte), ADAPTER_IDLE);
+        }
+        assert_eq!(adapter.transfer(GAME_BOY_ID), ADAPTER_ID);
+        adapter.transfer(0)
+    }
+
+    // receives the adapter's response, refusing it as corrupted if `refuse` is set
+    fn receive(adapter: &mut MobileAdapter<Stub>, refuse: bool) -> (u8, Vec<u8>) {
+        let mut header = [0; 6];
+        for byte in header.iter_mut() {
+            *byte = adapter.transfer(GAME_BOY_IDLE);
+        }
+        assert_eq!(header[..2], MAGIC);
+        let length = usize::from(u16::from_be_bytes([header[4], header[5]]));
+        let data: Vec<u8> = (0..length)
+            .map(|_| adapter.transfer(GAME_BOY_IDLE))
+            .collect();
+        let mut response = header[2..].to_vec();
+        response.extend_from_slice(&data);
+        let sum = [
+            adapter.transfer(GAME_BOY_IDLE),
+            adapter.transfer(GAME_BOY_IDLE),
+        ];
+        assert_eq!(sum, checksum(&response));
+        assert_eq!(adapter.transfer(GAME_BOY_ID), ADAPTER_ID);
+        let reply = if refuse {
+            ACK_BAD_CHECKSUM
+        } else {
+            header[2] ^ 0x80
+        };
+        assert_eq!(adapter.transfer(reply), 0);
+        (header[2], data)
+    }
+
+    let mut adapter = MobileAdapter::new(Stub::default());
+    // idle bytes and noise before a packet are ignored
+    assert_eq!(adapter.transfer(GAME_BOY_IDLE), ADAPTER_IDLE);
+    assert_eq!(adapter.transfer(MAGIC[0]), ADAPTER_IDLE);
+
+    let ok = |command: u8, data: &[u8]| (command ^ 0x80, command ^ 0x80, data.to_vec());
+    assert_eq!(
+        send(&mut adapter, command::BEGIN_SESSION, b"NINTENDO"),
+        ok(command::BEGIN_SESSION, b"NINTENDO")
+    );
+    assert!(adapter.in_session());
+
+    assert_eq!(
+        send(
+            &mut adapter,
+            command::WRITE_CONFIGURATION_DATA,
+            &[0x10, 1, 2, 3]
+        ),
+        ok(command::WRITE_CONFIGURATION_DATA, &[0x10, 3])
+    );
+    assert_eq!(
+        send(&mut adapter, command::READ_CONFIGURATION_DATA, &[0x0F, 3]),
+        ok(command::READ_CONFIGURATION_DATA, &[0x0F, 0, 1, 2])
+    );
+    assert_eq!(
+        send(
+            &mut adapter,
+            command::READ_CONFIGURATION_DATA,
+            &[0xF0, 0x20]
+        )
+        .2,
+        [command::READ_CONFIGURATION_DATA, 0x01]
+    );
+
+    // a busy line is reported with an error packet
+    let (ack, response, data) = send(&mut adapter, command::DIAL_TELEPHONE, b"\x00117");
+    assert_eq!(ack, command::DIAL_TELEPHONE ^ 0x80);
+    assert_eq!(response, command::ERROR ^ 0x80);
+    assert_eq!(data, [command::DIAL_TELEPHONE, 0x02]);
+    assert_eq!(
+        send(&mut adapter, command::DIAL_TELEPHONE, b"\x000755311973"),
+        ok(command::DIAL_TELEPHONE, &[])
+    );
+    assert_eq!(adapter.backend().dialed, ["117", "0755311973"]);
+    assert_eq!(
+        send(&mut adapter, command::TELEPHONE_STATUS, &[]).2[0],
+        0x05
+    );
+
+    let mut login = b"\x04g000\x04pass".to_vec();
+    login.extend_from_slice(&[0; 8]);
+    let (_, _, address) = send(&mut adapter, command::ISP_LOGIN, &login);
+    assert_eq!(address[..4], [10, 0, 0, 2]);
+    assert_eq!(
+        send(&mut adapter, command::DNS_QUERY, b"example.com"),
+        ok(command::DNS_QUERY, &[93, 184, 216, 34])
+    );
+    assert_eq!(
+        send(
+            &mut adapter,
+            command::OPEN_TCP_CONNECTION,
+            &[93, 184, 216, 34, 0, 110]
+        ),
+        ok(command::OPEN_TCP_CONNECTION, &[1])
+    );
+    assert_eq!(
+        adapter.backend().opened,
+        [SocketAddrV4::new(Ipv4Addr::new(93, 184, 216, 34), 110)]
+    );
+    assert_eq!(
+        send(&mut adapter, command::TRANSFER_DATA, b"\x01USER g000"),
+        ok(command::TRANSFER_DATA, b"\x01+OK")
+    );
+    assert_eq!(adapter.backend().sent, b"USER g000");
+    // a response that arrives corrupted is sent again until the game accepts it
+    assert_eq!(
+        send_packet(&mut adapter, command::TRANSFER_DATA, b"\x01PASS"),
+        command::TRANSFER_DATA ^ 0x80
+    );
+    let response = receive(&mut adapter, true);
+    assert_eq!(response, receive(&mut adapter, true));
+    assert_eq!(response, receive(&mut adapter, false));
+    assert_eq!(response.1, b"\x01+OK");
+    assert_eq!(adapter.transfer(GAME_BOY_IDLE), ADAPTER_IDLE);
+    // data that doesn't fit in a packet alongside the connection ID is an error
+    assert_eq!(
+        send(&mut adapter, command::TRANSFER_DATA, b"\x01LIST"),
+        (
+            command::TRANSFER_DATA ^ 0x80,
+            command::ERROR ^ 0x80,
+            vec![command::TRANSFER_DATA, 0x01]
+        )
+    );
+    // the backend reports the connection as closed
+    assert_eq!(
+        send(&mut adapter, command::TRANSFER_DATA, &[1]),
+        (
+            command::TRANSFER_DATA ^ 0x80,
+            command::TRANSFER_DATA_END ^ 0x80,
+            vec![1]
+        )
+    );
+
+    // a corrupted packet and an unknown command are refused outright
+    for &byte in &[0x99, 0x66, command::END_SESSION, 0, 0, 0, 0, 0x12] {
+        adapter.transfer(byte);
+    }
+    assert_eq!(adapter.transfer(GAME_BOY_ID), ADAPTER_ID);
+    assert_eq!(adapter.transfer(0), ACK_BAD_CHECKSUM);
+    assert_eq!(send(&mut adapter, 0x7F, &[]).0, ACK_UNKNOWN_COMMAND);
+    // while a known command with malformed data is acknowledged and answered with an error
+    for &(command, data) in &[
+        (command::TRANSFER_DATA, &[][..]),
+        (command::WRITE_CONFIGURATION_DATA, &[]),
+        (command::ISP_LOGIN, b"\x04g000"),
+        (command::CLOSE_TCP_CONNECTION, &[]),
+    ] {
+        assert_eq!(
+            send(&mut adapter, command, data),
+            (command ^ 0x80, command::ERROR ^ 0x80, vec![command, 0x01])
+        );
+    }
+
+    assert_eq!(
+        send(&mut adapter, command::END_SESSION, &[]),
+        ok(command::END_SESSION, &[])
+    );
+    assert!(!adapter.in_session());
+    assert_eq!(adapter.config()[0x10..0x13], [1, 2, 3]);
+}
+
+#[test]
+fn barcode_boy_swipe() {
+    use super::barcode_boy::*;
+    use super::*;
+    use crate::net::listener::BgbListener;
+    use std::net::TcpListener;
+
+    let listener = BgbListener::wrap(TcpListener::bind("127.0.0.1:0").unwrap());
+    let addr = listener.local_addr().unwrap();
+    let game = std::thread::spawn(move || {
+        let mut stream = BgbStream::connect(addr).unwrap();
+        assert!(matches!(
+            stream.read().unwrap(),
+            TypedBgbCommand::Status { .. }
+        ));
+        let mut replies = Vec::new();
+        for &byte in &HANDSHAKE {
+            stream
+                .write(&TypedBgbCommand::Sync1 {
+                    data: byte,
+                    high_speed: false,
+                    double_speed: false,
+                    timestamp: 0,
+                })
+                .unwrap();
+            match stream.read().unwrap() {
+                TypedBgbCommand::Sync2 { data } => replies.push(data),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert_eq!(replies, HANDSHAKE_REPLY);
+
+        // the first byte arrives before the game is ready for it
+        let mut received = Vec::new();
+        let mut ready = false;
+        while received.last() != Some(&END_OF_TEXT) {
+            match stream.read().unwrap() {
+                TypedBgbCommand::Sync1 { data, .. } if ready => {
+                    received.push(data);
+                    stream.write(&TypedBgbCommand::Sync2 { data: 0 }).unwrap();
+                }
+                TypedBgbCommand::Sync1 { .. } => {
+                    ready = true;
+                    stream.write(&TypedBgbCommand::Sync3Response).unwrap();
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        stream.write(&TypedBgbCommand::WantDisconnect).unwrap();
+        received
+    });
+
+    let (stream, _) = listener.accept().unwrap();
+    let mut link = PeripheralLink::new(BarcodeBoy::new(), stream).unwrap();
+    for _ in 0..HANDSHAKE.len() {
+        link.step().unwrap();
+    }
+    assert!(link.device().connected());
+    assert_eq!(
+        link.swipe("49023").unwrap_err().kind(),
+        io::ErrorKind::InvalidInput
+    );
+    link.swipe("4902370501653").unwrap();
+    assert_eq!(link.step().unwrap(), PeripheralEvent::Disconnected);
+    assert_eq!(link.device().swipes(), ["4902370501653"]);
+    assert_eq!(
+        game.join().unwrap(),
+        encode_barcode("4902370501653").unwrap()
+    );
+}
+
+#[cfg(test)]
+fn printer_packet(command: u8, compressed: bool, data: &[u8]) -> Vec<u8> {
+    let length = (data.len() as u16).to_le_bytes();
+    let mut body = vec![command, compressed as u8, length[0], length[1]];
+    body.extend_from_slice(data);
+    let sum = body
+        .iter()
+        .fold(0u16, |sum, &byte| sum.wrapping_add(u16::from(byte)));
+    let mut packet = super::printer::MAGIC.to_vec();
+    packet.extend_from_slice(&body);
+    packet.extend_from_slice(&sum.to_le_bytes());
+    packet.extend_from_slice(&[0, 0]);
+    packet
+}
+
+#[test]
+fn printer_prints() {
+    use super::printer::*;
+    use super::SerialDevice;
+
+    // sends a packet, returning the printer's status
+    fn send(printer: &mut GameBoyPrinter, packet: &[u8]) -> u8 {
+        let received: Vec<u8> = packet.iter().map(|&byte| printer.transfer(byte)).collect();
+        assert_eq!(received[received.len() - 2], DEVICE_ID);
+        received[received.len() - 1]
+    }
+
+    let mut printer = GameBoyPrinter::new();
+    assert_eq!(
+        send(&mut printer, &printer_packet(COMMAND_INIT, false, &[])),
+        0
+    );
+
+    // a row of tiles whose top row of pixels is color 1
+    let mut tiles = vec![0; 320];
+    for tile in tiles.chunks_mut(16) {
+        tile[0] = 0xFF;
+    }
+    assert_eq!(decompress(&[0x81, 7, 1, 1, 2]), [7, 7, 7, 1, 2]);
+    assert_eq!(
+        send(&mut printer, &printer_packet(COMMAND_DATA, false, &tiles)),
+        STATUS_UNPROCESSED
+    );
+    let mut bad = printer_packet(COMMAND_DATA, false, &tiles);
+    let checksum = bad.len() - 4;
+    bad[checksum] ^= 1;
+    assert_eq!(
+        send(&mut printer, &bad),
+        STATUS_UNPROCESSED | STATUS_CHECKSUM_ERROR
+    );
+    // and a compressed row where the first tile is black
+    let mut second = vec![0xFF; 16];
+    second.extend_from_slice(&[0; 304]);
+    let runs = [0x80 | 14, 0xFF, 0x80 | 125, 0, 0x80 | 125, 0, 0x80 | 48, 0];
+    assert_eq!(decompress(&runs), second);
+    send(&mut printer, &printer_packet(COMMAND_DATA, true, &runs));
+    send(&mut printer, &printer_packet(COMMAND_DATA, false, &[]));
+
+    assert_eq!(
+        send(
+            &mut printer,
+            &printer_packet(COMMAND_PRINT, false, &[1, 0x10, 0xE4, 0x40])
+        ),
+        0
+    );
+    // the game waits for the printer to be busy and then done
+    let status = printer_packet(COMMAND_STATUS, false, &[]);
+    assert_eq!(send(&mut printer, &status), STATUS_BUSY);
+    assert_eq!(send(&mut printer, &status), 0);
+
+    send(&mut printer, &printer_packet(COMMAND_DATA, false, &tiles));
+    send(
+        &mut printer,
+        &printer_packet(COMMAND_PRINT, false, &[1, 0x03, 0x1B, 0x7F]),
+    );
+    assert_eq!(
+        send(&mut printer, &printer_packet(0x7E, false, &[])),
+        STATUS_PACKET_ERROR
+    );
+
+    let prints = printer.take_prints();
+    assert_eq!(prints.len(), 2);
+    assert_eq!(prints[0].height(), 16);
+    assert_eq!((prints[0].margin_before, prints[0].margin_after), (1, 0));
+    let shades = prints[0].shades();
+    assert!(shades[..WIDTH].iter().all(|&shade| shade == 1));
+    assert!(shades[WIDTH..8 * WIDTH].iter().all(|&shade| shade == 0));
+    assert_eq!(
+        shades[8 * WIDTH..8 * WIDTH + 9],
+        [3, 3, 3, 3, 3, 3, 3, 3, 0]
+    );
+    // the second print's palette swaps white for black
+    assert_eq!(prints[1].shades()[WIDTH], 3);
+    assert_eq!(sheets(&prints), [&prints[..]]);
+    assert!(printer.prints().is_empty());
+}
+
+#[cfg(feature = "image")]
+#[test]
+fn printer_png() {
+    use super::printer::png::*;
+    use super::printer::*;
+
+    let mut data = vec![0; 320];
+    data[0] = 0xFF;
+    data[1] = 0xFF;
+    let print = |palette: u8, margins: (u8, u8), exposure: u8| Print {
+        data: data.clone(),
+        sheets: 1,
+        margin_before: margins.0,
+        margin_after: margins.1,
+        palette,
+        exposure,
+    };
+    let prints = [
+        print(0xE4, (1, 0), 0x40),
+        print(0x00, (0, 2), 0x00),
+        print(0xE4, (0, 1), 0x7F),
+    ];
+    let sheets = sheets(&prints);
+    assert_eq!(sheets.len(), 2);
+
+    let image = render(sheets[0]);
+    assert_eq!(image.dimensions(), (160, MARGIN_ROWS * 3 + 16));
+    let top = MARGIN_ROWS;
+    assert_eq!(image.get_pixel(0, top - 1).0, [255]);
+    assert_eq!(image.get_pixel(0, top).0, [0]);
+    assert_eq!(image.get_pixel(8, top).0, [255]);
+    // low exposure prints black as gray, and a palette of 0 means the usual one
+    assert_eq!(image.get_pixel(0, top + 8).0, [64]);
+
+    // exposure scales how dark a shade is from 75% at 0x00 to nearly 125% at 0x7F
+    let mut gray = print(0xE4, (0, 0), 0);
+    gray.data[..2].copy_from_slice(&[0x00, 0x80]);
+    for &(exposure, pixel) in &[(0x00, 128), (0x40, 85), (0x7F, 44)] {
+        gray.exposure = exposure;
+        let image = render(std::slice::from_ref(&gray));
+        assert_eq!(
+            image.get_pixel(0, 0).0,
+            [pixel],
+            "exposure {:#04x}",
+            exposure
+        );
+    }
+
+    let mut png = Vec::new();
+    write_png(sheets[1], &mut png).unwrap();
+    assert_eq!(&png[1..4], b"PNG");
+    let decoded = image::load_from_memory(&png).unwrap().to_luma8();
+    assert_eq!(decoded.dimensions(), (160, 8 + MARGIN_ROWS));
+    assert_eq!(decoded.get_pixel(0, 0).0, [0]);
+}
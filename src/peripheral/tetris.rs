@@ -0,0 +1,234 @@
+use super::SerialDevice;
+
+/// What the game driving the clock sends to connect, and while it's sending each block of
+/// data at the start of a round.
+pub const MASTER_SYNC: u8 = 0x29;
+/// What the other game answers with while it has nothing else to say.
+pub const SLAVE_SYNC: u8 = 0x55;
+/// The first of the four music choices, for A-TYPE. The others follow, ending with OFF.
+pub const MUSIC_A: u8 = 0x1C;
+/// Sent once the music has been chosen.
+pub const MUSIC_CONFIRM: u8 = 0x50;
+/// Sent once both players have chosen their starting heights.
+pub const HEIGHT_CONFIRM: u8 = 0x60;
+/// Added to the number of garbage lines a player is sending to the other.
+pub const ATTACK: u8 = 0x80;
+/// Sent by a player whose stack has reached the top.
+pub const TOPPED_OUT: u8 = 0x77;
+/// Sent by a player who has cleared enough lines to win.
+pub const CLEARED: u8 = 0xAA;
+
+/// The bytes that end the data sent at the start of a round.
+const ROUND_START: [u8; 5] = [0x30, 0x00, 0x02, 0x02, 0x20];
+/// The number of lines a player has to clear to win a round.
+const LINES_TO_WIN: u32 = 30;
+
+/// How far a `TetrisBot` has gotten with the game.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TetrisState {
+    /// Waiting for the game to connect.
+    Connecting,
+    /// Waiting for the player to choose the music.
+    Music,
+    /// Waiting for the player to choose their starting height.
+    Height,
+    /// Receiving the garbage and piece sequence for the next round.
+    Starting,
+    /// Playing a round.
+    Playing,
+    /// The round is over and the game is showing who won.
+    RoundOver,
+}
+
+/// How a round ended, from the bot's point of view.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Won,
+    Lost,
+}
+
+/// Plays the second player's side of Tetris's 2-player mode.
+///
+/// The game must be the one to start 2-player mode, so that it drives the clock. The bot
+/// accepts whichever music the player picks and answers with its own starting height. At
+/// the start of each round the game sends the garbage the round starts with and the sequence
+/// of pieces, which the bot keeps for inspection.
+///
+/// While a round is being played, both games exchange the height of their stacks. A player
+/// who clears two or more lines at once sends garbage to the other as `ATTACK` plus the number
+/// of lines, and the round ends when either player sends `TOPPED_OUT` or `CLEARED`. The bot
+/// only does what it's told by `clear_lines`, `set_stack_height` and `top_out`.
+#[derive(Clone, Debug)]
+pub struct TetrisBot {
+    state: TetrisState,
+    music: Option<u8>,
+    height: u8,
+    start_data: Vec<u8>,
+    stack_height: u8,
+    peer_stack_height: u8,
+    pending: Option<u8>,
+    lines: u32,
+    garbage_received: u32,
+    outcome: Option<Outcome>,
+}
+
+impl TetrisBot {
+    /// Creates a bot that picks the given starting height, from 0 to 5.
+    pub fn new(height: u8) -> TetrisBot {
+        TetrisBot {
+            state: TetrisState::Connecting,
+            music: None,
+            height: height.min(5),
+            start_data: Vec::new(),
+            stack_height: 0,
+            peer_stack_height: 0,
+            pending: None,
+            lines: 0,
+            garbage_received: 0,
+            outcome: None,
+        }
+    }
+
+    /// Returns how far the bot has gotten with the game.
+    pub fn state(&self) -> TetrisState {
+        self.state
+    }
+
+    /// Returns the music the player chose, from 0 for A-TYPE to 3 for OFF.
+    pub fn music(&self) -> Option<u8> {
+        self.music
+    }
+
+    /// Returns everything the game sent at the start of the current round: the starting
+    /// garbage and the piece sequence, each after a `MASTER_SYNC`.
+    pub fn start_data(&self) -> &[u8] {
+        &self.start_data
+    }
+
+    /// Returns the height of the player's stack, as they last reported it.
+    pub fn peer_stack_height(&self) -> u8 {
+        self.peer_stack_height
+    }
+
+    /// Returns how many lines of garbage the player has sent this round.
+    pub fn garbage_received(&self) -> u32 {
+        self.garbage_received
+    }
+
+    /// Returns how the last round ended, or `None` if it's still being played.
+    pub fn outcome(&self) -> Option<Outcome> {
+        self.outcome
+    }
+
+    /// Sets the stack height the bot reports to the player.
+    pub fn set_stack_height(&mut self, height: u8) {
+        self.stack_height = height;
+    }
+
+    /// Pretends to clear some lines at once, sending the player garbage for two or more and
+    /// winning the round once 30 lines have been cleared.
+    pub fn clear_lines(&mut self, count: u32) {
+        if self.state != TetrisState::Playing {
+            return;
+        }
+        self.lines += count;
+        self.pending = if self.lines >= LINES_TO_WIN {
+            Some(CLEARED)
+        } else {
+            match count {
+                2 => Some(ATTACK + 1),
+                3 => Some(ATTACK + 2),
+                4 => Some(ATTACK + 4),
+                _ => self.pending,
+            }
+        };
+    }
+
+    /// Gives up the current round.
+    pub fn top_out(&mut self) {
+        if self.state == TetrisState::Playing {
+            self.pending = Some(TOPPED_OUT);
+        }
+    }
+
+    fn start_round(&mut self) {
+        self.state = TetrisState::Starting;
+        self.start_data.clear();
+        self.stack_height = 0;
+        self.peer_stack_height = 0;
+        self.pending = None;
+        self.lines = 0;
+        self.garbage_received = 0;
+        self.outcome = None;
+    }
+
+    fn end_round(&mut self, outcome: Outcome) {
+        self.state = TetrisState::RoundOver;
+        self.outcome = Some(outcome);
+    }
+
+    fn play(&mut self, received: u8) -> u8 {
+        match received {
+            TOPPED_OUT => self.end_round(Outcome::Won),
+            CLEARED => self.end_round(Outcome::Lost),
+            _ if received > ATTACK => self.garbage_received += u32::from(received - ATTACK),
+            _ => self.peer_stack_height = received,
+        }
+        match self.pending.take() {
+            Some(byte) => {
+                match byte {
+                    TOPPED_OUT => self.end_round(Outcome::Lost),
+                    CLEARED => self.end_round(Outcome::Won),
+                    _ => {}
+                }
+                byte
+            }
+            None => self.stack_height,
+        }
+    }
+}
+
+impl SerialDevice for TetrisBot {
+    fn transfer(&mut self, received: u8) -> u8 {
+        match (self.state, received) {
+            (TetrisState::Connecting, MASTER_SYNC) => {
+                self.state = TetrisState::Music;
+                SLAVE_SYNC
+            }
+            (TetrisState::Connecting, _) => 0,
+            (TetrisState::Music, MUSIC_CONFIRM) => {
+                self.state = TetrisState::Height;
+                SLAVE_SYNC
+            }
+            (TetrisState::Music, _) => {
+                if (MUSIC_A..MUSIC_A + 4).contains(&received) {
+                    self.music = Some(received - MUSIC_A);
+                }
+                SLAVE_SYNC
+            }
+            (TetrisState::Height, HEIGHT_CONFIRM) | (TetrisState::RoundOver, HEIGHT_CONFIRM) => {
+                self.start_round();
+                SLAVE_SYNC
+            }
+            (TetrisState::Height, _) | (TetrisState::RoundOver, 0..=5) => {
+                self.state = TetrisState::Height;
+                self.height
+            }
+            (TetrisState::RoundOver, MASTER_SYNC) => {
+                self.state = TetrisState::Music;
+                SLAVE_SYNC
+            }
+            (TetrisState::RoundOver, _) => SLAVE_SYNC,
+            (TetrisState::Starting, _) => {
+                self.start_data.push(received);
+                if self.start_data.ends_with(&ROUND_START) {
+                    let len = self.start_data.len() - ROUND_START.len();
+                    self.start_data.truncate(len);
+                    self.state = TetrisState::Playing;
+                }
+                SLAVE_SYNC
+            }
+            (TetrisState::Playing, _) => self.play(received),
+        }
+    }
+}
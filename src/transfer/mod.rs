@@ -0,0 +1,275 @@
+mod tests;
+
+use std::io;
+use std::io::{Read, Write};
+
+/// The byte that starts every frame.
+pub const FRAME_START: u8 = 0x7E;
+/// What the game clocks out while it's waiting for a frame from the host. The host answers
+/// with `SerialStream`'s idle byte, `0xFF`, while it has nothing to send.
+pub const GAME_IDLE: u8 = 0x00;
+/// The most data a single frame can carry.
+pub const MAX_PAYLOAD: usize = 255;
+/// How many times a frame is sent before giving up, by default.
+pub const DEFAULT_ATTEMPTS: u32 = 5;
+/// How many bytes are read while waiting for a reply before a frame is sent again, by
+/// default. This is enough for a reply of the longest frame with room to spare.
+pub const DEFAULT_REPLY_BUDGET: usize = 1024;
+
+/// The kinds of frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameKind {
+    /// Carries the next part of a blob.
+    Data,
+    /// Marks the end of a blob.
+    End,
+    /// Confirms that the frame with the same sequence number arrived intact.
+    Ack,
+    /// Asks for the frame with the same sequence number to be sent again.
+    Nak,
+}
+
+impl FrameKind {
+    /// Returns the byte that identifies this kind of frame.
+    pub fn byte(self) -> u8 {
+        match self {
+            FrameKind::Data => 0x01,
+            FrameKind::End => 0x04,
+            FrameKind::Ack => 0x06,
+            FrameKind::Nak => 0x15,
+        }
+    }
+
+    /// Returns the kind of frame a byte identifies, if any.
+    pub fn from_byte(byte: u8) -> Option<FrameKind> {
+        match byte {
+            0x01 => Some(FrameKind::Data),
+            0x04 => Some(FrameKind::End),
+            0x06 => Some(FrameKind::Ack),
+            0x15 => Some(FrameKind::Nak),
+            _ => None,
+        }
+    }
+}
+
+/// A single frame of the transfer protocol.
+///
+/// On the wire, a frame is `FRAME_START`, the kind, the sequence number, the length of the
+/// data, the data itself, and a little-endian 16-bit sum of everything after `FRAME_START`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    pub sequence: u8,
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    /// Creates a frame without any data.
+    pub fn control(kind: FrameKind, sequence: u8) -> Frame {
+        Frame {
+            kind,
+            sequence,
+            data: Vec::new(),
+        }
+    }
+
+    /// Serializes the frame. Data past `MAX_PAYLOAD` bytes is left out.
+    pub fn encode(&self) -> Vec<u8> {
+        let data = &self.data[..self.data.len().min(MAX_PAYLOAD)];
+        let mut bytes = vec![
+            FRAME_START,
+            self.kind.byte(),
+            self.sequence,
+            data.len() as u8,
+        ];
+        bytes.extend_from_slice(data);
+        bytes.extend_from_slice(&checksum(&bytes[1..]).to_le_bytes());
+        bytes
+    }
+
+    /// Skips to the next `FRAME_START` and reads the frame that follows.
+    ///
+    /// Returns `None` if the frame arrived damaged, which is worth asking for again.
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Option<Frame>> {
+        let mut byte = [0];
+        while byte[0] != FRAME_START {
+            reader.read_exact(&mut byte)?;
+        }
+        let mut header = [0; 3];
+        reader.read_exact(&mut header)?;
+        let mut data = vec![0; usize::from(header[2])];
+        reader.read_exact(&mut data)?;
+        let mut sum = [0; 2];
+        reader.read_exact(&mut sum)?;
+
+        let expected = checksum(&header).wrapping_add(checksum(&data));
+        match FrameKind::from_byte(header[0]) {
+            Some(kind) if u16::from_le_bytes(sum) == expected => Ok(Some(Frame {
+                kind,
+                sequence: header[1],
+                data,
+            })),
+            _ => Ok(None),
+        }
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u16 {
+    bytes
+        .iter()
+        .fold(0u16, |sum, &byte| sum.wrapping_add(u16::from(byte)))
+}
+
+/// Sends and receives blobs of bytes to and from a game, such as save files and assets for
+/// a cartridge in development.
+///
+/// This is usually used over a `SerialStream`, with the game driving the clock and running
+/// the reference routine in `tests/fixtures/transfer.asm`. Whoever is sending a blob splits
+/// it into `Data` frames, numbered from 0 and wrapping around, and finishes with an `End`
+/// frame. The receiver answers each frame with an `Ack`, or with a `Nak` if it arrived
+/// damaged, and the sender repeats any frame that isn't acknowledged. A receiver that sees a
+/// frame twice, because its `Ack` was damaged, acknowledges it again without keeping it.
+///
+/// Bytes outside of frames, like `GAME_IDLE`, are ignored. A sender that reads more than its
+/// reply budget without getting a whole reply, because the reply was lost or its length was
+/// damaged, sends the frame again.
+///
+/// The receiver keeps the blob as soon as it acknowledges the `End` frame, so a blob is
+/// delivered at least once: if every `Ack` for the `End` frame is lost, the receiver has the
+/// whole blob but the sender reports a failure, and sending it again delivers it twice.
+#[derive(Debug)]
+pub struct FileTransfer<S: Read + Write> {
+    stream: S,
+    attempts: u32,
+    reply_budget: usize,
+}
+
+impl<S: Read + Write> FileTransfer<S> {
+    /// Wraps a byte stream to the game.
+    pub fn new(stream: S) -> FileTransfer<S> {
+        FileTransfer {
+            stream,
+            attempts: DEFAULT_ATTEMPTS,
+            reply_budget: DEFAULT_REPLY_BUDGET,
+        }
+    }
+
+    /// Sets how many times each frame is sent before giving up. At least one attempt is
+    /// always made.
+    pub fn set_attempts(&mut self, attempts: u32) {
+        self.attempts = attempts.max(1);
+    }
+
+    /// Sets how many bytes are read while waiting for a reply, including the reply itself,
+    /// before a frame is sent again.
+    pub fn set_reply_budget(&mut self, bytes: usize) {
+        self.reply_budget = bytes;
+    }
+
+    /// Gets a reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Gets a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Consumes the transfer, returning the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Sends a blob to the game.
+    ///
+    /// Returns an error of kind `InvalidData` if a frame isn't acknowledged after every
+    /// attempt. If that frame is the `End` frame, the game may have kept the blob anyway.
+    pub fn send(&mut self, blob: &[u8]) -> io::Result<()> {
+        let mut sequence = 0u8;
+        for chunk in blob.chunks(MAX_PAYLOAD) {
+            self.send_frame(&Frame {
+                kind: FrameKind::Data,
+                sequence,
+                data: chunk.to_vec(),
+            })?;
+            sequence = sequence.wrapping_add(1);
+        }
+        match self.send_frame(&Frame::control(FrameKind::End, sequence)) {
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidData => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "the end of the blob wasn't acknowledged after {} attempts, so the game \
+                     may or may not have kept it",
+                    self.attempts
+                ),
+            )),
+            result => result,
+        }
+    }
+
+    /// Receives a blob from the game.
+    pub fn receive(&mut self) -> io::Result<Vec<u8>> {
+        let mut blob = Vec::new();
+        let mut expected = 0u8;
+        loop {
+            let frame = match Frame::read_from(&mut self.stream)? {
+                Some(frame) => frame,
+                None => {
+                    self.reply(FrameKind::Nak, expected)?;
+                    continue;
+                }
+            };
+            match frame.kind {
+                FrameKind::Data | FrameKind::End if frame.sequence == expected => {
+                    self.reply(FrameKind::Ack, frame.sequence)?;
+                    if frame.kind == FrameKind::End {
+                        return Ok(blob);
+                    }
+                    blob.extend_from_slice(&frame.data);
+                    expected = expected.wrapping_add(1);
+                }
+                // a repeat of a frame that was already kept
+                FrameKind::Data | FrameKind::End => self.reply(FrameKind::Ack, frame.sequence)?,
+                FrameKind::Ack | FrameKind::Nak => {}
+            }
+        }
+    }
+
+    fn send_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        let bytes = frame.encode();
+        for _ in 0..self.attempts {
+            self.stream.write_all(&bytes)?;
+            self.stream.flush()?;
+            if let Some(reply) = self.read_reply()? {
+                if reply.kind == FrameKind::Ack && reply.sequence == frame.sequence {
+                    return Ok(());
+                }
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "frame {} wasn't acknowledged after {} attempts",
+                frame.sequence, self.attempts
+            ),
+        ))
+    }
+
+    fn read_reply(&mut self) -> io::Result<Option<Frame>> {
+        let mut reader = (&mut self.stream).take(self.reply_budget as u64);
+        match Frame::read_from(&mut reader) {
+            // the budget ran out, so the reply is treated like a damaged one
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof && reader.limit() == 0 => {
+                Ok(None)
+            }
+            result => result,
+        }
+    }
+
+    fn reply(&mut self, kind: FrameKind, sequence: u8) -> io::Result<()> {
+        self.stream
+            .write_all(&Frame::control(kind, sequence).encode())?;
+        self.stream.flush()
+    }
+}
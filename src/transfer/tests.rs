@@ -0,0 +1,243 @@
+/// Plays the game's part by driving the clock over BGB, damaging one byte on the way if asked.
+///
+/// Received bytes are counted without the host's idle bytes, which depend on timing.
+#[cfg(test)]
+struct Rom {
+    stream: crate::net::stream::BgbStream<std::net::TcpStream>,
+    damage_sent: Option<usize>,
+    damage_received: Option<usize>,
+}
+
+#[cfg(test)]
+impl Rom {
+    fn exchange(&mut self, data: u8) -> std::io::Result<u8> {
+        use crate::commands::TypedBgbCommand;
+
+        self.stream.write(&TypedBgbCommand::Sync1 {
+            data,
+            high_speed: false,
+            double_speed: false,
+            timestamp: 0,
+        })?;
+        loop {
+            match self.stream.read()? {
+                TypedBgbCommand::Sync2 { data } => return Ok(data),
+                TypedBgbCommand::Status { .. } => {}
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl std::io::Read for Rom {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut byte = self.exchange(super::GAME_IDLE)?;
+        if byte != 0xFF {
+            if self.damage_received == Some(0) {
+                byte ^= 0x40;
+            }
+            self.damage_received = self.damage_received.and_then(|n| n.checked_sub(1));
+        }
+        buf[0] = byte;
+        Ok(1)
+    }
+}
+
+#[cfg(test)]
+impl std::io::Write for Rom {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for &byte in buf {
+            let byte = if self.damage_sent == Some(0) {
+                byte ^ 0x40
+            } else {
+                byte
+            };
+            self.damage_sent = self.damage_sent.and_then(|n| n.checked_sub(1));
+            self.exchange(byte)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Plays the game's part without a link, answering each flushed frame with the next reply in
+/// `replies`, or with nothing for a lost reply, and clocking out `GAME_IDLE` otherwise.
+#[cfg(test)]
+struct Scripted {
+    replies: std::collections::VecDeque<Option<Vec<u8>>>,
+    pending: std::collections::VecDeque<u8>,
+    written: Vec<u8>,
+}
+
+#[cfg(test)]
+impl std::io::Read for Scripted {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        buf[0] = self.pending.pop_front().unwrap_or(super::GAME_IDLE);
+        Ok(1)
+    }
+}
+
+#[cfg(test)]
+impl std::io::Write for Scripted {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.written.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if let Some(Some(reply)) = self.replies.pop_front() {
+            self.pending.extend(reply);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn frames() {
+    use super::*;
+
+    let frame = Frame {
+        kind: FrameKind::Data,
+        sequence: 3,
+        data: vec![FRAME_START, 0xFF],
+    };
+    let bytes = frame.encode();
+    assert_eq!(bytes, [FRAME_START, 1, 3, 2, FRAME_START, 0xFF, 0x83, 0x01]);
+    // idle bytes before a frame are skipped
+    let mut stream = vec![GAME_IDLE, GAME_IDLE];
+    stream.extend_from_slice(&bytes);
+    assert_eq!(Frame::read_from(&mut &stream[..]).unwrap(), Some(frame));
+
+    let mut damaged = bytes.clone();
+    damaged[5] = 0;
+    assert_eq!(Frame::read_from(&mut &damaged[..]).unwrap(), None);
+    assert_eq!(
+        Frame::read_from(&mut &bytes[..3]).unwrap_err().kind(),
+        std::io::ErrorKind::UnexpectedEof
+    );
+}
+
+#[test]
+fn fixture_constants() {
+    use super::*;
+
+    let fixture = include_str!("../../tests/fixtures/transfer.asm");
+    let constant = |name: &str| -> u32 {
+        let line = fixture
+            .lines()
+            .find(|line| line.starts_with(&format!("DEF {} EQU ", name)))
+            .unwrap_or_else(|| panic!("{} isn't defined", name));
+        let value = line.rsplit(' ').next().unwrap();
+        match value.strip_prefix('$') {
+            Some(hex) => u32::from_str_radix(hex, 16).unwrap(),
+            None => value.parse().unwrap(),
+        }
+    };
+    assert_eq!(constant("FRAME_START"), u32::from(FRAME_START));
+    assert_eq!(constant("GAME_IDLE"), u32::from(GAME_IDLE));
+    assert_eq!(constant("MAX_PAYLOAD"), MAX_PAYLOAD as u32);
+    assert_eq!(constant("ATTEMPTS"), DEFAULT_ATTEMPTS);
+    assert_eq!(constant("REPLY_BUDGET"), DEFAULT_REPLY_BUDGET as u32);
+    for (name, kind) in &[
+        ("KIND_DATA", FrameKind::Data),
+        ("KIND_END", FrameKind::End),
+        ("KIND_ACK", FrameKind::Ack),
+        ("KIND_NAK", FrameKind::Nak),
+    ] {
+        assert_eq!(constant(name), u32::from(kind.byte()));
+    }
+}
+
+#[test]
+fn transfer_over_link() {
+    use super::*;
+    use crate::net::listener::BgbListener;
+    use crate::net::serial::SerialStream;
+    use crate::net::stream::BgbStream;
+    use std::net::TcpListener;
+
+    let listener = BgbListener::wrap(TcpListener::bind("127.0.0.1:0").unwrap());
+    let addr = listener.local_addr().unwrap();
+    let save: Vec<u8> = (0..600).map(|i| (i * 7) as u8).collect();
+    let upload = save.clone();
+    let game = std::thread::spawn(move || {
+        let rom = Rom {
+            stream: BgbStream::connect(addr).unwrap(),
+            // the host's first frame is damaged, so the game answers with a NAK and two ACKs
+            // before sending its own frames, the first of which is 261 bytes long
+            damage_sent: Some(3 * 6 + 261 + 10),
+            damage_received: Some(5),
+        };
+        let mut transfer = FileTransfer::new(rom);
+        let downloaded = transfer.receive().unwrap();
+        transfer.send(&upload).unwrap();
+        let rom = transfer.into_inner();
+        assert_eq!((rom.damage_sent, rom.damage_received), (None, None));
+        downloaded
+    });
+
+    let (stream, _) = listener.accept().unwrap();
+    let mut transfer = FileTransfer::new(SerialStream::new(stream).unwrap());
+    transfer.send(b"level 2 tiles").unwrap();
+    assert_eq!(transfer.receive().unwrap(), save);
+    assert_eq!(game.join().unwrap(), b"level 2 tiles");
+}
+
+#[test]
+fn lost_replies() {
+    use super::*;
+
+    let ack = Frame::control(FrameKind::Ack, 0).encode();
+    let mut bad_length = ack.clone();
+    bad_length[3] = 0x80;
+    let script = || Scripted {
+        replies: vec![None, Some(bad_length.clone()), Some(ack.clone())].into(),
+        pending: Default::default(),
+        written: Vec::new(),
+    };
+
+    let mut transfer = FileTransfer::new(script());
+    transfer.set_reply_budget(64);
+    transfer.send(&[]).unwrap();
+    let end = Frame::control(FrameKind::End, 0).encode();
+    assert_eq!(transfer.get_ref().written, end.repeat(3));
+
+    let mut transfer = FileTransfer::new(script());
+    transfer.set_reply_budget(64);
+    transfer.set_attempts(2);
+    assert_eq!(
+        transfer.send(&[]).unwrap_err().kind(),
+        std::io::ErrorKind::InvalidData
+    );
+}
+
+#[test]
+fn lost_end_acks() {
+    use super::*;
+
+    let ack = |sequence| Some(Frame::control(FrameKind::Ack, sequence).encode());
+    let mut sender = FileTransfer::new(Scripted {
+        replies: vec![ack(0), None, None].into(),
+        pending: Default::default(),
+        written: Vec::new(),
+    });
+    sender.set_reply_budget(64);
+    sender.set_attempts(2);
+    let error = sender.send(b"save").unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    assert!(error.to_string().contains("end of the blob"));
+
+    // the receiver kept the blob even though the sender couldn't tell
+    let mut receiver = FileTransfer::new(Scripted {
+        replies: Default::default(),
+        pending: sender.into_inner().written.into(),
+        written: Vec::new(),
+    });
+    assert_eq!(receiver.receive().unwrap(), b"save");
+    let acks = [ack(0).unwrap(), ack(1).unwrap()].concat();
+    assert_eq!(receiver.into_inner().written, acks);
+}
@@ -0,0 +1,331 @@
+; The Game Boy side of the file transfer protocol in src/transfer/mod.rs, for RGBDS.
+;
+; The game drives the clock, so LinkReceiveBlob blocks until the host starts sending. A
+; frame whose reply doesn't start within REPLY_BUDGET bytes is sent again. Call
+; LinkSendBlob or LinkReceiveBlob with interrupts in whatever state the rest of the game
+; expects; only the serial registers are touched.
+;
+; A frame is FRAME_START, the kind, the sequence number, the length of the data, the data
+; itself and a little-endian 16-bit sum of everything after FRAME_START.
+
+DEF rSB EQU $FF01
+DEF rSC EQU $FF02
+DEF SC_START_INTERNAL EQU $81
+
+DEF FRAME_START EQU $7E
+DEF GAME_IDLE EQU $00
+DEF KIND_DATA EQU $01
+DEF KIND_END EQU $04
+DEF KIND_ACK EQU $06
+DEF KIND_NAK EQU $15
+DEF MAX_PAYLOAD EQU 255
+DEF ATTEMPTS EQU 5
+DEF REPLY_BUDGET EQU 1024
+
+SECTION "Link transfer variables", WRAM0
+
+wChecksum: dw
+wSequence: db
+wAttempts: db
+wRemaining: dw
+wCount: dw
+wKind: db
+wLength: db
+wFrameData: ds MAX_PAYLOAD
+
+SECTION "Link transfer", ROM0
+
+; Sends a blob to the host.
+; @param hl: the blob
+; @param bc: its length
+; @return carry set if the host never acknowledged a frame
+LinkSendBlob::
+	ld a, c
+	ld [wRemaining], a
+	ld a, b
+	ld [wRemaining + 1], a
+	xor a
+	ld [wSequence], a
+.chunk
+	; d = min(remaining, MAX_PAYLOAD)
+	ld a, [wRemaining + 1]
+	and a
+	ld d, MAX_PAYLOAD
+	jr nz, .send
+	ld a, [wRemaining]
+	ld d, a
+	and a
+	jr z, .end
+.send
+	ld b, KIND_DATA
+	call SendWithRetries
+	ret c
+	ld a, l
+	add a, d
+	ld l, a
+	jr nc, .advanced
+	inc h
+.advanced
+	ld a, [wRemaining]
+	sub a, d
+	ld [wRemaining], a
+	jr nc, .counted
+	ld a, [wRemaining + 1]
+	dec a
+	ld [wRemaining + 1], a
+.counted
+	ld a, [wSequence]
+	inc a
+	ld [wSequence], a
+	jr .chunk
+.end
+	ld b, KIND_END
+	ld d, 0
+	; fall through
+
+; Sends a frame with the current sequence number until the host acknowledges it.
+; @param b: the kind of frame
+; @param d: the length of the data
+; @param hl: the data, which is preserved
+; @return carry set if every attempt failed
+SendWithRetries:
+	ld a, ATTEMPTS
+	ld [wAttempts], a
+.attempt
+	ld a, [wSequence]
+	ld c, a
+	push bc
+	push de
+	push hl
+	call SendFrame
+	call ReceiveReply
+	jr c, .failed
+	ld a, b
+	cp a, KIND_ACK
+	jr nz, .failed
+	ld a, [wSequence]
+	cp a, c
+	jr nz, .failed
+	pop hl
+	pop de
+	pop bc
+	and a
+	ret
+.failed
+	pop hl
+	pop de
+	pop bc
+	ld a, [wAttempts]
+	dec a
+	ld [wAttempts], a
+	jr nz, .attempt
+	scf
+	ret
+
+; Receives a blob from the host.
+; @param de: where to put the blob
+; @return bc: its length
+LinkReceiveBlob::
+	xor a
+	ld [wSequence], a
+	ld [wCount], a
+	ld [wCount + 1], a
+.frame
+	push de
+	call ReceiveFrame
+	jr nc, .intact
+	ld b, KIND_NAK
+	ld a, [wSequence]
+	ld c, a
+	call SendControl
+	pop de
+	jr .frame
+.intact
+	; everything that arrives intact is acknowledged, but repeats aren't kept
+	ld a, b
+	ld [wKind], a
+	ld a, d
+	ld [wLength], a
+	ld b, KIND_ACK
+	call SendControl
+	pop de
+	ld a, [wSequence]
+	cp a, c
+	jr nz, .frame
+	ld a, [wKind]
+	cp a, KIND_END
+	jr z, .done
+	ld a, [wLength]
+	and a
+	jr z, .next
+	ld b, a
+	ld hl, wFrameData
+.copy
+	ld a, [hli]
+	ld [de], a
+	inc de
+	dec b
+	jr nz, .copy
+	ld a, [wLength]
+	ld hl, wCount
+	add a, [hl]
+	ld [hli], a
+	jr nc, .next
+	inc [hl]
+.next
+	ld a, [wSequence]
+	inc a
+	ld [wSequence], a
+	jr .frame
+.done
+	ld a, [wCount]
+	ld c, a
+	ld a, [wCount + 1]
+	ld b, a
+	ret
+
+; Sends a frame without any data.
+; @param b: the kind of frame
+; @param c: the sequence number
+SendControl:
+	ld d, 0
+	; fall through
+
+; Sends a frame.
+; @param b: the kind of frame
+; @param c: the sequence number
+; @param d: the length of the data
+; @param hl: the data
+SendFrame:
+	call ClearChecksum
+	ld a, FRAME_START
+	call LinkExchange
+	ld a, b
+	call SendSummed
+	ld a, c
+	call SendSummed
+	ld a, d
+	call SendSummed
+	ld a, d
+	and a
+	jr z, .checksum
+	ld e, d
+.data
+	ld a, [hli]
+	call SendSummed
+	dec e
+	jr nz, .data
+.checksum
+	ld a, [wChecksum]
+	call LinkExchange
+	ld a, [wChecksum + 1]
+	jp LinkExchange
+
+; Like ReceiveFrame, but gives up if no frame starts within REPLY_BUDGET bytes.
+; @return carry set if the frame was damaged or never started
+ReceiveReply:
+	ld hl, REPLY_BUDGET
+.wait
+	ld a, GAME_IDLE
+	call LinkExchange
+	cp a, FRAME_START
+	jr z, ReceiveFrame.body
+	dec hl
+	ld a, h
+	or a, l
+	jr nz, .wait
+	scf
+	ret
+
+; Skips to the next frame from the host and receives it, putting its data in wFrameData.
+; @return b: the kind of frame
+; @return c: the sequence number
+; @return d: the length of the data
+; @return carry set if the frame was damaged
+ReceiveFrame:
+	ld a, GAME_IDLE
+	call LinkExchange
+	cp a, FRAME_START
+	jr nz, ReceiveFrame
+.body
+	call ClearChecksum
+	call ReceiveSummed
+	ld b, a
+	call ReceiveSummed
+	ld c, a
+	call ReceiveSummed
+	ld d, a
+	ld hl, wFrameData
+	and a
+	jr z, .checksum
+	ld e, a
+.data
+	call ReceiveSummed
+	ld [hli], a
+	dec e
+	jr nz, .data
+.checksum
+	ld a, GAME_IDLE
+	call LinkExchange
+	ld e, a
+	ld a, GAME_IDLE
+	call LinkExchange
+	ld hl, wChecksum + 1
+	cp a, [hl]
+	jr nz, .damaged
+	dec hl
+	ld a, e
+	cp a, [hl]
+	jr nz, .damaged
+	and a
+	ret
+.damaged
+	scf
+	ret
+
+ClearChecksum:
+	xor a
+	ld [wChecksum], a
+	ld [wChecksum + 1], a
+	ret
+
+; Sends a byte and adds it to wChecksum.
+; @param a: the byte
+SendSummed:
+	call AddToChecksum
+	jr LinkExchange
+
+; Receives a byte and adds it to wChecksum.
+; @return a: the byte
+ReceiveSummed:
+	ld a, GAME_IDLE
+	call LinkExchange
+	; fall through
+
+; @param a: the byte to add, which is preserved
+AddToChecksum:
+	push af
+	push hl
+	ld hl, wChecksum
+	add a, [hl]
+	ld [hli], a
+	jr nc, .done
+	inc [hl]
+.done
+	pop hl
+	pop af
+	ret
+
+; Exchanges a byte with the host, driving the clock.
+; @param a: the byte to send
+; @return a: the byte received
+LinkExchange::
+	ldh [rSB], a
+	ld a, SC_START_INTERNAL
+	ldh [rSC], a
+.wait
+	ldh a, [rSC]
+	bit 7, a
+	jr nz, .wait
+	ldh a, [rSB]
+	ret